@@ -0,0 +1,79 @@
+use axum::{
+    Router,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Json},
+    routing::get,
+};
+use chrono::{DateTime, Utc};
+use mongodb::bson::{self, oid::ObjectId};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{SharedState, api::bearer_claims, db::connection::Database, error::AppError};
+
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+#[derive(Deserialize)]
+struct AuditQuery {
+    user_id: Option<String>,
+    room_code: Option<String>,
+    before: Option<DateTime<Utc>>,
+    limit: Option<i64>,
+}
+
+// Pages backwards in time: pass the `at` of the last event as `before`.
+async fn query_audit_log(
+    State(state): State<SharedState>,
+    Query(query): Query<AuditQuery>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let claim = bearer_claims(state.db.clone(), &headers).await?;
+    if !state.config.admin_user_ids.contains(&claim.sub) {
+        return Err(AppError::Forbidden);
+    }
+
+    let user_id = query
+        .user_id
+        .as_deref()
+        .map(ObjectId::parse_str)
+        .transpose()
+        .map_err(|_| AppError::Validation("Malformed user id.".to_string()))?;
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let events = Database::find_audit_events(
+        state.db.clone(),
+        user_id,
+        query.room_code.as_deref(),
+        query.before.map(bson::DateTime::from_chrono),
+        limit,
+    )
+    .await?;
+
+    let events: Vec<_> = events
+        .iter()
+        .map(|event| {
+            json!({
+                "at": event.at.to_chrono().to_rfc3339(),
+                "action": event.action,
+                "actor": event.actor.to_hex(),
+                "target": event.target.map(|target| target.to_hex()),
+                "room_code": event.room_code,
+                "mouse_events": event.mouse_events,
+                "key_events": event.key_events,
+                "key": event.key,
+                "text": event.text
+            })
+        })
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "success": true, "events": events })),
+    ))
+}
+
+pub fn audit_router() -> Router<SharedState> {
+    Router::new().route("/", get(query_audit_log))
+}
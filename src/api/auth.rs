@@ -1,73 +1,141 @@
 use axum::{
-    routing::post, Router, extract::State, response::{IntoResponse, Json}, http:: StatusCode,
+    routing::post, Router, extract::State, response::IntoResponse, http::{HeaderMap, StatusCode},
 };
-use mongodb::{Collection, bson::{doc, oid::ObjectId}};
+use chrono::Utc;
+use mongodb::bson::{self, oid::ObjectId};
+use serde::Deserialize;
 use serde_json::json;
+use tracing::error;
 
 use crate::{
-     models::user_model::{LoginUser, RegisterUser, User}, utils::{bcrypt::{hash_password, verify_password}, jwt::{generate_access_token, generate_refresh_token}}, SharedState
+     api::bearer_session, db::connection::{is_duplicate_key, Database}, error::{AppError, ErrorCode, Json}, mailer::{reset_mail, verification_mail},
+     models::{email_token_model::{EmailToken, TokenPurpose}, revoked_token_model::RevokedToken, user_model::{LoginUser, RegisterUser, User}},
+     utils::{bcrypt::{hash_password, verify_password}, email_token, jwt::{generate_access_token, generate_refresh_token}}, ws::close_user_sockets, SharedState
 };
 
+const EMAIL_TAKEN: AppError = AppError::Rejected(ErrorCode::EmailTaken, "User already exists with this email.");
+const INVALID_EMAIL_TOKEN: AppError = AppError::Rejected(ErrorCode::InvalidEmailToken, "This link is invalid or has expired.");
+
+#[derive(Deserialize)]
+struct TokenRequest {
+    token: String,
+}
+
+#[derive(Deserialize)]
+struct EmailRequest {
+    email: String,
+}
+
+#[derive(Deserialize)]
+struct ResetPasswordRequest {
+    token: String,
+    password: String,
+}
+
+// Stores a fresh single-use token and mails its link. The mail is sent in the
+// background, so a slow or failing mail server neither delays the response
+// nor reveals whether the address has an account.
+async fn send_token_mail(
+    state: &SharedState,
+    user_id: ObjectId,
+    email: &str,
+    purpose: TokenPurpose,
+) -> Result<(), AppError> {
+    let config = &state.config;
+    let ttl = match purpose {
+        TokenPurpose::VerifyEmail => config.verify_token_ttl,
+        TokenPurpose::ResetPassword => config.reset_token_ttl,
+    };
+    let ttl = chrono::Duration::from_std(ttl).map_err(|_| AppError::Internal("token lifetime out of range"))?;
+
+    let (token, token_hash) = email_token::generate();
+    Database::create_email_token(state.db.clone(), EmailToken {
+        _id: None,
+        user_id,
+        purpose,
+        token_hash,
+        expires_at: bson::DateTime::from_chrono(Utc::now() + ttl),
+        used_at: None,
+    })
+    .await?;
+
+    let mail = match purpose {
+        TokenPurpose::VerifyEmail => verification_mail(config, email, &token),
+        TokenPurpose::ResetPassword => reset_mail(config, email, &token),
+    };
+    let mailer = state.mailer.clone();
+    tokio::spawn(async move {
+        if let Err(err) = mailer.send(&mail).await {
+            error!(error = %err, subject = %mail.subject, "Failed to send mail");
+        }
+    });
+
+    Ok(())
+}
+
 async fn register(
     State(state): State<SharedState>, 
     Json(payload): Json<RegisterUser>
-) -> Result<impl IntoResponse, StatusCode> {
-    let db = state.db;
-    let user_collection: &Collection<User> = &db.user;
-
-    if user_collection
-        .find_one(doc! {"email": &payload.email}, None)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .is_some()
-    {
-        return Ok((
-            StatusCode::BAD_REQUEST,
-            Json(json!({ "success": false, "message": "User already exists with this email." }))
-        ));
+) -> Result<impl IntoResponse, AppError> {
+    let db = state.db.clone();
+    let payload = payload.validate(&state.config.password_policy)?;
+
+    if Database::get_user_by_email(db.clone(), &payload.email).await?.is_some() {
+        return Err(EMAIL_TAKEN);
     }
 
-    let hashed_password = hash_password(&payload.password).map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    let hashed_password = hash_password(&payload.password)?;
 
+    let user_id = ObjectId::new();
     let new_user = User {
-        _id: Some(ObjectId::new()),
-        username: payload.username.clone(),
+        _id: Some(user_id),
+        username: payload.username,
         email: payload.email.clone(),
         password: hashed_password,
+        verified: false,
+        token_version: 0,
     };
 
-    user_collection.insert_one(&new_user, None)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    // A concurrent registration can still win the race; the unique index
+    // rejects the second insert.
+    Database::create_user(db, new_user).await.map_err(|err| {
+        if is_duplicate_key(&err) { EMAIL_TAKEN } else { err.into() }
+    })?;
+
+    // The account exists by now, so a failure here must not fail the request:
+    // a retry would only find the email taken. The link can be resent instead.
+    let message = match send_token_mail(&state, user_id, &payload.email, TokenPurpose::VerifyEmail).await {
+        Ok(()) => "User registered successfully.",
+        Err(err) => {
+            err.log();
+            "User registered, but the verification link could not be sent. Request a new one to verify your email."
+        }
+    };
 
     Ok((
         StatusCode::CREATED,
-        Json(json!({ "success": true, "message": "User registered successfully." }))
+        Json(json!({ "success": true, "message": message }))
     ))
 }
 
 async fn login(
     State(state): State<SharedState>,
     Json(payload): Json<LoginUser>,
-) -> Result<impl IntoResponse, StatusCode> {
+) -> Result<impl IntoResponse, AppError> {
     let db = state.db.clone();
-    let user_collection: &Collection<User> = &db.user;
-
-    let user = user_collection
-        .find_one(doc! {"email": &payload.email}, None)
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-        .ok_or(StatusCode::UNAUTHORIZED)?;
-
-    if let Ok(false) | Err(_) = verify_password(&payload.password, &user.password) {
-        return Ok((
-            StatusCode::BAD_REQUEST,
-            Json(json!({ "success": false, "message": "Incorrect password." })),
-        ));
+
+    // An unknown email and a wrong password get the same answer, so the
+    // response does not reveal which addresses have accounts.
+    let user = Database::get_user_by_email(db, payload.email.trim())
+        .await?
+        .ok_or(AppError::InvalidCredentials)?;
+
+    if !verify_password(&payload.password, &user.password)? {
+        return Err(AppError::InvalidCredentials);
     }
 
     let user_id = user._id.expect("User id not found in DB.").to_hex();
-    let access_token = generate_access_token(&user_id, &user.username, &user.email);
+    let access_token = generate_access_token(&user_id, &user.username, &user.email, user.token_version);
     let refresh_token = generate_refresh_token(&user_id);
 
     Ok((
@@ -76,13 +144,144 @@ async fn login(
             "success": true,
             "message": "User logged in successfully.",
             "access_token": access_token,
-            "refresh_token": refresh_token
+            "refresh_token": refresh_token,
+            "verified": user.verified
         }))
     ))
 }
 
+async fn verify_email(
+    State(state): State<SharedState>,
+    Json(payload): Json<TokenRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let token_hash = email_token::hash(&payload.token);
+    let token = Database::redeem_email_token(state.db.clone(), &token_hash, TokenPurpose::VerifyEmail)
+        .await?
+        .ok_or(INVALID_EMAIL_TOKEN)?;
+
+    Database::set_user_verified(state.db.clone(), token.user_id).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "success": true, "message": "Email verified successfully." }))
+    ))
+}
+
+async fn resend_verification(
+    State(state): State<SharedState>,
+    Json(payload): Json<EmailRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = Database::get_user_by_email(state.db.clone(), payload.email.trim()).await?;
+
+    if let Some(user) = user.filter(|user| !user.verified) {
+        let user_id = user._id.ok_or(AppError::Internal("user without an id"))?;
+        Database::revoke_email_tokens(state.db.clone(), user_id, TokenPurpose::VerifyEmail).await?;
+        send_token_mail(&state, user_id, &user.email, TokenPurpose::VerifyEmail).await?;
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "success": true, "message": "If that account needs verifying, a new link is on its way." }))
+    ))
+}
+
+// Answers the same whether or not the address has an account.
+async fn forgot_password(
+    State(state): State<SharedState>,
+    Json(payload): Json<EmailRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    if let Some(user) = Database::get_user_by_email(state.db.clone(), payload.email.trim()).await? {
+        let user_id = user._id.ok_or(AppError::Internal("user without an id"))?;
+        Database::revoke_email_tokens(state.db.clone(), user_id, TokenPurpose::ResetPassword).await?;
+        send_token_mail(&state, user_id, &user.email, TokenPurpose::ResetPassword).await?;
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "success": true, "message": "If an account exists for that email, a reset link is on its way." }))
+    ))
+}
+
+async fn reset_password(
+    State(state): State<SharedState>,
+    Json(payload): Json<ResetPasswordRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let db = state.db.clone();
+    let token_hash = email_token::hash(&payload.token);
+
+    // The new password is checked before the token is spent, so a rejected
+    // password does not burn the link.
+    let token = Database::get_email_token(db.clone(), &token_hash, TokenPurpose::ResetPassword)
+        .await?
+        .ok_or(INVALID_EMAIL_TOKEN)?;
+    let user = Database::get_user_by_id(db.clone(), token.user_id)
+        .await?
+        .ok_or(INVALID_EMAIL_TOKEN)?;
+    state
+        .config
+        .password_policy
+        .check(&payload.password, &user.username, &user.email.to_lowercase())
+        .map_err(|message| AppError::Validation(message.to_string()))?;
+
+    let token = Database::redeem_email_token(db.clone(), &token_hash, TokenPurpose::ResetPassword)
+        .await?
+        .ok_or(INVALID_EMAIL_TOKEN)?;
+
+    let hashed_password = hash_password(&payload.password)?;
+    Database::set_user_password(db.clone(), token.user_id, hashed_password).await?;
+    // Following the link proved the user reads that mailbox.
+    Database::set_user_verified(db.clone(), token.user_id).await?;
+    Database::revoke_email_tokens(db.clone(), token.user_id, TokenPurpose::ResetPassword).await?;
+    // Whoever knew the old password may still hold a session.
+    Database::bump_token_version(db, token.user_id).await?;
+    close_user_sockets(&state.ws_state, token.user_id, None).await;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "success": true, "message": "Password reset successfully." }))
+    ))
+}
+
+// Ends the session of the presented access token only.
+async fn logout(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let (user_id, claim) = bearer_session(state.db.clone(), &headers).await?;
+    let expires_at = bson::DateTime::from_millis(claim.exp as i64 * 1000);
+
+    Database::revoke_token(state.db.clone(), RevokedToken { jti: claim.jti.clone(), user_id, expires_at }).await?;
+    close_user_sockets(&state.ws_state, user_id, Some(claim.jti)).await;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "success": true, "message": "Logged out successfully." }))
+    ))
+}
+
+async fn logout_all(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let (user_id, _) = bearer_session(state.db.clone(), &headers).await?;
+
+    Database::bump_token_version(state.db.clone(), user_id).await?;
+    close_user_sockets(&state.ws_state, user_id, None).await;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "success": true, "message": "Logged out of every device." }))
+    ))
+}
+
 pub fn auth_router() -> Router<SharedState> {
     Router::new()
         .route("/register", post(register))
         .route("/login", post(login))
+        .route("/verify-email", post(verify_email))
+        .route("/resend-verification", post(resend_verification))
+        .route("/forgot-password", post(forgot_password))
+        .route("/reset-password", post(reset_password))
+        .route("/logout", post(logout))
+        .route("/logout-all", post(logout_all))
 }
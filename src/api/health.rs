@@ -0,0 +1,71 @@
+use axum::{
+    Router,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Json},
+    routing::get,
+};
+use serde_json::json;
+use std::time::Instant;
+use tokio::time::timeout;
+use tracing::warn;
+
+use crate::{SharedState, db::connection::Database};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+// Liveness only: answers as long as the server can handle requests at all.
+async fn healthz(State(state): State<SharedState>) -> impl IntoResponse {
+    Json(json!({
+        "status": "ok",
+        "version": VERSION,
+        "uptime_secs": state.lifecycle.uptime().as_secs()
+    }))
+}
+
+// Ready when MongoDB answers a ping in time and the server is not draining.
+async fn readyz(State(state): State<SharedState>) -> impl IntoResponse {
+    let started = Instant::now();
+    let ping = timeout(
+        state.config.readiness_timeout,
+        Database::ping(state.db.clone()),
+    );
+    let mongodb = match ping.await {
+        Ok(Ok(())) => json!({ "status": "ok", "latency_ms": started.elapsed().as_millis() as u64 }),
+        Ok(Err(err)) => {
+            warn!(error = %err, "Readiness ping to MongoDB failed");
+            json!({ "status": "error", "message": "MongoDB ping failed" })
+        }
+        Err(_) => {
+            warn!("Readiness ping to MongoDB timed out");
+            json!({ "status": "error", "message": "MongoDB ping timed out" })
+        }
+    };
+    let mongodb_ok = mongodb["status"] == "ok";
+
+    let draining = state.lifecycle.is_draining();
+    let sockets = state.ws_state.sockets.lock().await.len();
+
+    let (status, label) = match (draining, mongodb_ok) {
+        (true, _) => (StatusCode::SERVICE_UNAVAILABLE, "draining"),
+        (false, false) => (StatusCode::SERVICE_UNAVAILABLE, "unavailable"),
+        (false, true) => (StatusCode::OK, "ready"),
+    };
+
+    (
+        status,
+        Json(json!({
+            "status": label,
+            "version": VERSION,
+            "uptime_secs": state.lifecycle.uptime().as_secs(),
+            "sockets": sockets,
+            "checks": { "mongodb": mongodb }
+        })),
+    )
+}
+
+pub fn health_router() -> Router<SharedState> {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+}
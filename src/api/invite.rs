@@ -0,0 +1,160 @@
+use axum::{Router, extract::State, http::StatusCode, response::IntoResponse, routing::post};
+use chrono::{Duration, Utc};
+use mongodb::bson::{self, oid::ObjectId};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    SharedState,
+    api::{authorize, ensure_host},
+    db::connection::Database,
+    error::{AppError, Json},
+    models::invite_model::Invite,
+    utils::{jwt::generate_invite_token, validation::normalize_email},
+};
+
+const DEFAULT_EXPIRY_MINUTES: i64 = 24 * 60;
+const MAX_EXPIRY_MINUTES: i64 = 30 * 24 * 60;
+
+#[derive(Deserialize)]
+struct CreateInviteRequest {
+    access_token: String,
+    code: String,
+    expires_in_minutes: Option<i64>,
+    max_uses: Option<u32>,
+    email: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ListInvitesRequest {
+    access_token: String,
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct RevokeInviteRequest {
+    access_token: String,
+    code: String,
+    invite_id: String,
+}
+
+fn invite_token(secret: &str, invite: &Invite) -> Option<String> {
+    let invite_id = invite._id?.to_hex();
+    let exp = invite.expires_at.timestamp_millis() / 1000;
+
+    Some(generate_invite_token(
+        secret,
+        &invite_id,
+        &invite.room_code,
+        invite.email.as_deref(),
+        exp as usize,
+    ))
+}
+
+async fn create_invite(
+    State(state): State<SharedState>,
+    Json(payload): Json<CreateInviteRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let user_id = authorize(state.db.clone(), &payload.access_token).await?;
+    ensure_host(state.db.clone(), &payload.code, user_id).await?;
+
+    let expires_in = payload.expires_in_minutes.unwrap_or(DEFAULT_EXPIRY_MINUTES);
+    let max_uses = payload.max_uses.unwrap_or(1);
+    if !(1..=MAX_EXPIRY_MINUTES).contains(&expires_in) || max_uses == 0 {
+        return Err(AppError::Validation("Invalid invite settings.".to_string()));
+    }
+
+    let email = payload
+        .email
+        .as_deref()
+        .map(normalize_email)
+        .transpose()
+        .map_err(|message| AppError::Validation(message.to_string()))?;
+
+    let invite = Invite {
+        _id: Some(ObjectId::new()),
+        room_code: payload.code,
+        created_by: user_id,
+        expires_at: bson::DateTime::from_chrono(Utc::now() + Duration::minutes(expires_in)),
+        max_uses,
+        uses: 0,
+        email,
+        revoked: false,
+    };
+    let token = invite_token(&state.config.invite_secret, &invite)
+        .ok_or(AppError::Internal("invite without an id"))?;
+    let expires_at = invite.expires_at.to_chrono().to_rfc3339();
+    let invite_id = invite._id.map(|id| id.to_hex());
+
+    Database::create_invite(state.db.clone(), invite).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "success": true,
+            "message": "Invite created successfully",
+            "invite_id": invite_id,
+            "token": token,
+            "expires_at": expires_at
+        })),
+    ))
+}
+
+async fn list_invites(
+    State(state): State<SharedState>,
+    Json(payload): Json<ListInvitesRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let user_id = authorize(state.db.clone(), &payload.access_token).await?;
+    ensure_host(state.db.clone(), &payload.code, user_id).await?;
+
+    let invites = Database::get_invites_for_room(state.db.clone(), &payload.code).await?;
+
+    let invites: Vec<_> = invites
+        .iter()
+        .map(|invite| {
+            json!({
+                "invite_id": invite._id.map(|id| id.to_hex()),
+                "token": invite_token(&state.config.invite_secret, invite),
+                "expires_at": invite.expires_at.to_chrono().to_rfc3339(),
+                "max_uses": invite.max_uses,
+                "uses": invite.uses,
+                "email": invite.email,
+                "revoked": invite.revoked
+            })
+        })
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "success": true, "invites": invites })),
+    ))
+}
+
+async fn revoke_invite(
+    State(state): State<SharedState>,
+    Json(payload): Json<RevokeInviteRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let user_id = authorize(state.db.clone(), &payload.access_token).await?;
+    ensure_host(state.db.clone(), &payload.code, user_id).await?;
+
+    let invite_id = ObjectId::parse_str(&payload.invite_id)
+        .map_err(|_| AppError::Validation("Malformed invite id.".to_string()))?;
+
+    let revoked = Database::revoke_invite(state.db.clone(), invite_id, &payload.code).await?;
+
+    if !revoked {
+        return Err(AppError::NotFound("Invite not found."));
+    }
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "success": true, "message": "Invite revoked successfully" })),
+    ))
+}
+
+pub fn invite_router() -> Router<SharedState> {
+    Router::new()
+        .route("/create", post(create_invite))
+        .route("/list", post(list_invites))
+        .route("/revoke", post(revoke_invite))
+}
@@ -0,0 +1,46 @@
+use axum::{
+    Router,
+    extract::State,
+    http::{
+        HeaderMap, StatusCode,
+        header::{AUTHORIZATION, CONTENT_TYPE},
+    },
+    response::IntoResponse,
+    routing::get,
+};
+
+use tracing::error;
+
+use crate::{SharedState, db::connection::Database, error::AppError};
+
+// Scrapers authenticate with the static `METRICS_TOKEN`, if one is configured.
+async fn get_metrics(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    if let Some(expected) = &state.config.metrics_token {
+        let token = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if token != Some(expected.as_str()) {
+            return Err(AppError::Unauthenticated);
+        }
+    }
+
+    let metrics = &state.ws_state.metrics;
+    match Database::count_rooms(state.db.clone()).await {
+        Ok(count) => metrics.rooms_active.set(count as i64),
+        Err(err) => error!(error = %err, "Failed to count rooms for metrics"),
+    }
+
+    Ok((
+        StatusCode::OK,
+        [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+        metrics.render(),
+    ))
+}
+
+pub fn metrics_router() -> Router<SharedState> {
+    Router::new().route("/", get(get_metrics))
+}
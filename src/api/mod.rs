@@ -1,2 +1,85 @@
+use axum::http::{HeaderMap, header::AUTHORIZATION};
+use mongodb::bson::oid::ObjectId;
+use std::sync::Arc;
+
+use crate::{
+    db::connection::Database,
+    error::{AppError, ErrorCode},
+    utils::{
+        jwt::{AccessClaims, verify_access_token},
+        validation::{INVALID_ROOM_CODE, is_valid_room_code},
+    },
+};
+
+pub mod audit;
 pub mod auth;
-pub mod room;
\ No newline at end of file
+pub mod health;
+pub mod invite;
+pub mod metrics;
+pub mod recording;
+pub mod room;
+pub mod rtc;
+pub mod series;
+
+const SESSION_ENDED: AppError = AppError::Rejected(ErrorCode::InvalidToken, "Your session has ended, please sign in again.");
+
+// Checks the signature and expiry, then that the token was neither logged out
+// nor issued before the user logged out everywhere. Every REST handler and
+// WebSocket join goes through here.
+pub async fn authenticate(db: Arc<Database>, access_token: &str) -> Result<(ObjectId, AccessClaims), AppError> {
+    let claim = verify_access_token(access_token)?;
+    let user_id = ObjectId::parse_str(&claim.sub).map_err(|_| AppError::Validation("Malformed user id.".to_string()))?;
+
+    let user = Database::get_user_by_id(db.clone(), user_id).await?.ok_or(SESSION_ENDED)?;
+    if user.token_version != claim.ver || Database::is_token_revoked(db, &claim.jti).await? {
+        return Err(SESSION_ENDED);
+    }
+
+    Ok((user_id, claim))
+}
+
+pub async fn authorize(db: Arc<Database>, access_token: &str) -> Result<ObjectId, AppError> {
+    let (user_id, _) = authenticate(db, access_token).await?;
+    Ok(user_id)
+}
+
+pub async fn bearer_session(db: Arc<Database>, headers: &HeaderMap) -> Result<(ObjectId, AccessClaims), AppError> {
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or(AppError::Unauthenticated)?;
+
+    authenticate(db, token).await
+}
+
+pub async fn bearer_claims(db: Arc<Database>, headers: &HeaderMap) -> Result<AccessClaims, AppError> {
+    let (_, claim) = bearer_session(db, headers).await?;
+    Ok(claim)
+}
+
+pub async fn ensure_host(
+    db: Arc<Database>,
+    code: &str,
+    user_id: ObjectId,
+) -> Result<(), AppError> {
+    if !is_valid_room_code(code) {
+        return Err(AppError::Validation(INVALID_ROOM_CODE.to_string()));
+    }
+
+    let host_id = match Database::get_series_by_code(db.clone(), code).await? {
+        Some(series) => series.host_id,
+        None => {
+            Database::get_room_by_code(db, code)
+                .await?
+                .ok_or(AppError::NotFound("Room not found."))?
+                .host_id
+        }
+    };
+
+    if host_id != user_id {
+        return Err(AppError::Rejected(ErrorCode::NotHost, "Only the host can do that."));
+    }
+
+    Ok(())
+}
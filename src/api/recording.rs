@@ -0,0 +1,116 @@
+use axum::{
+    Router,
+    body::Body,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode, header},
+    response::IntoResponse,
+    routing::{get, post},
+};
+use mongodb::bson::oid::ObjectId;
+use serde::Deserialize;
+use serde_json::json;
+use tokio::fs::File;
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    SharedState,
+    api::{authorize, bearer_claims, ensure_host},
+    db::connection::Database,
+    error::{AppError, Json},
+    recording::track_path,
+};
+
+#[derive(Deserialize)]
+struct ListRecordingsRequest {
+    access_token: String,
+    code: String,
+}
+
+async fn list_recordings(
+    State(state): State<SharedState>,
+    Json(payload): Json<ListRecordingsRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let user_id = authorize(state.db.clone(), &payload.access_token).await?;
+    ensure_host(state.db.clone(), &payload.code, user_id).await?;
+
+    let recordings = Database::get_recordings_for_room(state.db.clone(), &payload.code).await?;
+
+    let recordings: Vec<_> = recordings
+        .iter()
+        .map(|recording| {
+            let tracks: Vec<_> = recording
+                .tracks
+                .iter()
+                .enumerate()
+                .map(|(index, track)| {
+                    json!({
+                        "index": index,
+                        "user_id": track.user_id.to_hex(),
+                        "kind": track.kind,
+                        "codec": track.codec
+                    })
+                })
+                .collect();
+
+            json!({
+                "recording_id": recording._id.map(|id| id.to_hex()),
+                "started_at": recording.started_at.to_chrono().to_rfc3339(),
+                "ended_at": recording.ended_at.map(|ended_at| ended_at.to_chrono().to_rfc3339()),
+                "tracks": tracks
+            })
+        })
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "success": true, "recordings": recordings })),
+    ))
+}
+
+// Only the host who started a recording may download it. Tracks are listed
+// once the recording has stopped.
+async fn download_track(
+    State(state): State<SharedState>,
+    Path((recording_id, index)): Path<(String, usize)>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let claim = bearer_claims(state.db.clone(), &headers).await?;
+    let user_id = ObjectId::parse_str(&claim.sub)
+        .map_err(|_| AppError::Validation("Malformed user id.".to_string()))?;
+    let recording_id = ObjectId::parse_str(&recording_id)
+        .map_err(|_| AppError::NotFound("Recording not found."))?;
+
+    let recording = Database::get_recording_by_id(state.db.clone(), recording_id)
+        .await?
+        .ok_or(AppError::NotFound("Recording not found."))?;
+
+    if recording.host_id != user_id {
+        return Err(AppError::Forbidden);
+    }
+
+    let track = recording
+        .tracks
+        .get(index)
+        .ok_or(AppError::NotFound("Track not found."))?;
+    let path = track_path(&state.config.recordings_dir, recording_id, &track.file_name);
+    let file = File::open(&path)
+        .await
+        .map_err(|_| AppError::NotFound("Track not found."))?;
+
+    let content_type = format!("{}/webm", track.kind);
+    let disposition = format!("attachment; filename=\"{}\"", track.file_name);
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        Body::from_stream(ReaderStream::new(file)),
+    ))
+}
+
+pub fn recording_router() -> Router<SharedState> {
+    Router::new()
+        .route("/list", post(list_recordings))
+        .route("/{recording_id}/{index}", get(download_track))
+}
@@ -1,22 +1,40 @@
 use axum::{
-    routing::post, Router, extract::State, response::{IntoResponse, Json}, http:: StatusCode,
+    routing::post, Router, extract::State, response::IntoResponse, http:: StatusCode,
 };
-use mongodb::bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use rand::Rng;
 
 use crate::{
+    api::{authorize, ensure_host, invite::invite_router},
     db::connection::Database,
-    utils::jwt::verify_access_token, SharedState,
+    error::{AppError, ErrorCode, Json},
+    models::room_model::RoomMode,
+    utils::bcrypt::hash_password, SharedState,
 };
 
 #[derive(Debug, Serialize, Deserialize)]
 struct CreateRequest {
-    access_token: String
+    access_token: String,
+    passcode: Option<String>,
+    #[serde(default)]
+    mode: RoomMode,
 }
 
-fn generate_code() -> String {
+#[derive(Debug, Serialize, Deserialize)]
+struct PasscodeRequest {
+    access_token: String,
+    code: String,
+    passcode: Option<String>,
+}
+
+pub const PASSCODE_RULE: &str = "Passcode must be 4 to 64 characters.";
+
+pub fn is_valid_passcode(passcode: &str) -> bool {
+    (4..=64).contains(&passcode.chars().count())
+}
+
+pub fn generate_code() -> String {
     let code: u32 = rand::thread_rng().gen_range(100000..999999);
     code.to_string()
 }
@@ -24,46 +42,87 @@ fn generate_code() -> String {
 async fn create_room(
     State(state): State<SharedState>, 
     Json(payload): Json<CreateRequest>
-) -> Result<impl IntoResponse, StatusCode> {
+) -> Result<impl IntoResponse, AppError> {
 
     let db = state.db.clone();
 
-    let claim = match verify_access_token(&payload.access_token) {
-        Ok(claim) => claim,
-        Err(err) => {
-            eprintln!("❌ JWT verification failed: {:?}", err);
-            return Err(StatusCode::UNAUTHORIZED);
+    let host_id = authorize(db.clone(), &payload.access_token).await?;
+
+    if payload.passcode.as_deref().is_some_and(|passcode| !is_valid_passcode(passcode)) {
+        return Err(AppError::Validation(PASSCODE_RULE.to_string()));
+    }
+
+    let hosted_rooms = Database::count_hosted_rooms(db.clone(), host_id).await?;
+    if hosted_rooms >= state.config.max_open_rooms_per_user {
+        return Err(AppError::Rejected(
+            ErrorCode::RoomQuotaExceeded,
+            "You already have the maximum number of open rooms.",
+        ));
+    }
+
+    let total_rooms = Database::count_rooms(db.clone()).await?;
+    if total_rooms >= state.config.max_rooms {
+        return Err(AppError::Rejected(
+            ErrorCode::RoomLimitReached,
+            "The server cannot host more rooms right now.",
+        ));
+    }
+
+    let passcode = payload
+        .passcode
+        .as_deref()
+        .map(hash_password)
+        .transpose()?;
+
+    let code = loop {
+        let code = generate_code();
+        if !Database::is_code_taken(db.clone(), &code).await? {
+            break code;
         }
     };
 
-    let host_id = match ObjectId::parse_str(&claim.sub) {
-        Ok(oid) => oid,
-        Err(_) => return Err(StatusCode::BAD_REQUEST),
-    };
+    let room = Database::create_room(db.clone(), host_id, code.clone(), passcode, payload.mode).await?;
+    // Add the host as a participant
+    Database::add_participant(db.clone(), &room, host_id).await?;
 
-    let code = generate_code();
-
-    match Database::create_room(db.clone(), host_id, code.clone()).await {
-        Ok(_) => {
-            // Add the host as a participant
-            match Database::add_participant(db.clone(), code.clone(), host_id).await {
-                Ok(_) => Ok((
-                    StatusCode::CREATED, 
-                    Json(json!({
-                        "success": true,
-                        "message": "Room created successfully",
-                        "code": code
-                    }))
-                )),
-                Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-            }
-        }
-        Err(_) => Err(StatusCode::INTERNAL_SERVER_ERROR),
-    }
+    Ok((
+        StatusCode::CREATED, 
+        Json(json!({
+            "success": true,
+            "message": "Room created successfully",
+            "code": code
+        }))
+    ))
 }
 
+async fn set_passcode(
+    State(state): State<SharedState>,
+    Json(payload): Json<PasscodeRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let user_id = authorize(state.db.clone(), &payload.access_token).await?;
+    ensure_host(state.db.clone(), &payload.code, user_id).await?;
+
+    if payload.passcode.as_deref().is_some_and(|passcode| !is_valid_passcode(passcode)) {
+        return Err(AppError::Validation(PASSCODE_RULE.to_string()));
+    }
+
+    let passcode = payload
+        .passcode
+        .as_deref()
+        .map(hash_password)
+        .transpose()?;
+
+    Database::set_room_passcode(state.db.clone(), &payload.code, passcode).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "success": true, "message": "Passcode updated successfully" })),
+    ))
+}
 
 pub fn room_router() -> Router<SharedState> {
     Router::new()
         .route("/create", post(create_room))
+        .route("/passcode", post(set_passcode))
+        .nest("/invite", invite_router())
 }
\ No newline at end of file
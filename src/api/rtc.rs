@@ -0,0 +1,32 @@
+use axum::{
+    Router,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Json},
+    routing::get,
+};
+use serde_json::json;
+
+use crate::{SharedState, api::bearer_claims, error::AppError, utils::turn::ice_servers};
+
+// Credentials expire together with the access token used to request them.
+async fn get_ice_servers(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let claim = bearer_claims(state.db.clone(), &headers).await?;
+    let servers = ice_servers(&state.config, &claim.sub, claim.exp);
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "success": true,
+            "ice_servers": servers,
+            "expires_at": claim.exp
+        })),
+    ))
+}
+
+pub fn rtc_router() -> Router<SharedState> {
+    Router::new().route("/ice-servers", get(get_ice_servers))
+}
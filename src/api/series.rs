@@ -0,0 +1,254 @@
+use axum::{Router, extract::State, http::StatusCode, response::IntoResponse, routing::post};
+use chrono::{DateTime, Utc, Weekday};
+use mongodb::bson::{self, oid::ObjectId};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+use crate::{
+    SharedState,
+    api::{
+        authorize,
+        room::{generate_code, is_valid_passcode},
+    },
+    db::connection::Database,
+    error::{AppError, Json},
+    models::series_model::{ExceptionAction, RecurrenceRule, Series, SeriesException},
+    utils::{
+        bcrypt::hash_password,
+        validation::{INVALID_ROOM_CODE, is_valid_room_code},
+    },
+};
+
+const MAX_DURATION_MINUTES: u32 = 24 * 60;
+const MAX_TITLE_CHARS: usize = 120;
+
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum RuleInput {
+    Daily {
+        interval: u32,
+    },
+    Weekly {
+        interval: u32,
+        weekdays: Vec<Weekday>,
+    },
+    Custom {
+        starts: Vec<DateTime<Utc>>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum ExceptionInput {
+    Skip,
+    Move { start: DateTime<Utc> },
+}
+
+#[derive(Deserialize)]
+struct CreateSeriesRequest {
+    access_token: String,
+    title: String,
+    rule: RuleInput,
+    starts_at: DateTime<Utc>,
+    duration_minutes: u32,
+    until: Option<DateTime<Utc>>,
+    passcode: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ExceptionRequest {
+    access_token: String,
+    code: String,
+    occurrence_start: DateTime<Utc>,
+    action: ExceptionInput,
+}
+
+#[derive(Deserialize)]
+struct AttendanceRequest {
+    access_token: String,
+    code: String,
+}
+
+impl From<RuleInput> for RecurrenceRule {
+    fn from(rule: RuleInput) -> Self {
+        match rule {
+            RuleInput::Daily { interval } => RecurrenceRule::Daily { interval },
+            RuleInput::Weekly { interval, weekdays } => {
+                RecurrenceRule::Weekly { interval, weekdays }
+            }
+            RuleInput::Custom { starts } => RecurrenceRule::Custom {
+                starts: starts
+                    .into_iter()
+                    .map(bson::DateTime::from_chrono)
+                    .collect(),
+            },
+        }
+    }
+}
+
+async fn load_hosted_series(
+    state: &SharedState,
+    code: &str,
+    user_id: ObjectId,
+) -> Result<Series, AppError> {
+    if !is_valid_room_code(code) {
+        return Err(AppError::Validation(INVALID_ROOM_CODE.to_string()));
+    }
+
+    let series = Database::get_series_by_code(state.db.clone(), code)
+        .await?
+        .ok_or(AppError::NotFound("Series not found."))?;
+
+    if series.host_id != user_id {
+        return Err(AppError::Forbidden);
+    }
+
+    Ok(series)
+}
+
+async fn create_series(
+    State(state): State<SharedState>,
+    Json(payload): Json<CreateSeriesRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let host_id = authorize(state.db.clone(), &payload.access_token).await?;
+
+    let title = payload.title.trim().to_string();
+    if title.is_empty() || title.chars().count() > MAX_TITLE_CHARS {
+        return Err(AppError::Validation(
+            "Title must be 1 to 120 characters.".to_string(),
+        ));
+    }
+
+    let rule = RecurrenceRule::from(payload.rule);
+    if !rule.is_valid()
+        || payload.duration_minutes == 0
+        || payload.duration_minutes > MAX_DURATION_MINUTES
+        || payload.until.is_some_and(|until| until < payload.starts_at)
+        || payload
+            .passcode
+            .as_deref()
+            .is_some_and(|passcode| !is_valid_passcode(passcode))
+    {
+        return Err(AppError::Validation(
+            "Invalid recurrence settings.".to_string(),
+        ));
+    }
+
+    let passcode = payload.passcode.as_deref().map(hash_password).transpose()?;
+
+    let code = loop {
+        let code = generate_code();
+        if !Database::is_code_taken(state.db.clone(), &code).await? {
+            break code;
+        }
+    };
+
+    let series = Series {
+        _id: Some(ObjectId::new()),
+        host_id,
+        code: code.clone(),
+        title,
+        rule,
+        starts_at: bson::DateTime::from_chrono(payload.starts_at),
+        duration_minutes: payload.duration_minutes,
+        until: payload.until.map(bson::DateTime::from_chrono),
+        exceptions: vec![],
+        passcode,
+    };
+
+    Database::create_series(state.db.clone(), series).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "success": true,
+            "message": "Series created successfully",
+            "code": code
+        })),
+    ))
+}
+
+async fn add_exception(
+    State(state): State<SharedState>,
+    Json(payload): Json<ExceptionRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let user_id = authorize(state.db.clone(), &payload.access_token).await?;
+    let series = load_hosted_series(&state, &payload.code, user_id).await?;
+
+    if !series.is_scheduled(payload.occurrence_start) {
+        return Err(AppError::Validation(
+            "No occurrence starts at that time.".to_string(),
+        ));
+    }
+
+    let action = match payload.action {
+        ExceptionInput::Skip => ExceptionAction::Skip,
+        ExceptionInput::Move { start } => ExceptionAction::Move {
+            start: bson::DateTime::from_chrono(start),
+        },
+    };
+    let occurrence_start = bson::DateTime::from_chrono(payload.occurrence_start);
+
+    let mut exceptions: Vec<SeriesException> = series
+        .exceptions
+        .into_iter()
+        .filter(|exception| exception.occurrence_start != occurrence_start)
+        .collect();
+    exceptions.push(SeriesException {
+        occurrence_start,
+        action,
+    });
+
+    Database::set_series_exceptions(state.db.clone(), &payload.code, &exceptions).await?;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "success": true, "message": "Occurrence updated successfully" })),
+    ))
+}
+
+async fn attendance(
+    State(state): State<SharedState>,
+    Json(payload): Json<AttendanceRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let user_id = authorize(state.db.clone(), &payload.access_token).await?;
+    let series = load_hosted_series(&state, &payload.code, user_id).await?;
+    let series_id = series
+        ._id
+        .ok_or(AppError::Internal("series without an id"))?;
+
+    let participants = Database::get_series_attendance(state.db.clone(), series_id).await?;
+
+    let mut occurrences: BTreeMap<bson::DateTime, Vec<String>> = BTreeMap::new();
+    for participant in participants {
+        if let Some(start) = participant.occurrence_start {
+            occurrences
+                .entry(start)
+                .or_default()
+                .push(participant.user_id.to_hex());
+        }
+    }
+
+    let occurrences: Vec<_> = occurrences
+        .into_iter()
+        .map(|(start, attendees)| {
+            json!({
+                "occurrence_start": start.to_chrono().to_rfc3339(),
+                "attendees": attendees
+            })
+        })
+        .collect();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "success": true, "occurrences": occurrences })),
+    ))
+}
+
+pub fn series_router() -> Router<SharedState> {
+    Router::new()
+        .route("/create", post(create_series))
+        .route("/exception", post(add_exception))
+        .route("/attendance", post(attendance))
+}
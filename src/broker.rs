@@ -0,0 +1,395 @@
+mod access;
+mod memory;
+mod redis;
+mod resume;
+
+use std::{collections::HashSet, fmt, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::config::Config;
+
+pub use self::{
+    access::{AccessControl, AccessError, AccessState, Grant},
+    memory::MemoryBroker,
+    redis::RedisBroker,
+    resume::{Replay, ResumeStore, Ticket},
+};
+
+// Frames stay JSON values on the wire so each instance can encode them the way
+// its own sockets negotiated.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Payload {
+    Text {
+        text: String,
+    },
+    Frame {
+        frame: Value,
+    },
+    // Closes the recipients' sockets, or only the one signed in with this
+    // token when given.
+    Revoke {
+        token_id: Option<String>,
+    },
+    // A remote-control event for the recipient's input queue.
+    Input {
+        sender: ObjectId,
+        coalesce: bool,
+        frame: Value,
+    },
+}
+
+// A message for users that are not connected to the publishing instance.
+// Every instance receives it and delivers to the recipients it holds.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Envelope {
+    pub recipients: Vec<ObjectId>,
+    pub payload: Payload,
+}
+
+#[derive(Debug)]
+pub enum BrokerError {
+    Redis(::redis::RedisError),
+    Encoding(serde_json::Error),
+}
+
+impl fmt::Display for BrokerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BrokerError::Redis(err) => write!(f, "redis: {}", err),
+            BrokerError::Encoding(err) => write!(f, "encoding: {}", err),
+        }
+    }
+}
+
+impl From<::redis::RedisError> for BrokerError {
+    fn from(err: ::redis::RedisError) -> Self {
+        BrokerError::Redis(err)
+    }
+}
+
+impl From<serde_json::Error> for BrokerError {
+    fn from(err: serde_json::Error) -> Self {
+        BrokerError::Encoding(err)
+    }
+}
+
+#[async_trait]
+pub trait Broker: Send + Sync {
+    async fn publish(&self, envelope: &Envelope) -> Result<(), BrokerError>;
+
+    // Yields every envelope published from now on, by any instance including
+    // this one. The receiver closes when the subscription is lost.
+    async fn subscribe(&self) -> Result<mpsc::UnboundedReceiver<Envelope>, BrokerError>;
+
+    // Records that the user is connected to `instance_id`. Entries may expire,
+    // so instances refresh them while the user stays connected.
+    async fn set_online(&self, user_id: ObjectId, instance_id: Uuid) -> Result<(), BrokerError>;
+
+    // Leaves the entry alone if the user has since connected elsewhere.
+    async fn set_offline(&self, user_id: ObjectId, instance_id: Uuid) -> Result<(), BrokerError>;
+
+    async fn online(&self, user_ids: &[ObjectId]) -> Result<HashSet<ObjectId>, BrokerError>;
+
+    // Resume tokens and buffered room events live here too, so a client can
+    // resume on any instance and sequence numbers are shared by all of them.
+
+    // Replaces any token the user already holds for the room.
+    async fn issue_resume_token(
+        &self,
+        user_id: ObjectId,
+        room_code: &str,
+    ) -> Result<String, BrokerError>;
+
+    // Starts the resume window once the user's socket is gone.
+    async fn suspend_resume_token(
+        &self,
+        user_id: ObjectId,
+        room_code: &str,
+    ) -> Result<(), BrokerError>;
+
+    async fn revoke_resume_token(
+        &self,
+        user_id: ObjectId,
+        room_code: &str,
+    ) -> Result<(), BrokerError>;
+
+    // Tokens are single use; the caller issues a fresh one after resuming.
+    async fn redeem_resume_token(
+        &self,
+        token: &str,
+        user_id: ObjectId,
+    ) -> Result<Option<Ticket>, BrokerError>;
+
+    // Buffers a room event, for the whole room or only `recipient`, and
+    // returns the frame to send.
+    async fn record_room_event(
+        &self,
+        room_code: &str,
+        recipient: Option<ObjectId>,
+        event: &Value,
+    ) -> Result<String, BrokerError>;
+
+    async fn replay_room_events(
+        &self,
+        room_code: &str,
+        user_id: ObjectId,
+        after: u64,
+    ) -> Result<Replay, BrokerError>;
+
+    async fn last_seq(&self) -> Result<u64, BrokerError>;
+
+    // Drops the room's buffered events and every resume token for it.
+    async fn forget_room(&self, room_code: &str) -> Result<(), BrokerError>;
+
+    // Remote-control grants, keyed by (controller, target). They are shared
+    // because the two sides may be connected to different instances.
+
+    async fn request_access(
+        &self,
+        key: (ObjectId, ObjectId),
+        grant: &Grant,
+    ) -> Result<(), AccessError>;
+
+    async fn grant_access(
+        &self,
+        key: (ObjectId, ObjectId),
+        duration: Option<Duration>,
+    ) -> Result<Grant, AccessError>;
+
+    async fn deny_access(&self, key: (ObjectId, ObjectId)) -> Result<Grant, AccessError>;
+
+    // Either side may revoke, so `user` and `other` can be in either role.
+    async fn revoke_access(
+        &self,
+        user: ObjectId,
+        other: ObjectId,
+    ) -> Result<((ObjectId, ObjectId), Grant), AccessError>;
+
+    // Leaves a grant alone that was re-granted since this expiry was set.
+    async fn expire_access(
+        &self,
+        key: (ObjectId, ObjectId),
+        expires_at: DateTime<Utc>,
+    ) -> Result<Option<Grant>, BrokerError>;
+
+    // An expired grant is removed and returned so the caller can announce it.
+    async fn check_access(
+        &self,
+        key: (ObjectId, ObjectId),
+    ) -> Result<(), (AccessError, Option<Grant>)>;
+
+    // Drops every pair the user is part of.
+    async fn release_access(
+        &self,
+        user: ObjectId,
+    ) -> Result<Vec<((ObjectId, ObjectId), Grant)>, BrokerError>;
+}
+
+// Uses Redis when `BROKER_URL` is set; a single instance needs no broker.
+pub async fn from_config(config: &Config) -> Result<Arc<dyn Broker>, BrokerError> {
+    match &config.broker_url {
+        Some(url) => Ok(Arc::new(
+            RedisBroker::connect(
+                url,
+                config.presence_ttl,
+                config.resume_window,
+                config.resume_buffer_events,
+            )
+            .await?,
+        )),
+        None => Ok(Arc::new(MemoryBroker::new(
+            config.resume_window,
+            config.resume_buffer_events,
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use serde_json::json;
+    use tokio::time::timeout;
+
+    use super::*;
+
+    // Two instances sharing one in-process broker, as two servers would share
+    // a Redis deployment.
+    fn instances() -> ((Uuid, MemoryBroker), (Uuid, MemoryBroker)) {
+        let broker = MemoryBroker::new(Duration::from_secs(60), 16);
+        ((Uuid::new_v4(), broker.clone()), (Uuid::new_v4(), broker))
+    }
+
+    async fn next(deliveries: &mut mpsc::UnboundedReceiver<Envelope>) -> Envelope {
+        timeout(Duration::from_secs(1), deliveries.recv())
+            .await
+            .expect("no envelope delivered")
+            .expect("subscription closed")
+    }
+
+    #[tokio::test]
+    async fn envelopes_reach_every_instance() {
+        let ((_, a), (_, b)) = instances();
+        let mut a_deliveries = a.subscribe().await.unwrap();
+        let mut b_deliveries = b.subscribe().await.unwrap();
+
+        let envelope = Envelope {
+            recipients: vec![ObjectId::new()],
+            payload: Payload::Text {
+                text: r#"{"message_type":"message"}"#.to_string(),
+            },
+        };
+        a.publish(&envelope).await.unwrap();
+
+        assert_eq!(next(&mut b_deliveries).await, envelope);
+        assert_eq!(next(&mut a_deliveries).await, envelope);
+    }
+
+    #[tokio::test]
+    async fn envelopes_keep_their_order() {
+        let ((_, a), (_, b)) = instances();
+        let mut deliveries = b.subscribe().await.unwrap();
+
+        let user_id = ObjectId::new();
+        for seq in 0..10 {
+            let envelope = Envelope {
+                recipients: vec![user_id],
+                payload: Payload::Frame {
+                    frame: json!({ "message_type": "mouse-move", "seq": seq }),
+                },
+            };
+            a.publish(&envelope).await.unwrap();
+        }
+
+        for seq in 0..10 {
+            let envelope = next(&mut deliveries).await;
+            assert_eq!(
+                envelope.payload,
+                Payload::Frame {
+                    frame: json!({ "message_type": "mouse-move", "seq": seq }),
+                }
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn presence_is_visible_across_instances() {
+        let ((a_id, a), (b_id, b)) = instances();
+        let alice = ObjectId::new();
+        let bob = ObjectId::new();
+
+        a.set_online(alice, a_id).await.unwrap();
+        b.set_online(bob, b_id).await.unwrap();
+
+        let online = b.online(&[alice, bob, ObjectId::new()]).await.unwrap();
+        assert_eq!(online, HashSet::from([alice, bob]));
+
+        a.set_offline(alice, a_id).await.unwrap();
+        assert!(b.online(&[alice]).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stale_instance_cannot_clear_presence() {
+        let ((a_id, a), (b_id, b)) = instances();
+        let alice = ObjectId::new();
+
+        // Alice reconnects to B before A notices her old socket is gone.
+        a.set_online(alice, a_id).await.unwrap();
+        b.set_online(alice, b_id).await.unwrap();
+        a.set_offline(alice, a_id).await.unwrap();
+
+        assert_eq!(a.online(&[alice]).await.unwrap(), HashSet::from([alice]));
+    }
+
+    #[tokio::test]
+    async fn sessions_resume_on_another_instance() {
+        let ((_, a), (_, b)) = instances();
+        let alice = ObjectId::new();
+        let bob = ObjectId::new();
+
+        let token = a.issue_resume_token(alice, "123456").await.unwrap();
+        a.record_room_event("123456", None, &json!({ "message_type": "joined" }))
+            .await
+            .unwrap();
+        a.suspend_resume_token(alice, "123456").await.unwrap();
+
+        // Events keep one sequence whichever instance records them.
+        let missed = json!({ "message_type": "message", "text": "hi" });
+        b.record_room_event("123456", None, &missed).await.unwrap();
+        b.record_room_event("123456", Some(bob), &missed)
+            .await
+            .unwrap();
+
+        assert!(b.redeem_resume_token(&token, bob).await.unwrap().is_none());
+        let ticket = b.redeem_resume_token(&token, alice).await.unwrap().unwrap();
+        assert_eq!(ticket.room_code, "123456");
+        assert_eq!(ticket.last_seq, 1);
+
+        let replay = b
+            .replay_room_events("123456", alice, ticket.last_seq)
+            .await
+            .unwrap();
+        assert!(replay.complete);
+        assert_eq!(replay.events.len(), 1);
+        assert!(replay.events[0].contains(r#""seq":2"#));
+
+        // Tokens are single use.
+        assert!(
+            a.redeem_resume_token(&token, alice)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn forgotten_rooms_cannot_be_resumed() {
+        let ((_, a), (_, b)) = instances();
+        let alice = ObjectId::new();
+
+        let token = a.issue_resume_token(alice, "123456").await.unwrap();
+        b.forget_room("123456").await.unwrap();
+
+        assert!(
+            a.redeem_resume_token(&token, alice)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn envelopes_survive_the_wire_format() {
+        let envelope = Envelope {
+            recipients: vec![ObjectId::new(), ObjectId::new()],
+            payload: Payload::Frame {
+                frame: json!({ "message_type": "offer", "from": ObjectId::new() }),
+            },
+        };
+
+        let text = serde_json::to_string(&envelope).unwrap();
+        assert_eq!(serde_json::from_str::<Envelope>(&text).unwrap(), envelope);
+    }
+
+    #[test]
+    fn revocations_name_the_token_they_end() {
+        let envelope = Envelope {
+            recipients: vec![ObjectId::new()],
+            payload: Payload::Revoke {
+                token_id: Some("5f0c6e1e-8a0b-4c55-9d55-0e8f3a1d2b7c".to_string()),
+            },
+        };
+
+        let text = serde_json::to_string(&envelope).unwrap();
+        assert!(text.contains(r#""kind":"revoke""#));
+        assert_eq!(serde_json::from_str::<Envelope>(&text).unwrap(), envelope);
+    }
+}
@@ -0,0 +1,322 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{DateTime, Utc};
+use mongodb::bson::oid::ObjectId;
+
+use super::BrokerError;
+use crate::error::ErrorCode;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AccessState {
+    Requested,
+    Granted,
+    Denied,
+    Revoked,
+    Expired,
+}
+
+impl AccessState {
+    pub fn message_type(&self) -> &'static str {
+        match self {
+            AccessState::Requested => "request-access",
+            AccessState::Granted => "allowed-access",
+            AccessState::Denied => "rejected-access",
+            AccessState::Revoked => "access-revoked",
+            AccessState::Expired => "access-expired",
+        }
+    }
+}
+
+pub enum AccessError {
+    AlreadyGranted,
+    NotRequested,
+    NotGranted,
+    Expired,
+    Broker(BrokerError),
+}
+
+impl From<BrokerError> for AccessError {
+    fn from(err: BrokerError) -> Self {
+        AccessError::Broker(err)
+    }
+}
+
+impl AccessError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AccessError::AlreadyGranted => ErrorCode::AccessAlreadyGranted,
+            AccessError::NotRequested => ErrorCode::AccessNotRequested,
+            AccessError::NotGranted => ErrorCode::AccessNotGranted,
+            AccessError::Expired => ErrorCode::AccessExpired,
+            AccessError::Broker(_) => ErrorCode::InternalError,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            AccessError::AlreadyGranted => "Remote control has already been granted.",
+            AccessError::NotRequested => "No pending remote-control request from that user.",
+            AccessError::NotGranted => "Remote control of that user has not been granted.",
+            AccessError::Expired => "The remote-control grant has expired.",
+            AccessError::Broker(_) => "Remote control is unavailable right now.",
+        }
+    }
+}
+
+// Keyed by (controller, target). Only live states are stored: a pair that
+// is denied, revoked or expired is dropped and starts over with a request.
+#[derive(Clone)]
+pub struct Grant {
+    pub state: AccessState,
+    pub room_code: String,
+    pub username: String,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+// In-process grant state, used by `MemoryBroker`.
+#[derive(Default)]
+pub struct AccessControl {
+    grants: HashMap<(ObjectId, ObjectId), Grant>,
+}
+
+impl AccessControl {
+    pub fn request(&mut self, key: (ObjectId, ObjectId), grant: Grant) -> Result<(), AccessError> {
+        if let Some(existing) = self.grants.get(&key)
+            && existing.state == AccessState::Granted
+            && !is_expired(existing, Utc::now())
+        {
+            return Err(AccessError::AlreadyGranted);
+        }
+
+        self.grants.insert(key, grant);
+        Ok(())
+    }
+
+    pub fn grant(
+        &mut self,
+        key: (ObjectId, ObjectId),
+        duration: Option<Duration>,
+    ) -> Result<Grant, AccessError> {
+        let grant = self
+            .grants
+            .get_mut(&key)
+            .filter(|grant| grant.state == AccessState::Requested)
+            .ok_or(AccessError::NotRequested)?;
+
+        grant.state = AccessState::Granted;
+        grant.expires_at = duration.map(|duration| Utc::now() + duration);
+        Ok(grant.clone())
+    }
+
+    pub fn deny(&mut self, key: (ObjectId, ObjectId)) -> Result<Grant, AccessError> {
+        match self.grants.get(&key) {
+            Some(grant) if grant.state == AccessState::Requested => {}
+            _ => return Err(AccessError::NotRequested),
+        }
+
+        self.grants.remove(&key).ok_or(AccessError::NotRequested)
+    }
+
+    // Either side may revoke, so `user` and `other` can be in either role.
+    pub fn revoke(
+        &mut self,
+        user: ObjectId,
+        other: ObjectId,
+    ) -> Result<((ObjectId, ObjectId), Grant), AccessError> {
+        [(user, other), (other, user)]
+            .into_iter()
+            .find_map(|key| self.grants.remove_entry(&key))
+            .ok_or(AccessError::NotGranted)
+    }
+
+    // Removes the grant if it is still the one that was set to expire at
+    // `expires_at`, so a re-grant in the meantime is left alone.
+    pub fn expire(
+        &mut self,
+        controller: ObjectId,
+        target: ObjectId,
+        expires_at: DateTime<Utc>,
+    ) -> Option<Grant> {
+        match self.grants.get(&(controller, target)) {
+            Some(grant)
+                if grant.state == AccessState::Granted && grant.expires_at == Some(expires_at) =>
+            {
+                self.grants.remove(&(controller, target))
+            }
+            _ => None,
+        }
+    }
+
+    // Gate for relaying control events. An expired grant is removed and
+    // returned so the caller can announce it.
+    pub fn check(
+        &mut self,
+        controller: ObjectId,
+        target: ObjectId,
+    ) -> Result<(), (AccessError, Option<Grant>)> {
+        let grant = match self.grants.get(&(controller, target)) {
+            Some(grant) if grant.state == AccessState::Granted => grant,
+            _ => return Err((AccessError::NotGranted, None)),
+        };
+
+        if is_expired(grant, Utc::now()) {
+            let grant = self.grants.remove(&(controller, target));
+            return Err((AccessError::Expired, grant));
+        }
+
+        Ok(())
+    }
+
+    // Drops every pair the user is part of, e.g. when they leave the room.
+    pub fn remove_user(&mut self, user: ObjectId) -> Vec<((ObjectId, ObjectId), Grant)> {
+        let keys: Vec<(ObjectId, ObjectId)> = self
+            .grants
+            .keys()
+            .filter(|(controller, target)| *controller == user || *target == user)
+            .copied()
+            .collect();
+
+        keys.into_iter()
+            .filter_map(|key| self.grants.remove_entry(&key))
+            .collect()
+    }
+}
+
+fn is_expired(grant: &Grant, now: DateTime<Utc>) -> bool {
+    grant.expires_at.is_some_and(|expires_at| expires_at <= now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair() -> (ObjectId, ObjectId) {
+        (ObjectId::new(), ObjectId::new())
+    }
+
+    fn requested() -> Grant {
+        Grant {
+            state: AccessState::Requested,
+            room_code: "123456".to_string(),
+            username: "alice".to_string(),
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn grant_requires_a_pending_request() {
+        let mut access = AccessControl::default();
+        let key = pair();
+
+        assert!(matches!(
+            access.grant(key, None),
+            Err(AccessError::NotRequested)
+        ));
+
+        access.request(key, requested()).ok().unwrap();
+        let grant = access.grant(key, None).ok().unwrap();
+        assert!(grant.state == AccessState::Granted);
+        assert!(access.check(key.0, key.1).is_ok());
+
+        // A second grant finds nothing pending.
+        assert!(matches!(
+            access.grant(key, None),
+            Err(AccessError::NotRequested)
+        ));
+    }
+
+    #[test]
+    fn request_is_rejected_while_granted() {
+        let mut access = AccessControl::default();
+        let key = pair();
+
+        access.request(key, requested()).ok().unwrap();
+        access.grant(key, None).ok().unwrap();
+
+        assert!(matches!(
+            access.request(key, requested()),
+            Err(AccessError::AlreadyGranted)
+        ));
+    }
+
+    #[test]
+    fn deny_drops_the_request() {
+        let mut access = AccessControl::default();
+        let key = pair();
+
+        assert!(matches!(access.deny(key), Err(AccessError::NotRequested)));
+
+        access.request(key, requested()).ok().unwrap();
+        assert!(access.deny(key).is_ok());
+        assert!(matches!(
+            access.grant(key, None),
+            Err(AccessError::NotRequested)
+        ));
+        assert!(matches!(
+            access.check(key.0, key.1),
+            Err((AccessError::NotGranted, None))
+        ));
+    }
+
+    #[test]
+    fn either_side_can_revoke() {
+        let mut access = AccessControl::default();
+        let (controller, target) = pair();
+
+        access
+            .request((controller, target), requested())
+            .ok()
+            .unwrap();
+        access.grant((controller, target), None).ok().unwrap();
+
+        let (key, _) = access.revoke(target, controller).ok().unwrap();
+        assert_eq!(key, (controller, target));
+        assert!(access.check(controller, target).is_err());
+        assert!(matches!(
+            access.revoke(controller, target),
+            Err(AccessError::NotGranted)
+        ));
+    }
+
+    #[test]
+    fn check_removes_an_expired_grant() {
+        let mut access = AccessControl::default();
+        let key = pair();
+
+        access.request(key, requested()).ok().unwrap();
+        access.grant(key, Some(Duration::ZERO)).ok().unwrap();
+
+        let (err, grant) = access.check(key.0, key.1).unwrap_err();
+        assert!(matches!(err, AccessError::Expired));
+        assert!(grant.is_some());
+
+        // An expired grant no longer blocks a fresh request.
+        assert!(access.request(key, requested()).is_ok());
+    }
+
+    #[test]
+    fn expire_leaves_a_regrant_alone() {
+        let mut access = AccessControl::default();
+        let key = pair();
+
+        access.request(key, requested()).ok().unwrap();
+        let first = access
+            .grant(key, Some(Duration::from_secs(60)))
+            .ok()
+            .unwrap();
+        let expires_at = first.expires_at.unwrap();
+
+        assert!(access.expire(key.0, key.1, expires_at).is_some());
+        assert!(access.check(key.0, key.1).is_err());
+
+        access.request(key, requested()).ok().unwrap();
+        access
+            .grant(key, Some(Duration::from_secs(120)))
+            .ok()
+            .unwrap();
+
+        // The timer for the first grant must not take down the second.
+        assert!(access.expire(key.0, key.1, expires_at).is_none());
+        assert!(access.check(key.0, key.1).is_ok());
+    }
+}
@@ -0,0 +1,219 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use mongodb::bson::oid::ObjectId;
+use serde_json::Value;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc,
+};
+use tracing::warn;
+use uuid::Uuid;
+
+use super::{
+    AccessControl, AccessError, Broker, BrokerError, Envelope, Grant, Replay, ResumeStore, Ticket,
+};
+
+const CHANNEL_CAPACITY: usize = 1024;
+
+// In-process broker. Clones share one channel, so it serves a single server
+// and stands in for Redis when several instances run in one process.
+#[derive(Clone)]
+pub struct MemoryBroker {
+    events: broadcast::Sender<Envelope>,
+    presence: Arc<DashMap<ObjectId, Uuid>>,
+    resume: Arc<Mutex<ResumeStore>>,
+    access: Arc<Mutex<AccessControl>>,
+}
+
+impl MemoryBroker {
+    pub fn new(resume_window: Duration, resume_capacity: usize) -> Self {
+        let (events, _) = broadcast::channel(CHANNEL_CAPACITY);
+        MemoryBroker {
+            events,
+            presence: Arc::new(DashMap::new()),
+            resume: Arc::new(Mutex::new(ResumeStore::new(resume_window, resume_capacity))),
+            access: Arc::new(Mutex::new(AccessControl::default())),
+        }
+    }
+}
+
+#[async_trait]
+impl Broker for MemoryBroker {
+    async fn publish(&self, envelope: &Envelope) -> Result<(), BrokerError> {
+        // Fails only when nobody is subscribed, which is not an error here.
+        let _ = self.events.send(envelope.clone());
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> Result<mpsc::UnboundedReceiver<Envelope>, BrokerError> {
+        let mut events = self.events.subscribe();
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(envelope) => {
+                        if sender.send(envelope).is_err() {
+                            break;
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        warn!(skipped, "Broker subscriber fell behind, envelopes dropped");
+                    }
+                    Err(RecvError::Closed) => break,
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+
+    async fn set_online(&self, user_id: ObjectId, instance_id: Uuid) -> Result<(), BrokerError> {
+        self.presence.insert(user_id, instance_id);
+        Ok(())
+    }
+
+    async fn set_offline(&self, user_id: ObjectId, instance_id: Uuid) -> Result<(), BrokerError> {
+        self.presence
+            .remove_if(&user_id, |_, owner| *owner == instance_id);
+        Ok(())
+    }
+
+    async fn online(&self, user_ids: &[ObjectId]) -> Result<HashSet<ObjectId>, BrokerError> {
+        Ok(user_ids
+            .iter()
+            .filter(|user_id| self.presence.contains_key(user_id))
+            .copied()
+            .collect())
+    }
+
+    async fn issue_resume_token(
+        &self,
+        user_id: ObjectId,
+        room_code: &str,
+    ) -> Result<String, BrokerError> {
+        Ok(self.resume.lock().unwrap().issue(user_id, room_code))
+    }
+
+    async fn suspend_resume_token(
+        &self,
+        user_id: ObjectId,
+        room_code: &str,
+    ) -> Result<(), BrokerError> {
+        self.resume.lock().unwrap().suspend(user_id, room_code);
+        Ok(())
+    }
+
+    async fn revoke_resume_token(
+        &self,
+        user_id: ObjectId,
+        room_code: &str,
+    ) -> Result<(), BrokerError> {
+        self.resume.lock().unwrap().revoke(user_id, room_code);
+        Ok(())
+    }
+
+    async fn redeem_resume_token(
+        &self,
+        token: &str,
+        user_id: ObjectId,
+    ) -> Result<Option<Ticket>, BrokerError> {
+        Ok(self.resume.lock().unwrap().redeem(token, user_id))
+    }
+
+    async fn record_room_event(
+        &self,
+        room_code: &str,
+        recipient: Option<ObjectId>,
+        event: &Value,
+    ) -> Result<String, BrokerError> {
+        Ok(self
+            .resume
+            .lock()
+            .unwrap()
+            .record(room_code, recipient, event))
+    }
+
+    async fn replay_room_events(
+        &self,
+        room_code: &str,
+        user_id: ObjectId,
+        after: u64,
+    ) -> Result<Replay, BrokerError> {
+        Ok(self
+            .resume
+            .lock()
+            .unwrap()
+            .replay(room_code, user_id, after))
+    }
+
+    async fn last_seq(&self) -> Result<u64, BrokerError> {
+        Ok(self.resume.lock().unwrap().last_seq())
+    }
+
+    async fn forget_room(&self, room_code: &str) -> Result<(), BrokerError> {
+        self.resume.lock().unwrap().remove_room(room_code);
+        Ok(())
+    }
+
+    async fn request_access(
+        &self,
+        key: (ObjectId, ObjectId),
+        grant: &Grant,
+    ) -> Result<(), AccessError> {
+        self.access.lock().unwrap().request(key, grant.clone())
+    }
+
+    async fn grant_access(
+        &self,
+        key: (ObjectId, ObjectId),
+        duration: Option<Duration>,
+    ) -> Result<Grant, AccessError> {
+        self.access.lock().unwrap().grant(key, duration)
+    }
+
+    async fn deny_access(&self, key: (ObjectId, ObjectId)) -> Result<Grant, AccessError> {
+        self.access.lock().unwrap().deny(key)
+    }
+
+    async fn revoke_access(
+        &self,
+        user: ObjectId,
+        other: ObjectId,
+    ) -> Result<((ObjectId, ObjectId), Grant), AccessError> {
+        self.access.lock().unwrap().revoke(user, other)
+    }
+
+    async fn expire_access(
+        &self,
+        (controller, target): (ObjectId, ObjectId),
+        expires_at: DateTime<Utc>,
+    ) -> Result<Option<Grant>, BrokerError> {
+        Ok(self
+            .access
+            .lock()
+            .unwrap()
+            .expire(controller, target, expires_at))
+    }
+
+    async fn check_access(
+        &self,
+        (controller, target): (ObjectId, ObjectId),
+    ) -> Result<(), (AccessError, Option<Grant>)> {
+        self.access.lock().unwrap().check(controller, target)
+    }
+
+    async fn release_access(
+        &self,
+        user: ObjectId,
+    ) -> Result<Vec<((ObjectId, ObjectId), Grant)>, BrokerError> {
+        Ok(self.access.lock().unwrap().remove_user(user))
+    }
+}
@@ -0,0 +1,858 @@
+use std::{collections::HashSet, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use mongodb::bson::oid::ObjectId;
+use redis::{AsyncCommands, Client, FromRedisValue, Script, aio::MultiplexedConnection};
+use serde_json::Value;
+use tokio::sync::mpsc;
+use tracing::warn;
+use uuid::Uuid;
+
+use super::{
+    AccessError, AccessState, Broker, BrokerError, Envelope, Grant, Replay, Ticket,
+    resume::Sequenced,
+};
+
+const CHANNEL: &str = "telesync:envelopes";
+const PRESENCE_PREFIX: &str = "telesync:presence:";
+
+// Resume state mirrors `ResumeStore`. A ticket hash is keyed by its token and
+// a holder key maps (user, room) to the token, so each user holds at most one
+// per room. A room's log is a sorted set scored by `seq`, whose members are
+// `{recorded at, ms}|{recipient or empty}|{frame}`.
+const RESUME_SEQ: &str = "telesync:resume:seq";
+const TICKET_PREFIX: &str = "telesync:resume:ticket:";
+const HOLDER_PREFIX: &str = "telesync:resume:holder:";
+const HOLDERS_PREFIX: &str = "telesync:resume:holders:";
+const LOG_PREFIX: &str = "telesync:resume:log:";
+const EVICTED_PREFIX: &str = "telesync:resume:evicted:";
+
+// Grants mirror `AccessControl`: a hash per `{controller}:{target}` pair, plus
+// a set per user of the pairs they are part of.
+const GRANT_PREFIX: &str = "telesync:access:grant:";
+const PAIRS_PREFIX: &str = "telesync:access:pairs:";
+
+// Live tickets have no expiry in `ResumeStore`, but here they would outlast an
+// instance that dies before suspending them, so they expire after a day.
+const LIVE_TICKET_TTL_MS: u64 = 24 * 60 * 60 * 1000;
+
+// Deletes the presence key only if it still names the given instance.
+const CLEAR_PRESENCE: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+// Every key a script touches is passed in KEYS. The ticket a holder key points
+// to is read beforehand, so the scripts that take it return -1 when the holder
+// has moved on in the meantime and the caller reads it again.
+const ISSUE_TICKET: &str = r#"
+if (redis.call("GET", KEYS[1]) or "") ~= ARGV[4] then
+    return -1
+end
+if KEYS[5] then
+    redis.call("DEL", KEYS[5])
+end
+local last_seq = redis.call("GET", KEYS[3]) or "0"
+redis.call("HSET", KEYS[4], "user_id", ARGV[2], "room_code", ARGV[3], "last_seq", last_seq)
+redis.call("PEXPIRE", KEYS[4], ARGV[5])
+redis.call("SET", KEYS[1], ARGV[1], "PX", ARGV[5])
+redis.call("SADD", KEYS[2], ARGV[2])
+return 1
+"#;
+
+const SUSPEND_TICKET: &str = r#"
+if redis.call("GET", KEYS[1]) ~= ARGV[2] then
+    return -1
+end
+if redis.call("EXISTS", KEYS[3]) == 0 then
+    return 0
+end
+redis.call("HSET", KEYS[3], "last_seq", redis.call("GET", KEYS[2]) or "0")
+redis.call("PEXPIRE", KEYS[3], ARGV[1])
+redis.call("PEXPIRE", KEYS[1], ARGV[1])
+return 1
+"#;
+
+const REVOKE_TICKET: &str = r#"
+if (redis.call("GET", KEYS[1]) or "") ~= ARGV[2] then
+    return -1
+end
+if KEYS[3] then
+    redis.call("DEL", KEYS[3], KEYS[1])
+end
+redis.call("SREM", KEYS[2], ARGV[1])
+return 1
+"#;
+
+// Returns the ticket's room code and last seq, or nil if the token is unknown,
+// expired or belongs to someone else. KEYS[2] is the holder key of the user
+// and room the caller read from the ticket.
+const REDEEM_TICKET: &str = r#"
+local ticket = redis.call("HMGET", KEYS[1], "user_id", "room_code", "last_seq")
+if ticket[1] ~= ARGV[1] or ticket[2] ~= ARGV[3] then
+    return false
+end
+redis.call("DEL", KEYS[1])
+if redis.call("GET", KEYS[2]) == ARGV[2] then
+    redis.call("DEL", KEYS[2])
+end
+return {ticket[2], ticket[3]}
+"#;
+
+const RECORD_EVENT: &str = r#"
+redis.call("ZADD", KEYS[1], ARGV[1], ARGV[2])
+local over = redis.call("ZCARD", KEYS[1]) - tonumber(ARGV[3])
+if over > 0 then
+    local evicted = redis.call("ZPOPMIN", KEYS[1], over)
+    local seq = evicted[#evicted]
+    if tonumber(seq) > tonumber(redis.call("GET", KEYS[2]) or "0") then
+        redis.call("SET", KEYS[2], seq)
+    end
+end
+return 0
+"#;
+
+// Evicts events older than the cutoff, then returns the highest evicted seq
+// and the frames after `ARGV[2]` that the user may see.
+const REPLAY_EVENTS: &str = r#"
+local entries = redis.call("ZRANGE", KEYS[1], 0, -1, "WITHSCORES")
+local evicted_through = redis.call("GET", KEYS[2]) or "0"
+local events = {}
+for i = 1, #entries, 2 do
+    local at, recipient, text = string.match(entries[i], "^(%d+)|(%x*)|(.*)$")
+    local seq = entries[i + 1]
+    if tonumber(at) < tonumber(ARGV[1]) then
+        redis.call("ZREM", KEYS[1], entries[i])
+        if tonumber(seq) > tonumber(evicted_through) then
+            evicted_through = seq
+        end
+    elseif tonumber(seq) > tonumber(ARGV[2]) and (recipient == "" or recipient == ARGV[3]) then
+        table.insert(events, text)
+    end
+end
+redis.call("SET", KEYS[2], evicted_through)
+return {evicted_through, events}
+"#;
+
+// KEYS[4..] are holder and ticket keys in turn, with the token each holder was
+// read to hold in ARGV. Tickets issued after that read expire on their own.
+const FORGET_ROOM: &str = r#"
+for i = 1, #ARGV do
+    local holder = KEYS[2 + i * 2]
+    if redis.call("GET", holder) == ARGV[i] then
+        redis.call("DEL", holder)
+    end
+    redis.call("DEL", KEYS[3 + i * 2])
+end
+redis.call("DEL", KEYS[1], KEYS[2], KEYS[3])
+return 0
+"#;
+
+// Access scripts take the grant key and both users' pair sets as KEYS and the
+// pair as ARGV[1]. Those returning a grant reply with its state, room code,
+// username and expiry in ms ("" for none).
+const REQUEST_GRANT: &str = r#"
+local grant = redis.call("HMGET", KEYS[1], "state", "expires_at")
+if grant[1] == "granted" and (grant[2] == "" or tonumber(grant[2]) > tonumber(ARGV[2])) then
+    return 0
+end
+redis.call("DEL", KEYS[1])
+redis.call("HSET", KEYS[1], "state", "requested", "room_code", ARGV[3], "username", ARGV[4], "expires_at", "")
+redis.call("SADD", KEYS[2], ARGV[1])
+redis.call("SADD", KEYS[3], ARGV[1])
+return 1
+"#;
+
+const GRANT_GRANT: &str = r#"
+local grant = redis.call("HMGET", KEYS[1], "state", "room_code", "username")
+if grant[1] ~= "requested" then
+    return false
+end
+redis.call("HSET", KEYS[1], "state", "granted", "expires_at", ARGV[2])
+return {"granted", grant[2], grant[3], ARGV[2]}
+"#;
+
+// Removes the grant if it is in the state ARGV[2] (any when "") and, when
+// ARGV[3] is given, still expires at that time.
+const TAKE_GRANT: &str = r#"
+local grant = redis.call("HMGET", KEYS[1], "state", "room_code", "username", "expires_at")
+if not grant[1] or (ARGV[2] ~= "" and grant[1] ~= ARGV[2]) then
+    return false
+end
+if ARGV[3] ~= "" and grant[4] ~= ARGV[3] then
+    return false
+end
+redis.call("DEL", KEYS[1])
+redis.call("SREM", KEYS[2], ARGV[1])
+redis.call("SREM", KEYS[3], ARGV[1])
+return grant
+"#;
+
+// Nil when not granted, empty when granted, the removed grant when expired.
+const CHECK_GRANT: &str = r#"
+local grant = redis.call("HMGET", KEYS[1], "state", "room_code", "username", "expires_at")
+if grant[1] ~= "granted" then
+    return false
+end
+if grant[4] ~= "" and tonumber(grant[4]) <= tonumber(ARGV[2]) then
+    redis.call("DEL", KEYS[1])
+    redis.call("SREM", KEYS[2], ARGV[1])
+    redis.call("SREM", KEYS[3], ARGV[1])
+    return grant
+end
+return {}
+"#;
+
+// Takes the pairs read from the user's set in ARGV, and the grant key and both
+// pair sets of each in KEYS[2..]. Replies with the pair followed by the grant
+// for each one removed.
+const RELEASE_GRANTS: &str = r#"
+local released = {}
+for i, pair in ipairs(ARGV) do
+    local key = KEYS[i * 3 - 1]
+    local grant = redis.call("HMGET", key, "state", "room_code", "username", "expires_at")
+    redis.call("DEL", key)
+    redis.call("SREM", KEYS[1], pair)
+    redis.call("SREM", KEYS[i * 3], pair)
+    redis.call("SREM", KEYS[i * 3 + 1], pair)
+    if grant[1] then
+        table.insert(released, pair)
+        for _, field in ipairs(grant) do
+            table.insert(released, field)
+        end
+    end
+end
+return released
+"#;
+
+pub struct RedisBroker {
+    client: Client,
+    connection: MultiplexedConnection,
+    presence_ttl: Duration,
+    resume_window: Duration,
+    resume_capacity: usize,
+}
+
+impl RedisBroker {
+    pub async fn connect(
+        url: &str,
+        presence_ttl: Duration,
+        resume_window: Duration,
+        resume_capacity: usize,
+    ) -> Result<Self, BrokerError> {
+        let client = Client::open(url)?;
+        let connection = client.get_multiplexed_async_connection().await?;
+
+        Ok(RedisBroker {
+            client,
+            connection,
+            presence_ttl,
+            resume_window,
+            resume_capacity: resume_capacity.max(1),
+        })
+    }
+}
+
+fn presence_key(user_id: &ObjectId) -> String {
+    format!("{}{}", PRESENCE_PREFIX, user_id.to_hex())
+}
+
+fn holder_key(user_id: &ObjectId, room_code: &str) -> String {
+    format!("{}{}:{}", HOLDER_PREFIX, user_id.to_hex(), room_code)
+}
+
+fn prefixed(prefix: &str, id: &str) -> String {
+    format!("{}{}", prefix, id)
+}
+
+fn pair((controller, target): (ObjectId, ObjectId)) -> String {
+    format!("{}:{}", controller.to_hex(), target.to_hex())
+}
+
+fn parse_pair(pair: &str) -> Option<(ObjectId, ObjectId)> {
+    let (controller, target) = pair.split_once(':')?;
+    Some((
+        ObjectId::parse_str(controller).ok()?,
+        ObjectId::parse_str(target).ok()?,
+    ))
+}
+
+fn parse_grant(fields: &[String]) -> Option<Grant> {
+    let [state, room_code, username, expires_at] = fields else {
+        return None;
+    };
+    let state = match state.as_str() {
+        "requested" => AccessState::Requested,
+        "granted" => AccessState::Granted,
+        _ => return None,
+    };
+    let expires_at = match expires_at.as_str() {
+        "" => None,
+        millis => Some(DateTime::from_timestamp_millis(millis.parse().ok()?)?),
+    };
+
+    Some(Grant {
+        state,
+        room_code: room_code.clone(),
+        username: username.clone(),
+        expires_at,
+    })
+}
+
+fn expiry_arg(expires_at: Option<DateTime<Utc>>) -> String {
+    expires_at
+        .map(|at| at.timestamp_millis().to_string())
+        .unwrap_or_default()
+}
+
+impl RedisBroker {
+    async fn holder_token(&self, holder: &str) -> Result<Option<String>, BrokerError> {
+        let mut connection = self.connection.clone();
+        Ok(connection.get(holder).await?)
+    }
+
+    async fn run_access<T: FromRedisValue>(
+        &self,
+        script: &str,
+        key: (ObjectId, ObjectId),
+        args: &[String],
+    ) -> Result<T, BrokerError> {
+        let script = Script::new(script);
+        let pair = pair(key);
+        let mut invocation = script.key(prefixed(GRANT_PREFIX, &pair));
+        invocation
+            .key(prefixed(PAIRS_PREFIX, &key.0.to_hex()))
+            .key(prefixed(PAIRS_PREFIX, &key.1.to_hex()))
+            .arg(&pair);
+        for arg in args {
+            invocation.arg(arg);
+        }
+
+        let mut connection = self.connection.clone();
+        Ok(invocation.invoke_async(&mut connection).await?)
+    }
+
+    async fn take_grant(
+        &self,
+        key: (ObjectId, ObjectId),
+        state: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<Option<Grant>, BrokerError> {
+        let fields: Option<Vec<String>> = self
+            .run_access(
+                TAKE_GRANT,
+                key,
+                &[state.to_string(), expiry_arg(expires_at)],
+            )
+            .await?;
+        Ok(fields.as_deref().and_then(parse_grant))
+    }
+}
+
+#[async_trait]
+impl Broker for RedisBroker {
+    async fn publish(&self, envelope: &Envelope) -> Result<(), BrokerError> {
+        let payload = serde_json::to_string(envelope)?;
+        let mut connection = self.connection.clone();
+        let _: () = connection.publish(CHANNEL, payload).await?;
+        Ok(())
+    }
+
+    async fn subscribe(&self) -> Result<mpsc::UnboundedReceiver<Envelope>, BrokerError> {
+        let mut pubsub = self.client.get_async_pubsub().await?;
+        pubsub.subscribe(CHANNEL).await?;
+        let (sender, receiver) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let mut messages = pubsub.into_on_message();
+            while let Some(message) = messages.next().await {
+                let payload: String = match message.get_payload() {
+                    Ok(payload) => payload,
+                    Err(err) => {
+                        warn!(error = %err, "Unreadable broker message");
+                        continue;
+                    }
+                };
+
+                match serde_json::from_str(&payload) {
+                    Ok(envelope) => {
+                        if sender.send(envelope).is_err() {
+                            break;
+                        }
+                    }
+                    Err(err) => warn!(error = %err, "Malformed broker envelope"),
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+
+    async fn set_online(&self, user_id: ObjectId, instance_id: Uuid) -> Result<(), BrokerError> {
+        let mut connection = self.connection.clone();
+        let _: () = connection
+            .set_ex(
+                presence_key(&user_id),
+                instance_id.to_string(),
+                self.presence_ttl.as_secs().max(1),
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn set_offline(&self, user_id: ObjectId, instance_id: Uuid) -> Result<(), BrokerError> {
+        let mut connection = self.connection.clone();
+        let _: i64 = Script::new(CLEAR_PRESENCE)
+            .key(presence_key(&user_id))
+            .arg(instance_id.to_string())
+            .invoke_async(&mut connection)
+            .await?;
+        Ok(())
+    }
+
+    async fn online(&self, user_ids: &[ObjectId]) -> Result<HashSet<ObjectId>, BrokerError> {
+        if user_ids.is_empty() {
+            return Ok(HashSet::new());
+        }
+
+        let keys: Vec<String> = user_ids.iter().map(presence_key).collect();
+        let mut connection = self.connection.clone();
+        let owners: Vec<Option<String>> = redis::cmd("MGET")
+            .arg(keys)
+            .query_async(&mut connection)
+            .await?;
+
+        Ok(user_ids
+            .iter()
+            .zip(owners)
+            .filter(|(_, owner)| owner.is_some())
+            .map(|(user_id, _)| *user_id)
+            .collect())
+    }
+
+    async fn issue_resume_token(
+        &self,
+        user_id: ObjectId,
+        room_code: &str,
+    ) -> Result<String, BrokerError> {
+        let token = Uuid::new_v4().simple().to_string();
+        let holder = holder_key(&user_id, room_code);
+        let script = Script::new(ISSUE_TICKET);
+        let mut connection = self.connection.clone();
+        loop {
+            let previous = self.holder_token(&holder).await?;
+            let mut invocation = script.key(&holder);
+            invocation
+                .key(prefixed(HOLDERS_PREFIX, room_code))
+                .key(RESUME_SEQ)
+                .key(prefixed(TICKET_PREFIX, &token));
+            if let Some(previous) = &previous {
+                invocation.key(prefixed(TICKET_PREFIX, previous));
+            }
+            let issued: i64 = invocation
+                .arg(&token)
+                .arg(user_id.to_hex())
+                .arg(room_code)
+                .arg(previous.unwrap_or_default())
+                .arg(LIVE_TICKET_TTL_MS)
+                .invoke_async(&mut connection)
+                .await?;
+            if issued >= 0 {
+                return Ok(token);
+            }
+        }
+    }
+
+    async fn suspend_resume_token(
+        &self,
+        user_id: ObjectId,
+        room_code: &str,
+    ) -> Result<(), BrokerError> {
+        let holder = holder_key(&user_id, room_code);
+        let script = Script::new(SUSPEND_TICKET);
+        let mut connection = self.connection.clone();
+        loop {
+            let Some(token) = self.holder_token(&holder).await? else {
+                return Ok(());
+            };
+            let suspended: i64 = script
+                .key(&holder)
+                .key(RESUME_SEQ)
+                .key(prefixed(TICKET_PREFIX, &token))
+                .arg(self.resume_window.as_millis().max(1) as u64)
+                .arg(&token)
+                .invoke_async(&mut connection)
+                .await?;
+            if suspended >= 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn revoke_resume_token(
+        &self,
+        user_id: ObjectId,
+        room_code: &str,
+    ) -> Result<(), BrokerError> {
+        let holder = holder_key(&user_id, room_code);
+        let script = Script::new(REVOKE_TICKET);
+        let mut connection = self.connection.clone();
+        loop {
+            let token = self.holder_token(&holder).await?;
+            let mut invocation = script.key(&holder);
+            invocation.key(prefixed(HOLDERS_PREFIX, room_code));
+            if let Some(token) = &token {
+                invocation.key(prefixed(TICKET_PREFIX, token));
+            }
+            let revoked: i64 = invocation
+                .arg(user_id.to_hex())
+                .arg(token.unwrap_or_default())
+                .invoke_async(&mut connection)
+                .await?;
+            if revoked >= 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn redeem_resume_token(
+        &self,
+        token: &str,
+        user_id: ObjectId,
+    ) -> Result<Option<Ticket>, BrokerError> {
+        let key = prefixed(TICKET_PREFIX, token);
+        let mut connection = self.connection.clone();
+        let (owner, room_code): (Option<String>, Option<String>) = redis::cmd("HMGET")
+            .arg(&key)
+            .arg("user_id")
+            .arg("room_code")
+            .query_async(&mut connection)
+            .await?;
+        let Some(room_code) = room_code.filter(|_| owner == Some(user_id.to_hex())) else {
+            return Ok(None);
+        };
+
+        let ticket: Option<(String, u64)> = Script::new(REDEEM_TICKET)
+            .key(&key)
+            .key(holder_key(&user_id, &room_code))
+            .arg(user_id.to_hex())
+            .arg(token)
+            .arg(&room_code)
+            .invoke_async(&mut connection)
+            .await?;
+
+        Ok(ticket.map(|(room_code, last_seq)| Ticket {
+            user_id,
+            room_code,
+            last_seq,
+        }))
+    }
+
+    async fn record_room_event(
+        &self,
+        room_code: &str,
+        recipient: Option<ObjectId>,
+        event: &Value,
+    ) -> Result<String, BrokerError> {
+        let mut connection = self.connection.clone();
+        let seq: u64 = connection.incr(RESUME_SEQ, 1).await?;
+        let text = serde_json::to_string(&Sequenced { seq, event })?;
+        let entry = format!(
+            "{}|{}|{}",
+            Utc::now().timestamp_millis(),
+            recipient.map(|id| id.to_hex()).unwrap_or_default(),
+            text
+        );
+
+        let _: i64 = Script::new(RECORD_EVENT)
+            .key(prefixed(LOG_PREFIX, room_code))
+            .key(prefixed(EVICTED_PREFIX, room_code))
+            .arg(seq)
+            .arg(entry)
+            .arg(self.resume_capacity)
+            .invoke_async(&mut connection)
+            .await?;
+        Ok(text)
+    }
+
+    async fn replay_room_events(
+        &self,
+        room_code: &str,
+        user_id: ObjectId,
+        after: u64,
+    ) -> Result<Replay, BrokerError> {
+        let cutoff = Utc::now().timestamp_millis() - self.resume_window.as_millis() as i64;
+        let mut connection = self.connection.clone();
+        let (evicted_through, events): (u64, Vec<String>) = Script::new(REPLAY_EVENTS)
+            .key(prefixed(LOG_PREFIX, room_code))
+            .key(prefixed(EVICTED_PREFIX, room_code))
+            .arg(cutoff)
+            .arg(after)
+            .arg(user_id.to_hex())
+            .invoke_async(&mut connection)
+            .await?;
+
+        Ok(Replay {
+            events,
+            complete: after >= evicted_through,
+        })
+    }
+
+    async fn last_seq(&self) -> Result<u64, BrokerError> {
+        let mut connection = self.connection.clone();
+        let seq: Option<u64> = connection.get(RESUME_SEQ).await?;
+        Ok(seq.unwrap_or(0))
+    }
+
+    async fn forget_room(&self, room_code: &str) -> Result<(), BrokerError> {
+        let holders = prefixed(HOLDERS_PREFIX, room_code);
+        let mut connection = self.connection.clone();
+        let user_ids: Vec<String> = connection.smembers(&holders).await?;
+
+        let script = Script::new(FORGET_ROOM);
+        let mut invocation = script.key(&holders);
+        invocation
+            .key(prefixed(LOG_PREFIX, room_code))
+            .key(prefixed(EVICTED_PREFIX, room_code));
+        for user_id in user_ids {
+            let holder = format!("{}{}:{}", HOLDER_PREFIX, user_id, room_code);
+            if let Some(token) = self.holder_token(&holder).await? {
+                invocation
+                    .key(holder)
+                    .key(prefixed(TICKET_PREFIX, &token))
+                    .arg(token);
+            }
+        }
+
+        let _: i64 = invocation.invoke_async(&mut connection).await?;
+        Ok(())
+    }
+
+    async fn request_access(
+        &self,
+        key: (ObjectId, ObjectId),
+        grant: &Grant,
+    ) -> Result<(), AccessError> {
+        let now = Utc::now().timestamp_millis().to_string();
+        let requested: i64 = self
+            .run_access(
+                REQUEST_GRANT,
+                key,
+                &[now, grant.room_code.clone(), grant.username.clone()],
+            )
+            .await?;
+
+        match requested {
+            0 => Err(AccessError::AlreadyGranted),
+            _ => Ok(()),
+        }
+    }
+
+    async fn grant_access(
+        &self,
+        key: (ObjectId, ObjectId),
+        duration: Option<Duration>,
+    ) -> Result<Grant, AccessError> {
+        let expires_at = duration.map(|duration| Utc::now() + duration);
+        let fields: Option<Vec<String>> = self
+            .run_access(GRANT_GRANT, key, &[expiry_arg(expires_at)])
+            .await?;
+        fields
+            .as_deref()
+            .and_then(parse_grant)
+            .ok_or(AccessError::NotRequested)
+    }
+
+    async fn deny_access(&self, key: (ObjectId, ObjectId)) -> Result<Grant, AccessError> {
+        self.take_grant(key, "requested", None)
+            .await?
+            .ok_or(AccessError::NotRequested)
+    }
+
+    async fn revoke_access(
+        &self,
+        user: ObjectId,
+        other: ObjectId,
+    ) -> Result<((ObjectId, ObjectId), Grant), AccessError> {
+        for key in [(user, other), (other, user)] {
+            if let Some(grant) = self.take_grant(key, "", None).await? {
+                return Ok((key, grant));
+            }
+        }
+        Err(AccessError::NotGranted)
+    }
+
+    async fn expire_access(
+        &self,
+        key: (ObjectId, ObjectId),
+        expires_at: DateTime<Utc>,
+    ) -> Result<Option<Grant>, BrokerError> {
+        self.take_grant(key, "granted", Some(expires_at)).await
+    }
+
+    async fn check_access(
+        &self,
+        key: (ObjectId, ObjectId),
+    ) -> Result<(), (AccessError, Option<Grant>)> {
+        let now = Utc::now().timestamp_millis().to_string();
+        let fields: Option<Vec<String>> = self
+            .run_access(CHECK_GRANT, key, &[now])
+            .await
+            .map_err(|err| (AccessError::Broker(err), None))?;
+
+        match fields {
+            None => Err((AccessError::NotGranted, None)),
+            Some(fields) if fields.is_empty() => Ok(()),
+            Some(fields) => Err((AccessError::Expired, parse_grant(&fields))),
+        }
+    }
+
+    async fn release_access(
+        &self,
+        user: ObjectId,
+    ) -> Result<Vec<((ObjectId, ObjectId), Grant)>, BrokerError> {
+        let pairs_key = prefixed(PAIRS_PREFIX, &user.to_hex());
+        let mut connection = self.connection.clone();
+        let pairs: Vec<String> = connection.smembers(&pairs_key).await?;
+        if pairs.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let script = Script::new(RELEASE_GRANTS);
+        let mut invocation = script.key(&pairs_key);
+        for pair in &pairs {
+            let Some((controller, target)) = pair.split_once(':') else {
+                continue;
+            };
+            invocation
+                .key(prefixed(GRANT_PREFIX, pair))
+                .key(prefixed(PAIRS_PREFIX, controller))
+                .key(prefixed(PAIRS_PREFIX, target))
+                .arg(pair);
+        }
+        let released: Vec<String> = invocation.invoke_async(&mut connection).await?;
+
+        Ok(released
+            .chunks(5)
+            .filter_map(|chunk| Some((parse_pair(&chunk[0])?, parse_grant(&chunk[1..])?)))
+            .collect())
+    }
+}
+
+// These need a Redis server and are skipped unless `TEST_REDIS_URL` names one.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn broker() -> Option<RedisBroker> {
+        let url = std::env::var("TEST_REDIS_URL").ok()?;
+        let broker =
+            RedisBroker::connect(&url, Duration::from_secs(60), Duration::from_secs(60), 16)
+                .await
+                .unwrap();
+        Some(broker)
+    }
+
+    // The log and sequence are shared, so each test uses a room of its own.
+    fn room_code() -> String {
+        format!("{:06}", Uuid::new_v4().as_u128() % 1_000_000)
+    }
+
+    async fn ttl(broker: &RedisBroker, key: &str) -> i64 {
+        let mut connection = broker.connection.clone();
+        connection.pttl(key).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn tickets_expire_and_are_redeemed_once() {
+        let Some(broker) = broker().await else {
+            return;
+        };
+        let alice = ObjectId::new();
+        let room = room_code();
+
+        let stale = broker.issue_resume_token(alice, &room).await.unwrap();
+        let token = broker.issue_resume_token(alice, &room).await.unwrap();
+        let ticket_key = prefixed(TICKET_PREFIX, &token);
+        let holder = holder_key(&alice, &room);
+
+        // Live tickets expire even if no instance suspends them.
+        assert!(ttl(&broker, &ticket_key).await > 60_000);
+        assert!(ttl(&broker, &holder).await > 60_000);
+
+        broker.suspend_resume_token(alice, &room).await.unwrap();
+        assert!(ttl(&broker, &ticket_key).await <= 60_000);
+
+        let redeemed = broker.redeem_resume_token(&stale, alice).await.unwrap();
+        assert!(redeemed.is_none());
+        let redeemed = broker.redeem_resume_token(&token, ObjectId::new()).await;
+        assert!(redeemed.unwrap().is_none());
+
+        let ticket = broker.redeem_resume_token(&token, alice).await.unwrap();
+        assert_eq!(ticket.unwrap().room_code, room);
+
+        // Tokens are single use.
+        let redeemed = broker.redeem_resume_token(&token, alice).await.unwrap();
+        assert!(redeemed.is_none());
+        assert_eq!(broker.holder_token(&holder).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn forgotten_rooms_drop_their_tickets() {
+        let Some(broker) = broker().await else {
+            return;
+        };
+        let (alice, bob) = (ObjectId::new(), ObjectId::new());
+        let room = room_code();
+
+        let alice_token = broker.issue_resume_token(alice, &room).await.unwrap();
+        let bob_token = broker.issue_resume_token(bob, &room).await.unwrap();
+        broker.forget_room(&room).await.unwrap();
+
+        for (user_id, token) in [(alice, alice_token), (bob, bob_token)] {
+            assert_eq!(ttl(&broker, &prefixed(TICKET_PREFIX, &token)).await, -2);
+            assert_eq!(ttl(&broker, &holder_key(&user_id, &room)).await, -2);
+        }
+        assert_eq!(ttl(&broker, &prefixed(HOLDERS_PREFIX, &room)).await, -2);
+    }
+
+    #[tokio::test]
+    async fn released_grants_leave_no_pairs_behind() {
+        let Some(broker) = broker().await else {
+            return;
+        };
+        let (alice, bob) = (ObjectId::new(), ObjectId::new());
+        let grant = Grant {
+            state: AccessState::Requested,
+            room_code: room_code(),
+            username: "alice".to_string(),
+            expires_at: None,
+        };
+
+        broker
+            .request_access((alice, bob), &grant)
+            .await
+            .ok()
+            .unwrap();
+        broker.grant_access((alice, bob), None).await.ok().unwrap();
+        assert!(broker.check_access((alice, bob)).await.is_ok());
+
+        let released = broker.release_access(bob).await.unwrap();
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].0, (alice, bob));
+        assert!(broker.check_access((alice, bob)).await.is_err());
+
+        let mut connection = broker.connection.clone();
+        for user_id in [alice, bob] {
+            let pairs: Vec<String> = connection
+                .smembers(prefixed(PAIRS_PREFIX, &user_id.to_hex()))
+                .await
+                .unwrap();
+            assert!(pairs.is_empty());
+        }
+    }
+}
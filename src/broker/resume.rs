@@ -0,0 +1,202 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use mongodb::bson::oid::ObjectId;
+use serde::Serialize;
+use uuid::Uuid;
+
+// Outgoing room events carry a `seq` so a reconnecting client can say which
+// was the last one it saw.
+#[derive(Serialize)]
+pub(super) struct Sequenced<'a, T> {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub event: &'a T,
+}
+
+struct RoomEvent {
+    seq: u64,
+    at: Instant,
+    // `None` for events that went to the whole room.
+    recipient: Option<ObjectId>,
+    text: String,
+}
+
+struct RoomLog {
+    events: VecDeque<RoomEvent>,
+    // Highest sequence number that is no longer buffered.
+    evicted_through: u64,
+}
+
+pub struct Ticket {
+    pub user_id: ObjectId,
+    pub room_code: String,
+    pub last_seq: u64,
+}
+
+// A resume token stays valid while its socket is connected and for `window`
+// after it drops.
+struct HeldTicket {
+    ticket: Ticket,
+    expires_at: Option<Instant>,
+}
+
+pub struct Replay {
+    pub events: Vec<String>,
+    // False when some of the missed events have already been evicted.
+    pub complete: bool,
+}
+
+// In-process resume state, used by `MemoryBroker`.
+pub struct ResumeStore {
+    window: Duration,
+    capacity: usize,
+    next_seq: u64,
+    tickets: HashMap<String, HeldTicket>,
+    rooms: HashMap<String, RoomLog>,
+}
+
+impl ResumeStore {
+    pub fn new(window: Duration, capacity: usize) -> Self {
+        ResumeStore {
+            window,
+            capacity: capacity.max(1),
+            next_seq: 0,
+            tickets: HashMap::new(),
+            rooms: HashMap::new(),
+        }
+    }
+
+    pub fn last_seq(&self) -> u64 {
+        self.next_seq
+    }
+
+    // Replaces any token the user already holds for the room.
+    pub fn issue(&mut self, user_id: ObjectId, room_code: &str) -> String {
+        self.prune_tickets(Instant::now());
+        self.revoke(user_id, room_code);
+
+        let token = Uuid::new_v4().simple().to_string();
+        self.tickets.insert(
+            token.clone(),
+            HeldTicket {
+                ticket: Ticket {
+                    user_id,
+                    room_code: room_code.to_owned(),
+                    last_seq: self.next_seq,
+                },
+                expires_at: None,
+            },
+        );
+        token
+    }
+
+    // Starts the resume window once the user's socket is gone.
+    pub fn suspend(&mut self, user_id: ObjectId, room_code: &str) {
+        let expires_at = Instant::now() + self.window;
+        let last_seq = self.next_seq;
+        for held in self.tickets.values_mut() {
+            if held.ticket.user_id == user_id && held.ticket.room_code == room_code {
+                held.ticket.last_seq = last_seq;
+                held.expires_at = Some(expires_at);
+            }
+        }
+    }
+
+    pub fn revoke(&mut self, user_id: ObjectId, room_code: &str) {
+        self.tickets
+            .retain(|_, held| held.ticket.user_id != user_id || held.ticket.room_code != room_code);
+    }
+
+    // Tokens are single use; the caller issues a fresh one after resuming.
+    pub fn redeem(&mut self, token: &str, user_id: ObjectId) -> Option<Ticket> {
+        self.prune_tickets(Instant::now());
+        match self.tickets.get(token) {
+            Some(held) if held.ticket.user_id == user_id => {
+                self.tickets.remove(token).map(|held| held.ticket)
+            }
+            _ => None,
+        }
+    }
+
+    // Buffers the event and returns the frame to send.
+    pub fn record<T: Serialize>(
+        &mut self,
+        room_code: &str,
+        recipient: Option<ObjectId>,
+        event: &T,
+    ) -> String {
+        let now = Instant::now();
+        self.next_seq += 1;
+        let seq = self.next_seq;
+        let text = serde_json::to_string(&Sequenced { seq, event }).unwrap();
+
+        let log = self
+            .rooms
+            .entry(room_code.to_owned())
+            .or_insert_with(|| RoomLog {
+                events: VecDeque::new(),
+                evicted_through: 0,
+            });
+        evict_expired(log, now, self.window);
+        if log.events.len() >= self.capacity
+            && let Some(evicted) = log.events.pop_front()
+        {
+            log.evicted_through = evicted.seq;
+        }
+        log.events.push_back(RoomEvent {
+            seq,
+            at: now,
+            recipient,
+            text: text.clone(),
+        });
+
+        text
+    }
+
+    pub fn replay(&mut self, room_code: &str, user_id: ObjectId, after: u64) -> Replay {
+        let Some(log) = self.rooms.get_mut(room_code) else {
+            return Replay {
+                events: vec![],
+                complete: true,
+            };
+        };
+
+        evict_expired(log, Instant::now(), self.window);
+        Replay {
+            events: log
+                .events
+                .iter()
+                .filter(|event| event.seq > after)
+                .filter(|event| event.recipient.is_none_or(|id| id == user_id))
+                .map(|event| event.text.clone())
+                .collect(),
+            complete: after >= log.evicted_through,
+        }
+    }
+
+    pub fn remove_room(&mut self, room_code: &str) {
+        self.rooms.remove(room_code);
+        self.tickets
+            .retain(|_, held| held.ticket.room_code != room_code);
+    }
+
+    fn prune_tickets(&mut self, now: Instant) {
+        self.tickets
+            .retain(|_, held| held.expires_at.is_none_or(|at| at > now));
+    }
+}
+
+// Logs stay around, even when empty, until the room is removed so
+// `evicted_through` is never forgotten.
+fn evict_expired(log: &mut RoomLog, now: Instant, window: Duration) {
+    while let Some(event) = log.events.front() {
+        if now.duration_since(event.at) <= window {
+            break;
+        }
+        log.evicted_through = event.seq;
+        log.events.pop_front();
+    }
+}
@@ -0,0 +1,126 @@
+use std::{env, path::PathBuf, str::FromStr, time::Duration};
+
+use crate::utils::validation::PasswordPolicy;
+
+pub struct Config {
+    pub max_participants_per_room: usize,
+    pub max_open_rooms_per_user: u64,
+    pub max_rooms: u64,
+    pub passcode_max_failures: u32,
+    pub passcode_window: Duration,
+    pub passcode_lockout: Duration,
+    pub reaper_interval: Duration,
+    pub room_idle_timeout: Duration,
+    pub reaper_dry_run: bool,
+    pub stun_urls: Vec<String>,
+    pub turn_urls: Vec<String>,
+    pub turn_secret: Option<String>,
+    pub invite_secret: String,
+    pub recordings_dir: PathBuf,
+    pub audit_keystrokes: bool,
+    pub admin_user_ids: Vec<String>,
+    pub input_rate_per_sec: u32,
+    pub input_burst: u32,
+    pub ping_interval: Duration,
+    pub max_missed_pongs: u32,
+    pub resume_window: Duration,
+    pub resume_buffer_events: usize,
+    pub broker_url: Option<String>,
+    pub presence_ttl: Duration,
+    pub metrics_token: Option<String>,
+    pub log_level: String,
+    pub log_json: bool,
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+    pub drain_grace: Duration,
+    pub readiness_timeout: Duration,
+    pub password_policy: PasswordPolicy,
+    pub app_url: String,
+    pub smtp_url: Option<String>,
+    pub mail_from: String,
+    pub mail_dir: PathBuf,
+    pub verify_token_ttl: Duration,
+    pub reset_token_ttl: Duration,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        let config = Config {
+            max_participants_per_room: env_or("ROOM_MAX_PARTICIPANTS", 8),
+            max_open_rooms_per_user: env_or("USER_MAX_OPEN_ROOMS", 5),
+            max_rooms: env_or("MAX_ROOMS", 1000),
+            passcode_max_failures: env_or("PASSCODE_MAX_FAILURES", 5),
+            passcode_window: Duration::from_secs(env_or("PASSCODE_WINDOW_SECS", 15 * 60)),
+            passcode_lockout: Duration::from_secs(env_or("PASSCODE_LOCKOUT_SECS", 15 * 60)),
+            reaper_interval: Duration::from_secs(env_or("REAPER_INTERVAL_SECS", 60)),
+            room_idle_timeout: Duration::from_secs(env_or("ROOM_IDLE_TIMEOUT_SECS", 600)),
+            reaper_dry_run: env_or("REAPER_DRY_RUN", false),
+            stun_urls: env_list("STUN_URLS"),
+            turn_urls: env_list("TURN_URLS"),
+            turn_secret: env::var("TURN_SECRET").ok(),
+            invite_secret: env_required("INVITE_TOKEN_SECRET"),
+            recordings_dir: env_or("RECORDINGS_DIR", PathBuf::from("recordings")),
+            audit_keystrokes: env_or("AUDIT_KEYSTROKES", false),
+            admin_user_ids: env_list("ADMIN_USER_IDS"),
+            input_rate_per_sec: env_or("INPUT_RATE_PER_SEC", 120),
+            input_burst: env_or("INPUT_BURST", 240),
+            ping_interval: Duration::from_secs(env_or("WS_PING_INTERVAL_SECS", 20)),
+            max_missed_pongs: env_or("WS_MAX_MISSED_PONGS", 2),
+            resume_window: Duration::from_secs(env_or("RESUME_WINDOW_SECS", 120)),
+            resume_buffer_events: env_or("RESUME_BUFFER_EVENTS", 256),
+            broker_url: env::var("BROKER_URL").ok(),
+            presence_ttl: Duration::from_secs(env_or("PRESENCE_TTL_SECS", 60)),
+            metrics_token: env::var("METRICS_TOKEN").ok(),
+            log_level: env_or("LOG_LEVEL", "info".to_string()),
+            log_json: env_or("LOG_JSON", true),
+            otlp_endpoint: env::var("OTEL_EXPORTER_OTLP_ENDPOINT").ok(),
+            service_name: env_or("OTEL_SERVICE_NAME", "telesync".to_string()),
+            drain_grace: Duration::from_secs(env_or("DRAIN_GRACE_SECS", 10)),
+            readiness_timeout: Duration::from_millis(env_or("READINESS_TIMEOUT_MS", 2000)),
+            password_policy: PasswordPolicy {
+                min_length: env_or("PASSWORD_MIN_LENGTH", 10),
+                check_breached: env_or("PASSWORD_CHECK_BREACHED", true),
+            },
+            app_url: env_or("APP_URL", "http://localhost:5173".to_string()),
+            smtp_url: env::var("SMTP_URL").ok(),
+            mail_from: env_or("MAIL_FROM", "Telesync <no-reply@localhost>".to_string()),
+            mail_dir: env_or("MAIL_DIR", PathBuf::from("mail")),
+            verify_token_ttl: Duration::from_secs(env_or("VERIFY_TOKEN_TTL_SECS", 24 * 60 * 60)),
+            reset_token_ttl: Duration::from_secs(env_or("RESET_TOKEN_TTL_SECS", 60 * 60)),
+        };
+
+        // Presence is refreshed on every ping tick, so a shorter TTL would
+        // let online users flicker offline between refreshes.
+        if config.presence_ttl <= config.ping_interval {
+            panic!("❌ PRESENCE_TTL_SECS in .env must be greater than WS_PING_INTERVAL_SECS");
+        }
+
+        config
+    }
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    match env::var(key) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("❌ {} in .env is not valid", key)),
+        Err(_) => default,
+    }
+}
+
+fn env_required(key: &str) -> String {
+    env::var(key).unwrap_or_else(|_| panic!("❌ {} not found in .env", key))
+}
+
+fn env_list(key: &str) -> Vec<String> {
+    env::var(key)
+        .map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_owned)
+                .collect()
+        })
+        .unwrap_or_default()
+}
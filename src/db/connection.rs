@@ -1,83 +1,462 @@
+use chrono::Utc;
+use futures_util::TryStreamExt;
 use mongodb::{
-    Client, Collection,
-    bson::{doc, oid::ObjectId},
-    error::Result,
+    Client, Collection, IndexModel,
+    bson::{self, doc, oid::ObjectId},
+    error::{ErrorKind, Result, WriteFailure},
+    options::{
+        Collation, CollationStrength, FindOneAndUpdateOptions, FindOneOptions, FindOptions,
+        IndexOptions, ReturnDocument, UpdateOptions,
+    },
 };
-use std::{env, sync::Arc};
+use std::{env, sync::Arc, time::Duration};
+use tracing::error;
 
-use crate::models::{participant_model::Participant, room_model::Room, user_model::User};
+use crate::metrics::Metrics;
+use crate::models::{
+    audit_model::AuditEvent,
+    email_token_model::{EmailToken, TokenPurpose},
+    invite_model::Invite,
+    participant_model::Participant,
+    recording_model::{Recording, RecordingTrack},
+    revoked_token_model::RevokedToken,
+    room_model::{Room, RoomMode},
+    series_model::{Series, SeriesException},
+    user_model::User,
+};
+
+const DUPLICATE_KEY: i32 = 11000;
+
+// Emails compare case-insensitively, which also matches accounts created
+// before addresses were normalized on registration.
+fn email_collation() -> Collation {
+    Collation::builder()
+        .locale("en")
+        .strength(CollationStrength::Secondary)
+        .build()
+}
+
+pub fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    matches!(
+        err.kind.as_ref(),
+        ErrorKind::Write(WriteFailure::WriteError(write_error)) if write_error.code == DUPLICATE_KEY
+    )
+}
 
 pub struct Database {
     pub user: Collection<User>,
     pub room: Collection<Room>,
     pub participant: Collection<Participant>,
+    pub series: Collection<Series>,
+    pub invite: Collection<Invite>,
+    pub recording: Collection<Recording>,
+    pub audit: Collection<AuditEvent>,
+    pub email_token: Collection<EmailToken>,
+    pub revoked_token: Collection<RevokedToken>,
+    pub metrics: Arc<Metrics>,
+    database: mongodb::Database,
 }
 
 impl Database {
-    pub async fn init() -> Result<Self> {
+    pub async fn init(metrics: Arc<Metrics>) -> Result<Self> {
         let db_url = env::var("MONGODB_URI").expect("❌ MONGODB_URI not found in .env");
         let client = Client::with_uri_str(&db_url).await?;
 
         let db_name = env::var("DB_NAME").unwrap_or_else(|_| "my_database".to_string());
         let db = client.database(&db_name);
+        // The driver connects lazily; fail at startup rather than on the
+        // first request if the server is unreachable.
+        db.run_command(doc! { "ping": 1 }, None).await?;
 
         let user: Collection<User> = db.collection("users");
         let room: Collection<Room> = db.collection("rooms");
         let participant: Collection<Participant> = db.collection("participants");
+        let series: Collection<Series> = db.collection("series");
+        let invite: Collection<Invite> = db.collection("invites");
+        let recording: Collection<Recording> = db.collection("recordings");
+        let audit: Collection<AuditEvent> = db.collection("audit_log");
+        let email_token: Collection<EmailToken> = db.collection("email_tokens");
+        let revoked_token: Collection<RevokedToken> = db.collection("revoked_tokens");
+
+        // Closes the gap between the existence check in `register` and the
+        // insert. Existing addresses that differ only by case block the
+        // index; the server still starts, relying on the check alone.
+        let email_index = IndexModel::builder()
+            .keys(doc! { "email": 1 })
+            .options(
+                IndexOptions::builder()
+                    .name("email_unique".to_string())
+                    .unique(true)
+                    .collation(email_collation())
+                    .build(),
+            )
+            .build();
+        if let Err(err) = user.create_index(email_index, None).await {
+            error!(error = %err, "Failed to create the unique index on users.email");
+        }
+
+        let token_indexes = [
+            IndexModel::builder()
+                .keys(doc! { "token_hash": 1 })
+                .options(IndexOptions::builder().unique(true).build())
+                .build(),
+            IndexModel::builder()
+                .keys(doc! { "expires_at": 1 })
+                .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+                .build(),
+        ];
+        email_token.create_indexes(token_indexes, None).await?;
+
+        let revoked_expiry = IndexModel::builder()
+            .keys(doc! { "expires_at": 1 })
+            .options(IndexOptions::builder().expire_after(Duration::ZERO).build())
+            .build();
+        revoked_token.create_index(revoked_expiry, None).await?;
 
         Ok(Database {
             user,
             room,
             participant,
+            series,
+            invite,
+            recording,
+            audit,
+            email_token,
+            revoked_token,
+            metrics,
+            database: db,
         })
     }
 
+    pub async fn ping(db: Arc<Database>) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("ping", async move {
+                db.database.run_command(doc! { "ping": 1 }, None).await?;
+                Ok(())
+            })
+            .await
+    }
+
     pub async fn get_user_by_id(
         db: Arc<Database>,
         user_id: ObjectId,
     ) -> mongodb::error::Result<Option<User>> {
-        let filter = doc! {"_id" : user_id};
-        let user = db.user.find_one(filter, None).await?;
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("get_user_by_id", async move {
+                let filter = doc! {"_id" : user_id};
+                let user = db.user.find_one(filter, None).await?;
 
-        Ok(user)
+                Ok(user)
+            })
+            .await
+    }
+
+    pub async fn get_user_by_email(
+        db: Arc<Database>,
+        email: &str,
+    ) -> mongodb::error::Result<Option<User>> {
+        let metrics = db.metrics.clone();
+        let email = email.to_string();
+        metrics
+            .observe_db("get_user_by_email", async move {
+                let options = FindOneOptions::builder()
+                    .collation(email_collation())
+                    .build();
+                db.user.find_one(doc! { "email": email }, options).await
+            })
+            .await
+    }
+
+    // Fails with a duplicate-key error if the email is already registered.
+    pub async fn create_user(db: Arc<Database>, user: User) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("create_user", async move {
+                db.user.insert_one(user, None).await?;
+                Ok(())
+            })
+            .await
     }
 
     pub async fn create_room(
         db: Arc<Database>,
         host_id: ObjectId,
         code: String,
-    ) -> mongodb::error::Result<()> {
-        let new_room = Room {
-            _id: Some(ObjectId::new()),
-            host_id,
-            code,
-            participants_id: vec![],
-        };
+        passcode: Option<String>,
+        mode: RoomMode,
+    ) -> mongodb::error::Result<Room> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("create_room", async move {
+                let new_room = Room {
+                    _id: Some(ObjectId::new()),
+                    host_id,
+                    code,
+                    participants_id: vec![],
+                    series_id: None,
+                    occurrence_start: None,
+                    passcode,
+                    mode,
+                };
 
-        db.room.insert_one(new_room, None).await?;
-        Ok(())
+                db.room.insert_one(&new_room, None).await?;
+                Ok(new_room)
+            })
+            .await
     }
 
+    // For a series code this is the room of the occurrence that can be joined
+    // now, once a join has opened it with `open_room`.
     pub async fn get_room_by_code(
         db: Arc<Database>,
         room_code: &str,
     ) -> mongodb::error::Result<Option<Room>> {
-        let filter = doc! { "code": room_code };
-        let room = db.room.find_one(filter, None).await?;
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("get_room_by_code", async move {
+                let filter = match Database::get_series_by_code(db.clone(), room_code).await? {
+                    Some(series) => match (series._id, series.active_occurrence(Utc::now())) {
+                        (Some(series_id), Some(start)) => doc! {
+                            "series_id": series_id,
+                            "occurrence_start": bson::DateTime::from_chrono(start),
+                        },
+                        _ => return Ok(None),
+                    },
+                    None => doc! { "code": room_code },
+                };
+                let room = db.room.find_one(filter, None).await?;
 
-        Ok(room)
+                Ok(room)
+            })
+            .await
     }
 
+    // Like `get_room_by_code`, but first materializes the series occurrence
+    // that can be joined now. Only joins call this; lookups stay read-only.
+    pub async fn open_room(
+        db: Arc<Database>,
+        room_code: &str,
+    ) -> mongodb::error::Result<Option<Room>> {
+        match Database::get_series_by_code(db.clone(), room_code).await? {
+            Some(series) => Database::open_occurrence(db, &series).await,
+            None => Database::get_room_by_code(db, room_code).await,
+        }
+    }
+
+    pub async fn is_code_taken(db: Arc<Database>, code: &str) -> mongodb::error::Result<bool> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("is_code_taken", async move {
+                let filter = doc! { "code": code };
+                let room = db.room.find_one(filter.clone(), None).await?;
+                let series = db.series.find_one(filter, None).await?;
+
+                Ok(room.is_some() || series.is_some())
+            })
+            .await
+    }
+
+    pub async fn create_series(db: Arc<Database>, series: Series) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("create_series", async move {
+                db.series.insert_one(series, None).await?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn get_series_by_code(
+        db: Arc<Database>,
+        code: &str,
+    ) -> mongodb::error::Result<Option<Series>> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("get_series_by_code", async move {
+                let filter = doc! { "code": code };
+                let series = db.series.find_one(filter, None).await?;
+
+                Ok(series)
+            })
+            .await
+    }
+
+    pub async fn set_series_exceptions(
+        db: Arc<Database>,
+        code: &str,
+        exceptions: &[SeriesException],
+    ) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("set_series_exceptions", async move {
+                let filter = doc! { "code": code };
+                let update = doc! {
+                    "$set": { "exceptions": bson::to_bson(exceptions)? }
+                };
+
+                db.series.update_one(filter, update, None).await?;
+                Ok(())
+            })
+            .await
+    }
+
+    // Every occurrence of a series shares the series code. Rooms of
+    // occurrences that have ended are dropped when the next one opens; one
+    // that is still running keeps its members until the reaper closes it.
+    pub async fn open_occurrence(
+        db: Arc<Database>,
+        series: &Series,
+    ) -> mongodb::error::Result<Option<Room>> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("open_occurrence", async move {
+                let now = Utc::now();
+                let (series_id, start) = match (series._id, series.active_occurrence(now)) {
+                    (Some(series_id), Some(start)) => {
+                        (series_id, bson::DateTime::from_chrono(start))
+                    }
+                    _ => return Ok(None),
+                };
+
+                let length = chrono::Duration::minutes(series.duration_minutes.into());
+                let ended = bson::DateTime::from_chrono(now - length);
+                db.room
+                    .delete_many(
+                        doc! { "series_id": series_id, "occurrence_start": { "$lte": ended } },
+                        None,
+                    )
+                    .await?;
+
+                let filter = doc! { "series_id": series_id, "occurrence_start": start };
+                let update = doc! {
+                    "$setOnInsert": {
+                        "host_id": series.host_id,
+                        "code": &series.code,
+                        "participants_id": [],
+                        "passcode": &series.passcode,
+                    }
+                };
+                let options = FindOneAndUpdateOptions::builder()
+                    .upsert(true)
+                    .return_document(ReturnDocument::After)
+                    .build();
+
+                db.room.find_one_and_update(filter, update, options).await
+            })
+            .await
+    }
+
+    pub async fn get_series_attendance(
+        db: Arc<Database>,
+        series_id: ObjectId,
+    ) -> mongodb::error::Result<Vec<Participant>> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("get_series_attendance", async move {
+                let filter = doc! { "series_id": series_id };
+                let participants = db
+                    .participant
+                    .find(filter, None)
+                    .await?
+                    .try_collect()
+                    .await?;
+
+                Ok(participants)
+            })
+            .await
+    }
+
+    // The host is not listed in `participants_id`, so a room of
+    // `max_participants` holds at most `max_participants - 1` of them. The size
+    // check and the insert happen in one update so concurrent admissions
+    // cannot overfill the room.
     pub async fn add_participant_to_room(
         db: Arc<Database>,
         room_code: &str,
         user_id: ObjectId,
+        max_participants: usize,
+    ) -> mongodb::error::Result<bool> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("add_participant_to_room", async move {
+                let max_others = max_participants.saturating_sub(1) as i64;
+                let filter = doc! {
+                    "code": room_code,
+                    "$or": [
+                        { "participants_id": user_id },
+                        { "$expr": { "$lt": [{ "$size": "$participants_id" }, max_others] } },
+                    ],
+                };
+                let update = doc! { "$addToSet": { "participants_id": user_id } };
+
+                let result = db.room.update_one(filter, update, None).await?;
+                Ok(result.matched_count > 0)
+            })
+            .await
+    }
+
+    pub async fn get_all_rooms(db: Arc<Database>) -> mongodb::error::Result<Vec<Room>> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("get_all_rooms", async move {
+                let rooms = db.room.find(None, None).await?.try_collect().await?;
+                Ok(rooms)
+            })
+            .await
+    }
+
+    pub async fn finish_participants(
+        db: Arc<Database>,
+        room_code: &str,
+    ) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("finish_participants", async move {
+                let filter = doc! { "room_code": room_code, "left_at": { "$exists": false } };
+                let update = doc! { "$set": { "left_at": bson::DateTime::now() } };
+
+                db.participant.update_many(filter, update, None).await?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn delete_room_by_id(
+        db: Arc<Database>,
+        room_id: ObjectId,
     ) -> mongodb::error::Result<()> {
-        let filter = doc! { "code": room_code };
-        let update = doc! { "$addToSet": { "participants_id": user_id } };
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("delete_room_by_id", async move {
+                let filter = doc! { "_id": room_id };
+                db.room.delete_one(filter, None).await?;
+                Ok(())
+            })
+            .await
+    }
 
-        db.room.update_one(filter, update, None).await?;
-        Ok(())
+    pub async fn count_rooms(db: Arc<Database>) -> mongodb::error::Result<u64> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("count_rooms", async move {
+                db.room.count_documents(None, None).await
+            })
+            .await
+    }
+
+    pub async fn count_hosted_rooms(
+        db: Arc<Database>,
+        host_id: ObjectId,
+    ) -> mongodb::error::Result<u64> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("count_hosted_rooms", async move {
+                let filter = doc! { "host_id": host_id };
+                db.room.count_documents(filter, None).await
+            })
+            .await
     }
 
     pub async fn remove_participant_from_room(
@@ -85,13 +464,18 @@ impl Database {
         room_code: &str,
         user_id: ObjectId,
     ) -> mongodb::error::Result<()> {
-        let filter = doc! {"code": room_code};
-        let update = doc! {
-            "$pull": { "participants_id": user_id }
-        };
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("remove_participant_from_room", async move {
+                let filter = doc! {"code": room_code};
+                let update = doc! {
+                    "$pull": { "participants_id": user_id }
+                };
 
-        db.room.update_one(filter, update, None).await?;
-        Ok(())
+                db.room.update_one(filter, update, None).await?;
+                Ok(())
+            })
+            .await
     }
 
     pub async fn update_host_id(
@@ -99,35 +483,472 @@ impl Database {
         room_code: &str,
         new_host_id: ObjectId,
     ) -> mongodb::error::Result<()> {
-        let filter = doc! { "code": room_code };
-        let update = doc! {
-            "$set": { "host_id": new_host_id }
-        };
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("update_host_id", async move {
+                let filter = doc! { "code": room_code };
+                let update = doc! {
+                    "$set": { "host_id": new_host_id }
+                };
+
+                db.room.update_one(filter, update, None).await?;
 
-        db.room.update_one(filter, update, None).await?;
+                Ok(())
+            })
+            .await
+    }
+
+    // Applies to a plain room or to a series and its current occurrence alike.
+    pub async fn set_room_passcode(
+        db: Arc<Database>,
+        room_code: &str,
+        passcode: Option<String>,
+    ) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("set_room_passcode", async move {
+                let filter = doc! { "code": room_code };
+                let update = match passcode {
+                    Some(passcode) => doc! { "$set": { "passcode": passcode } },
+                    None => doc! { "$unset": { "passcode": "" } },
+                };
 
-        Ok(())
+                db.room
+                    .update_many(filter.clone(), update.clone(), None)
+                    .await?;
+                db.series.update_one(filter, update, None).await?;
+                Ok(())
+            })
+            .await
     }
 
     pub async fn delete_room(db: Arc<Database>, room_code: &str) -> mongodb::error::Result<()> {
-        let filter = doc! { "code": room_code };
-        db.room.delete_one(filter, None).await?;
-        Ok(())
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("delete_room", async move {
+                let filter = doc! { "code": room_code };
+                db.room.delete_one(filter, None).await?;
+                Ok(())
+            })
+            .await
     }
 
     pub async fn add_participant(
         db: Arc<Database>,
-        room_code: String,
+        room: &Room,
+        user_id: ObjectId,
+    ) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        let room_code = room.code.clone();
+        let occurrence = room.series_id.zip(room.occurrence_start);
+        metrics
+            .observe_db("add_participant", async move {
+                if let Some((series_id, occurrence_start)) = occurrence {
+                    let filter = doc! {
+                        "user_id": user_id,
+                        "series_id": series_id,
+                        "occurrence_start": occurrence_start,
+                    };
+                    let update = doc! { "$setOnInsert": { "room_code": &room_code } };
+                    let options = UpdateOptions::builder().upsert(true).build();
+
+                    db.participant.update_one(filter, update, options).await?;
+                    return Ok(());
+                }
+
+                let new_participant = Participant {
+                    _id: Some(ObjectId::new()),
+                    user_id,
+                    room_code,
+                    series_id: None,
+                    occurrence_start: None,
+                    left_at: None,
+                };
+
+                db.participant.insert_one(new_participant, None).await?;
+
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn create_invite(db: Arc<Database>, invite: Invite) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("create_invite", async move {
+                db.invite.insert_one(invite, None).await?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn get_invite_by_id(
+        db: Arc<Database>,
+        invite_id: ObjectId,
+    ) -> mongodb::error::Result<Option<Invite>> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("get_invite_by_id", async move {
+                let filter = doc! { "_id": invite_id };
+                let invite = db.invite.find_one(filter, None).await?;
+
+                Ok(invite)
+            })
+            .await
+    }
+
+    pub async fn get_invites_for_room(
+        db: Arc<Database>,
+        room_code: &str,
+    ) -> mongodb::error::Result<Vec<Invite>> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("get_invites_for_room", async move {
+                let filter = doc! { "room_code": room_code };
+                let invites = db.invite.find(filter, None).await?.try_collect().await?;
+
+                Ok(invites)
+            })
+            .await
+    }
+
+    pub async fn revoke_invite(
+        db: Arc<Database>,
+        invite_id: ObjectId,
+        room_code: &str,
+    ) -> mongodb::error::Result<bool> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("revoke_invite", async move {
+                let filter = doc! { "_id": invite_id, "room_code": room_code };
+                let update = doc! { "$set": { "revoked": true } };
+
+                let result = db.invite.update_one(filter, update, None).await?;
+                Ok(result.matched_count > 0)
+            })
+            .await
+    }
+
+    // Consumes one use of the invite; returns `None` once it is revoked, expired
+    // or used up, so concurrent joins can never exceed `max_uses`.
+    pub async fn redeem_invite(
+        db: Arc<Database>,
+        invite_id: ObjectId,
+        room_code: &str,
+    ) -> mongodb::error::Result<Option<Invite>> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("redeem_invite", async move {
+                let filter = doc! {
+                    "_id": invite_id,
+                    "room_code": room_code,
+                    "revoked": false,
+                    "expires_at": { "$gt": bson::DateTime::now() },
+                    "$expr": { "$lt": ["$uses", "$max_uses"] },
+                };
+                let update = doc! { "$inc": { "uses": 1 } };
+                let options = FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build();
+
+                db.invite.find_one_and_update(filter, update, options).await
+            })
+            .await
+    }
+
+    // Gives back a use taken by `redeem_invite` when the join it was redeemed
+    // for did not go through.
+    pub async fn release_invite(
+        db: Arc<Database>,
+        invite_id: ObjectId,
+    ) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("release_invite", async move {
+                let filter = doc! { "_id": invite_id, "uses": { "$gt": 0 } };
+                let update = doc! { "$inc": { "uses": -1 } };
+
+                db.invite.update_one(filter, update, None).await?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn create_recording(
+        db: Arc<Database>,
+        recording: Recording,
+    ) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("create_recording", async move {
+                db.recording.insert_one(recording, None).await?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn finish_recording(
+        db: Arc<Database>,
+        recording_id: ObjectId,
+        tracks: &[RecordingTrack],
+    ) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("finish_recording", async move {
+                let filter = doc! { "_id": recording_id };
+                let update = doc! {
+                    "$set": {
+                        "ended_at": bson::DateTime::now(),
+                        "tracks": bson::to_bson(tracks)?,
+                    }
+                };
+
+                db.recording.update_one(filter, update, None).await?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn get_recording_by_id(
+        db: Arc<Database>,
+        recording_id: ObjectId,
+    ) -> mongodb::error::Result<Option<Recording>> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("get_recording_by_id", async move {
+                let filter = doc! { "_id": recording_id };
+                let recording = db.recording.find_one(filter, None).await?;
+
+                Ok(recording)
+            })
+            .await
+    }
+
+    pub async fn get_recordings_for_room(
+        db: Arc<Database>,
+        room_code: &str,
+    ) -> mongodb::error::Result<Vec<Recording>> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("get_recordings_for_room", async move {
+                let filter = doc! { "room_code": room_code };
+                let recordings = db.recording.find(filter, None).await?.try_collect().await?;
+
+                Ok(recordings)
+            })
+            .await
+    }
+
+    // The audit log is append-only: there is deliberately no update or delete.
+    pub async fn insert_audit_event(
+        db: Arc<Database>,
+        event: AuditEvent,
+    ) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("insert_audit_event", async move {
+                db.audit.insert_one(event, None).await?;
+                Ok(())
+            })
+            .await
+    }
+
+    // Newest first. A user matches as either the actor or the target.
+    pub async fn find_audit_events(
+        db: Arc<Database>,
+        user_id: Option<ObjectId>,
+        room_code: Option<&str>,
+        before: Option<bson::DateTime>,
+        limit: i64,
+    ) -> mongodb::error::Result<Vec<AuditEvent>> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("find_audit_events", async move {
+                let mut filter = doc! {};
+                if let Some(user_id) = user_id {
+                    filter.insert(
+                        "$or",
+                        vec![doc! { "actor": user_id }, doc! { "target": user_id }],
+                    );
+                }
+                if let Some(room_code) = room_code {
+                    filter.insert("room_code", room_code);
+                }
+                if let Some(before) = before {
+                    filter.insert("at", doc! { "$lt": before });
+                }
+                let options = FindOptions::builder()
+                    .sort(doc! { "at": -1 })
+                    .limit(limit)
+                    .build();
+
+                let events = db.audit.find(filter, options).await?.try_collect().await?;
+                Ok(events)
+            })
+            .await
+    }
+
+    pub async fn set_user_verified(
+        db: Arc<Database>,
+        user_id: ObjectId,
+    ) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("set_user_verified", async move {
+                let update = doc! { "$set": { "verified": true } };
+                db.user
+                    .update_one(doc! { "_id": user_id }, update, None)
+                    .await?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn set_user_password(
+        db: Arc<Database>,
+        user_id: ObjectId,
+        password: String,
+    ) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("set_user_password", async move {
+                let update = doc! { "$set": { "password": password } };
+                db.user
+                    .update_one(doc! { "_id": user_id }, update, None)
+                    .await?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn create_email_token(
+        db: Arc<Database>,
+        token: EmailToken,
+    ) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("create_email_token", async move {
+                db.email_token.insert_one(token, None).await?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub async fn get_email_token(
+        db: Arc<Database>,
+        token_hash: &str,
+        purpose: TokenPurpose,
+    ) -> mongodb::error::Result<Option<EmailToken>> {
+        let metrics = db.metrics.clone();
+        let token_hash = token_hash.to_string();
+        metrics
+            .observe_db("get_email_token", async move {
+                let filter = doc! {
+                    "token_hash": token_hash,
+                    "purpose": bson::to_bson(&purpose)?,
+                    "used_at": null,
+                    "expires_at": { "$gt": bson::DateTime::now() },
+                };
+                db.email_token.find_one(filter, None).await
+            })
+            .await
+    }
+
+    // Marks the token used and returns it, or `None` if it is unknown, expired,
+    // already used or meant for something else. Atomic, so a link works once
+    // even when opened twice at the same moment.
+    pub async fn redeem_email_token(
+        db: Arc<Database>,
+        token_hash: &str,
+        purpose: TokenPurpose,
+    ) -> mongodb::error::Result<Option<EmailToken>> {
+        let metrics = db.metrics.clone();
+        let token_hash = token_hash.to_string();
+        metrics
+            .observe_db("redeem_email_token", async move {
+                let now = bson::DateTime::now();
+                let filter = doc! {
+                    "token_hash": token_hash,
+                    "purpose": bson::to_bson(&purpose)?,
+                    "used_at": null,
+                    "expires_at": { "$gt": now },
+                };
+                let update = doc! { "$set": { "used_at": now } };
+                let options = FindOneAndUpdateOptions::builder()
+                    .return_document(ReturnDocument::After)
+                    .build();
+
+                db.email_token
+                    .find_one_and_update(filter, update, options)
+                    .await
+            })
+            .await
+    }
+
+    // Voids every outstanding token of the kind, e.g. older reset links once
+    // the password has changed.
+    pub async fn revoke_email_tokens(
+        db: Arc<Database>,
+        user_id: ObjectId,
+        purpose: TokenPurpose,
+    ) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("revoke_email_tokens", async move {
+                let filter = doc! {
+                    "user_id": user_id,
+                    "purpose": bson::to_bson(&purpose)?,
+                    "used_at": null,
+                };
+                let update = doc! { "$set": { "used_at": bson::DateTime::now() } };
+                db.email_token.update_many(filter, update, None).await?;
+                Ok(())
+            })
+            .await
+    }
+
+    // Logs the user out of every device: tokens issued before the bump no
+    // longer match the stored version.
+    pub async fn bump_token_version(
+        db: Arc<Database>,
         user_id: ObjectId,
     ) -> mongodb::error::Result<()> {
-        let new_participant = Participant {
-            _id: Some(ObjectId::new()),
-            user_id,
-            room_code,
-        };
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("bump_token_version", async move {
+                let update = doc! { "$inc": { "token_version": 1 } };
+                db.user
+                    .update_one(doc! { "_id": user_id }, update, None)
+                    .await?;
+                Ok(())
+            })
+            .await
+    }
 
-        db.participant.insert_one(new_participant, None).await?;
+    // Revoking a token twice is not an error; logging out is idempotent.
+    pub async fn revoke_token(
+        db: Arc<Database>,
+        token: RevokedToken,
+    ) -> mongodb::error::Result<()> {
+        let metrics = db.metrics.clone();
+        metrics
+            .observe_db("revoke_token", async move {
+                match db.revoked_token.insert_one(token, None).await {
+                    Err(err) if !is_duplicate_key(&err) => Err(err),
+                    _ => Ok(()),
+                }
+            })
+            .await
+    }
 
-        Ok(())
+    pub async fn is_token_revoked(db: Arc<Database>, jti: &str) -> mongodb::error::Result<bool> {
+        let metrics = db.metrics.clone();
+        let jti = jti.to_string();
+        metrics
+            .observe_db("is_token_revoked", async move {
+                let revoked = db.revoked_token.find_one(doc! { "_id": jti }, None).await?;
+                Ok(revoked.is_some())
+            })
+            .await
     }
 }
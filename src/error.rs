@@ -0,0 +1,310 @@
+use std::fmt;
+
+use axum::{
+    extract::{FromRequest, rejection::JsonRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use serde_json::json;
+use tracing::{debug, error};
+
+// Stable, machine-readable codes shared by REST error bodies and WebSocket
+// `error` frames. Clients branch on these, so existing names must not change.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorCode {
+    InternalError,
+    InvalidRequest,
+    Unauthenticated,
+    InvalidToken,
+    InvalidCredentials,
+    InvalidEmailToken,
+    EmailTaken,
+    Forbidden,
+    NotFound,
+    RoomQuotaExceeded,
+    RoomLimitReached,
+    RoomFull,
+    NotInRoom,
+    NotHost,
+    PasscodeRequired,
+    InvalidPasscode,
+    TooManyAttempts,
+    RateLimited,
+    TargetOverloaded,
+    InvalidSignal,
+    InvalidInput,
+    PayloadTooLarge,
+    SfuError,
+    AlreadyRecording,
+    NotRecording,
+    RecordingFailed,
+    ResumeFailed,
+    AccessAlreadyGranted,
+    AccessNotRequested,
+    AccessNotGranted,
+    AccessExpired,
+}
+
+impl ErrorCode {
+    pub fn status(self) -> StatusCode {
+        match self {
+            ErrorCode::InternalError | ErrorCode::RecordingFailed => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            ErrorCode::SfuError => StatusCode::BAD_GATEWAY,
+            ErrorCode::InvalidRequest
+            | ErrorCode::InvalidEmailToken
+            | ErrorCode::InvalidSignal
+            | ErrorCode::InvalidInput
+            | ErrorCode::PasscodeRequired => StatusCode::BAD_REQUEST,
+            ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorCode::Unauthenticated
+            | ErrorCode::InvalidToken
+            | ErrorCode::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            ErrorCode::Forbidden | ErrorCode::NotHost | ErrorCode::InvalidPasscode => {
+                StatusCode::FORBIDDEN
+            }
+            ErrorCode::NotFound => StatusCode::NOT_FOUND,
+            ErrorCode::EmailTaken
+            | ErrorCode::RoomFull
+            | ErrorCode::NotInRoom
+            | ErrorCode::AlreadyRecording
+            | ErrorCode::NotRecording
+            | ErrorCode::ResumeFailed
+            | ErrorCode::AccessAlreadyGranted
+            | ErrorCode::AccessNotRequested
+            | ErrorCode::AccessNotGranted => StatusCode::CONFLICT,
+            ErrorCode::AccessExpired => StatusCode::GONE,
+            ErrorCode::RoomQuotaExceeded | ErrorCode::TooManyAttempts | ErrorCode::RateLimited => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            ErrorCode::RoomLimitReached | ErrorCode::TargetOverloaded => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+        }
+    }
+}
+
+// Library errors are logged here and reach clients only as their code and a
+// generic message, so driver and token details are never exposed.
+#[derive(Debug)]
+pub enum AppError {
+    Database(mongodb::error::Error),
+    Token(jsonwebtoken::errors::Error),
+    Password(bcrypt::BcryptError),
+    // A broken invariant; the text is logged, not shown to the client.
+    Internal(&'static str),
+    Validation(String),
+    Unauthenticated,
+    InvalidCredentials,
+    Forbidden,
+    NotFound(&'static str),
+    Rejected(ErrorCode, &'static str),
+}
+
+impl AppError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::Database(_) | AppError::Password(_) | AppError::Internal(_) => {
+                ErrorCode::InternalError
+            }
+            AppError::Token(_) => ErrorCode::InvalidToken,
+            AppError::Validation(_) => ErrorCode::InvalidRequest,
+            AppError::Unauthenticated => ErrorCode::Unauthenticated,
+            AppError::InvalidCredentials => ErrorCode::InvalidCredentials,
+            AppError::Forbidden => ErrorCode::Forbidden,
+            AppError::NotFound(_) => ErrorCode::NotFound,
+            AppError::Rejected(code, _) => *code,
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            AppError::Database(_) | AppError::Password(_) | AppError::Internal(_) => {
+                "Something went wrong, please try again later."
+            }
+            AppError::Token(_) => "Your session is invalid or has expired.",
+            AppError::Validation(message) => message,
+            AppError::Unauthenticated => "Sign in to continue.",
+            AppError::InvalidCredentials => "Incorrect email or password.",
+            AppError::Forbidden => "You are not allowed to do that.",
+            AppError::NotFound(message) | AppError::Rejected(_, message) => message,
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.code().status()
+    }
+
+    pub fn log(&self) {
+        match self {
+            AppError::Database(err) => error!(error = %err, "Database operation failed"),
+            AppError::Password(err) => error!(error = %err, "Password hashing failed"),
+            AppError::Internal(context) => error!(context, "Internal error"),
+            AppError::Token(err) => debug!(error = %err, "JWT verification failed"),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::Database(err) => write!(f, "database: {}", err),
+            AppError::Token(err) => write!(f, "token: {}", err),
+            AppError::Password(err) => write!(f, "password: {}", err),
+            AppError::Internal(context) => write!(f, "internal: {}", context),
+            _ => f.write_str(self.message()),
+        }
+    }
+}
+
+impl From<mongodb::error::Error> for AppError {
+    fn from(err: mongodb::error::Error) -> Self {
+        AppError::Database(err)
+    }
+}
+
+impl From<jsonwebtoken::errors::Error> for AppError {
+    fn from(err: jsonwebtoken::errors::Error) -> Self {
+        AppError::Token(err)
+    }
+}
+
+impl From<bcrypt::BcryptError> for AppError {
+    fn from(err: bcrypt::BcryptError) -> Self {
+        AppError::Password(err)
+    }
+}
+
+// Bodies the extractor cannot read are rejected with the usual error body
+// rather than axum's plain-text one.
+impl From<JsonRejection> for AppError {
+    fn from(rejection: JsonRejection) -> Self {
+        if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            return AppError::Rejected(
+                ErrorCode::PayloadTooLarge,
+                "The request body is too large.",
+            );
+        }
+        AppError::Validation(rejection.body_text())
+    }
+}
+
+// `axum::Json`, whose rejections go through `AppError`. Handlers use it both
+// to read request bodies and to reply.
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(AppError))]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        self.log();
+
+        (
+            self.status(),
+            Json(json!({
+                "success": false,
+                "error": { "code": self.code(), "message": self.message() }
+            })),
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::to_bytes;
+    use serde_json::Value;
+
+    use super::*;
+
+    async fn render(error: AppError) -> (StatusCode, Value) {
+        let response = error.into_response();
+        let status = response.status();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn errors_share_one_body_shape() {
+        let (status, body) = render(AppError::Rejected(
+            ErrorCode::RoomQuotaExceeded,
+            "You already have the maximum number of open rooms.",
+        ))
+        .await;
+
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(
+            body,
+            json!({
+                "success": false,
+                "error": {
+                    "code": "room-quota-exceeded",
+                    "message": "You already have the maximum number of open rooms."
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn library_errors_hide_their_details() {
+        let token =
+            jsonwebtoken::errors::Error::from(jsonwebtoken::errors::ErrorKind::InvalidSignature);
+        let (status, body) = render(token.into()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"]["code"], "invalid-token");
+        assert_eq!(
+            body["error"]["message"],
+            "Your session is invalid or has expired."
+        );
+
+        let database = mongodb::error::Error::custom("connection reset by 10.0.0.7");
+        let (status, body) = render(database.into()).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(body["error"]["code"], "internal-error");
+        assert!(!body.to_string().contains("10.0.0.7"));
+    }
+
+    #[tokio::test]
+    async fn unreadable_bodies_share_the_body_shape() {
+        use axum::{body::Body, http::Request};
+
+        #[derive(serde::Deserialize)]
+        struct Payload {
+            #[allow(dead_code)]
+            email: String,
+        }
+
+        let request = Request::builder()
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"name":"alice"}"#))
+            .unwrap();
+        let Err(error) = Json::<Payload>::from_request(request, &()).await else {
+            panic!("a body without `email` was accepted");
+        };
+
+        let (status, body) = render(error).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["success"], false);
+        assert_eq!(body["error"]["code"], "invalid-request");
+    }
+
+    #[test]
+    fn codes_serialize_as_kebab_case() {
+        assert_eq!(json!(ErrorCode::SfuError), "sfu-error");
+        assert_eq!(
+            json!(ErrorCode::AccessAlreadyGranted),
+            "access-already-granted"
+        );
+        assert_eq!(json!(ErrorCode::PayloadTooLarge), "payload-too-large");
+    }
+}
@@ -0,0 +1,81 @@
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use tokio::signal;
+use tracing::info;
+
+use crate::ws::{self, AppState};
+
+pub struct Lifecycle {
+    started: Instant,
+    draining: AtomicBool,
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Lifecycle {
+            started: Instant::now(),
+            draining: AtomicBool::new(false),
+        }
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    fn start_draining(&self) {
+        self.draining.store(true, Ordering::Relaxed);
+    }
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Lifecycle::new()
+    }
+}
+
+async fn terminated() {
+    let interrupt = async {
+        signal::ctrl_c()
+            .await
+            .expect("❌ Failed to listen for Ctrl+C");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("❌ Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {},
+        _ = terminate => {},
+    }
+}
+
+// Resolves once the server should stop accepting connections. On SIGTERM or
+// Ctrl+C the server first reports itself unready for `grace`, so the load
+// balancer stops routing to it, then asks every socket to reconnect elsewhere.
+pub async fn drain_on_signal(lifecycle: Arc<Lifecycle>, ws_state: Arc<AppState>, grace: Duration) {
+    terminated().await;
+
+    lifecycle.start_draining();
+    info!(grace_secs = grace.as_secs(), "Draining before shutdown");
+    tokio::time::sleep(grace).await;
+
+    ws::close_all(&ws_state).await;
+    info!("Shutting down");
+}
@@ -0,0 +1,89 @@
+mod file;
+mod smtp;
+
+use std::{fmt, io, sync::Arc};
+
+use async_trait::async_trait;
+
+use crate::config::Config;
+
+pub use self::{file::FileMailer, smtp::SmtpMailer};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum MailError {
+    Smtp(lettre::transport::smtp::Error),
+    Message(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for MailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MailError::Smtp(err) => write!(f, "smtp: {}", err),
+            MailError::Message(err) => write!(f, "message: {}", err),
+            MailError::Io(err) => write!(f, "io: {}", err),
+        }
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for MailError {
+    fn from(err: lettre::transport::smtp::Error) -> Self {
+        MailError::Smtp(err)
+    }
+}
+
+impl From<io::Error> for MailError {
+    fn from(err: io::Error) -> Self {
+        MailError::Io(err)
+    }
+}
+
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, mail: &Mail) -> Result<(), MailError>;
+}
+
+// Uses SMTP when `SMTP_URL` is set; otherwise mail is written to `MAIL_DIR`
+// so verification and reset links can be followed during development.
+pub fn from_config(config: &Config) -> Result<Arc<dyn Mailer>, MailError> {
+    match &config.smtp_url {
+        Some(url) => Ok(Arc::new(SmtpMailer::new(url, &config.mail_from)?)),
+        None => Ok(Arc::new(FileMailer::new(config.mail_dir.clone()))),
+    }
+}
+
+pub fn verification_mail(config: &Config, to: &str, token: &str) -> Mail {
+    Mail {
+        to: to.to_string(),
+        subject: "Confirm your email address".to_string(),
+        body: format!(
+            "Open this link to confirm your email address:\n\n{}/verify-email?token={}\n\n\
+             The link expires in {} hours. If you did not create an account, ignore this mail.\n",
+            config.app_url,
+            token,
+            config.verify_token_ttl.as_secs() / 3600,
+        ),
+    }
+}
+
+pub fn reset_mail(config: &Config, to: &str, token: &str) -> Mail {
+    Mail {
+        to: to.to_string(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Open this link to choose a new password:\n\n{}/reset-password?token={}\n\n\
+             The link expires in {} minutes and works once. If you did not ask to reset \
+             your password, ignore this mail.\n",
+            config.app_url,
+            token,
+            config.reset_token_ttl.as_secs() / 60,
+        ),
+    }
+}
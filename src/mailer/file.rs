@@ -0,0 +1,73 @@
+use std::path::PathBuf;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use tokio::fs;
+use tracing::info;
+use uuid::Uuid;
+
+use super::{Mail, MailError, Mailer};
+
+// Writes each mail to its own file instead of sending it. Meant for local
+// development and tests; mail bodies carry live tokens.
+pub struct FileMailer {
+    dir: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new(dir: PathBuf) -> Self {
+        FileMailer { dir }
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, mail: &Mail) -> Result<(), MailError> {
+        fs::create_dir_all(&self.dir).await?;
+
+        let path = self.dir.join(format!(
+            "{}-{}.eml",
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            Uuid::new_v4().simple()
+        ));
+        let contents = format!(
+            "To: {}\nSubject: {}\n\n{}",
+            mail.to, mail.subject, mail.body
+        );
+        fs::write(&path, contents).await?;
+
+        info!(path = %path.display(), subject = %mail.subject, "Mail written to file");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn mail_is_written_to_its_own_file() {
+        let dir = std::env::temp_dir().join(format!("mailer-{}", Uuid::new_v4().simple()));
+        let mailer = FileMailer::new(dir.clone());
+        let mail = Mail {
+            to: "alice@example.com".to_string(),
+            subject: "Reset your password".to_string(),
+            body: "https://app.example.com/reset-password?token=abc\n".to_string(),
+        };
+
+        mailer.send(&mail).await.unwrap();
+        mailer.send(&mail).await.unwrap();
+
+        let mut entries = fs::read_dir(&dir).await.unwrap();
+        let mut contents = vec![];
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            contents.push(fs::read_to_string(entry.path()).await.unwrap());
+        }
+        fs::remove_dir_all(&dir).await.unwrap();
+
+        assert_eq!(contents.len(), 2);
+        assert!(contents.iter().all(|contents| contents
+            == "To: alice@example.com\nSubject: Reset your password\n\n\
+                https://app.example.com/reset-password?token=abc\n"));
+    }
+}
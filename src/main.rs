@@ -1,29 +1,52 @@
 mod api;
+mod broker;
+mod config;
 mod db;
+mod error;
+mod lifecycle;
+mod mailer;
+mod metrics;
 mod models;
+mod reaper;
+mod recording;
+mod sfu;
+mod telemetry;
 mod utils;
 mod ws;
 
 use axum::{
     Router,
     http::{
-        HeaderValue, Method,
+        HeaderName, HeaderValue, Method,
         header::{AUTHORIZATION, CONTENT_TYPE},
     },
+    middleware,
     routing::get,
 };
 use dotenv::dotenv;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::Duration;
 use tower_cookies::CookieManagerLayer;
-use tower_http::cors::CorsLayer;
+use tower_http::{
+    cors::CorsLayer,
+    trace::{DefaultOnResponse, TraceLayer},
+};
+use tracing::{Level, info};
 // use tokio::sync::broadcast;
-use std::collections::HashMap;
-use tokio::sync::Mutex;
 
 use crate::{
-    api::{auth::auth_router, room::room_router},
+    api::{
+        audit::audit_router, auth::auth_router, health::health_router, metrics::metrics_router,
+        recording::recording_router, room::room_router, rtc::rtc_router, series::series_router,
+    },
+    config::Config,
     db::connection::Database,
+    lifecycle::{Lifecycle, drain_on_signal},
+    mailer::Mailer,
+    metrics::{Metrics, track_http},
+    sfu::Sfu,
+    utils::rate_limit::AttemptLimiter,
     ws::AppState,
 };
 
@@ -31,46 +54,102 @@ use crate::{
 pub struct SharedState {
     pub db: Arc<Database>,
     pub ws_state: Arc<AppState>,
+    pub config: Arc<Config>,
+    pub sfu: Arc<Sfu>,
+    pub lifecycle: Arc<Lifecycle>,
+    pub mailer: Arc<dyn Mailer>,
 }
 
 #[tokio::main]
 async fn main() {
     dotenv().ok();
 
+    let config = Arc::new(Config::from_env());
+    let _telemetry = telemetry::init(&config);
+    let metrics = Arc::new(Metrics::new());
+
     let db = Arc::new(
-        Database::init()
+        Database::init(metrics.clone())
             .await
             .expect("❌ Failed to connect to MongoDB"),
     );
     // let (tx, _rx) = broadcast::channel(100);
-    let user_sockets = Arc::new(Mutex::new(HashMap::new()));
-    let sockets = Arc::new(Mutex::new(HashMap::new()));
+    let broker = broker::from_config(&config)
+        .await
+        .expect("❌ Failed to connect to the broker");
+
+    let passcode_attempts = AttemptLimiter::new(
+        config.passcode_max_failures,
+        config.passcode_window,
+        config.passcode_lockout,
+    );
+
+    let app_state = Arc::new(AppState::new(broker, passcode_attempts, metrics.clone()));
+
+    tokio::spawn(ws::run_deliveries(app_state.clone()));
+
+    let sfu = Arc::new(Sfu::new(&config, db.clone()).expect("❌ Failed to set up the SFU"));
+
+    tokio::spawn(reaper::run(
+        db.clone(),
+        app_state.clone(),
+        sfu.clone(),
+        config.clone(),
+    ));
+
+    let mailer = mailer::from_config(&config).expect("❌ Failed to set up the mailer");
 
-    let app_state = Arc::new(AppState {
-        user_sockets,
-        sockets,
-    });
+    let lifecycle = Arc::new(Lifecycle::new());
+    let shutdown = drain_on_signal(lifecycle.clone(), app_state.clone(), config.drain_grace);
 
     let shared_state = SharedState {
         db: db.clone(),
         ws_state: app_state,
+        config,
+        sfu,
+        lifecycle,
+        mailer,
     };
 
     let cors = CorsLayer::new()
         .allow_origin(HeaderValue::from_static("http://localhost:5173"))
         .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
-        .allow_headers([CONTENT_TYPE, AUTHORIZATION])
+        .allow_headers([
+            CONTENT_TYPE,
+            AUTHORIZATION,
+            HeaderName::from_static("traceparent"),
+            HeaderName::from_static("tracestate"),
+        ])
         .allow_credentials(true)
         .max_age(Duration::from_secs(3600));
 
     let app = Router::new()
         .nest("/auth", auth_router())
         .nest("/room", room_router())
+        .nest("/series", series_router())
+        .nest("/rtc", rtc_router())
+        .nest("/recording", recording_router())
+        .nest("/audit", audit_router())
+        .nest("/metrics", metrics_router())
+        .merge(health_router())
         .route("/ws", get(ws::handler))
+        .layer(middleware::from_fn_with_state(metrics, track_http))
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(telemetry::http_span)
+                .on_response(DefaultOnResponse::new().level(Level::INFO)),
+        )
         .layer(CookieManagerLayer::new())
         .layer(cors)
         .with_state(shared_state);
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    info!(address = "0.0.0.0:3000", "Listening");
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown)
+    .await
+    .unwrap();
 }
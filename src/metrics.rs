@@ -0,0 +1,194 @@
+use std::{future::Future, sync::Arc, time::Instant};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use tracing::{Instrument, field, info_span};
+
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+const PARTICIPANT_BUCKETS: &[f64] = &[1.0, 2.0, 3.0, 4.0, 6.0, 8.0, 12.0, 16.0, 24.0, 32.0];
+
+// Labels are limited to values the server picks (route templates, method and
+// message-type names) so clients cannot create new series.
+pub struct Metrics {
+    registry: Registry,
+    pub ws_connections: IntGauge,
+    pub rooms_active: IntGauge,
+    pub rooms_reaped: IntCounter,
+    pub room_participants: Histogram,
+    pub ws_messages: IntCounterVec,
+    pub ws_send_failures: IntCounter,
+    pub ws_join_duration: HistogramVec,
+    db_duration: HistogramVec,
+    db_errors: IntCounterVec,
+    http_requests: IntCounterVec,
+    http_duration: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("telesync".to_string()), None).unwrap();
+
+        let metrics = Metrics {
+            ws_connections: IntGauge::new("ws_connections", "Open WebSocket connections.").unwrap(),
+            rooms_active: IntGauge::new("rooms_active", "Open rooms.").unwrap(),
+            rooms_reaped: IntCounter::new("rooms_reaped_total", "Idle rooms closed by the reaper.")
+                .unwrap(),
+            room_participants: Histogram::with_opts(
+                HistogramOpts::new(
+                    "room_participants",
+                    "Room size, host included, each time a participant is admitted.",
+                )
+                .buckets(PARTICIPANT_BUCKETS.to_vec()),
+            )
+            .unwrap(),
+            ws_messages: IntCounterVec::new(
+                Opts::new("ws_messages_total", "WebSocket messages handled, by type."),
+                &["type"],
+            )
+            .unwrap(),
+            ws_send_failures: IntCounter::new(
+                "ws_send_failures_total",
+                "Frames that could not be written to a socket.",
+            )
+            .unwrap(),
+            ws_join_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "ws_join_duration_seconds",
+                    "Time spent handling join, admission and resume messages.",
+                )
+                .buckets(LATENCY_BUCKETS.to_vec()),
+                &["type"],
+            )
+            .unwrap(),
+            db_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "db_operation_duration_seconds",
+                    "MongoDB operation latency.",
+                )
+                .buckets(LATENCY_BUCKETS.to_vec()),
+                &["method"],
+            )
+            .unwrap(),
+            db_errors: IntCounterVec::new(
+                Opts::new("db_operation_errors_total", "Failed MongoDB operations."),
+                &["method"],
+            )
+            .unwrap(),
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests served."),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            http_duration: HistogramVec::new(
+                HistogramOpts::new("http_request_duration_seconds", "HTTP request latency.")
+                    .buckets(LATENCY_BUCKETS.to_vec()),
+                &["method", "route"],
+            )
+            .unwrap(),
+            registry,
+        };
+
+        let collectors: [Box<dyn prometheus::core::Collector>; 11] = [
+            Box::new(metrics.ws_connections.clone()),
+            Box::new(metrics.rooms_active.clone()),
+            Box::new(metrics.rooms_reaped.clone()),
+            Box::new(metrics.room_participants.clone()),
+            Box::new(metrics.ws_messages.clone()),
+            Box::new(metrics.ws_send_failures.clone()),
+            Box::new(metrics.ws_join_duration.clone()),
+            Box::new(metrics.db_duration.clone()),
+            Box::new(metrics.db_errors.clone()),
+            Box::new(metrics.http_requests.clone()),
+            Box::new(metrics.http_duration.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+
+        metrics
+    }
+
+    pub fn render(&self) -> String {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    pub async fn observe_db<T, E, F>(&self, method: &str, operation: F) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        // Named after the method, as a client span, so exported traces read
+        // as calls out to MongoDB.
+        let span = info_span!(
+            "db",
+            otel.name = method,
+            otel.kind = "client",
+            otel.status_code = field::Empty,
+            db.system = "mongodb",
+            db.operation = method,
+        );
+        let started = Instant::now();
+        let result = operation.instrument(span.clone()).await;
+
+        self.db_duration
+            .with_label_values(&[method])
+            .observe(started.elapsed().as_secs_f64());
+        if result.is_err() {
+            self.db_errors.with_label_values(&[method]).inc();
+            span.record("otel.status_code", "error");
+        }
+
+        result
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+// Records every HTTP request under its route template rather than the raw
+// path, so ids in the URL do not each get their own series.
+pub async fn track_http(
+    State(metrics): State<Arc<Metrics>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let method = match request.method().as_str() {
+        method @ ("GET" | "POST" | "PUT" | "PATCH" | "DELETE" | "HEAD" | "OPTIONS") => method,
+        _ => "other",
+    }
+    .to_owned();
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+
+    metrics
+        .http_duration
+        .with_label_values(&[&method, &route])
+        .observe(started.elapsed().as_secs_f64());
+    metrics
+        .http_requests
+        .with_label_values(&[&method, &route, response.status().as_str()])
+        .inc();
+
+    response
+}
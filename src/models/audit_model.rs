@@ -0,0 +1,61 @@
+use mongodb::bson::{self, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditAction {
+    AccessRequested,
+    AccessGranted,
+    AccessRejected,
+    AccessRevoked,
+    AccessExpired,
+    SessionStarted,
+    SessionEnded,
+    KeyPress,
+    ClipboardPaste,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub _id: Option<ObjectId>,
+
+    pub at: bson::DateTime,
+    pub action: AuditAction,
+    pub actor: ObjectId,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<ObjectId>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room_code: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mouse_events: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_events: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction, actor: ObjectId) -> Self {
+        AuditEvent {
+            _id: None,
+            at: bson::DateTime::now(),
+            action,
+            actor,
+            target: None,
+            room_code: None,
+            mouse_events: None,
+            key_events: None,
+            key: None,
+            text: None,
+        }
+    }
+}
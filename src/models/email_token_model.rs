@@ -0,0 +1,25 @@
+use mongodb::bson::{self, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+// Only a hash of the token is stored, so a database leak does not hand out
+// working links. Expired documents are removed by a TTL index.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct EmailToken {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub _id: Option<ObjectId>,
+
+    pub user_id: ObjectId,
+    pub purpose: TokenPurpose,
+    pub token_hash: String,
+    pub expires_at: bson::DateTime,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub used_at: Option<bson::DateTime>,
+}
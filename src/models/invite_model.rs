@@ -0,0 +1,22 @@
+use mongodb::bson::{self, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Invite {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub _id: Option<ObjectId>,
+
+    pub room_code: String,
+    pub created_by: ObjectId,
+    pub expires_at: bson::DateTime,
+    pub max_uses: u32,
+
+    #[serde(default)]
+    pub uses: u32,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+
+    #[serde(default)]
+    pub revoked: bool,
+}
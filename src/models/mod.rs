@@ -1,3 +1,9 @@
 pub mod user_model;
 pub mod room_model;
-pub mod participant_model;
\ No newline at end of file
+pub mod participant_model;
+pub mod series_model;
+pub mod invite_model;
+pub mod recording_model;
+pub mod audit_model;
+pub mod email_token_model;
+pub mod revoked_token_model;
\ No newline at end of file
@@ -1,11 +1,20 @@
-use mongodb::bson::oid::ObjectId;
-use serde::{Serialize, Deserialize};
+use mongodb::bson::{self, oid::ObjectId};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Participant {
-    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")] 
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub _id: Option<ObjectId>,
 
     pub user_id: ObjectId,
     pub room_code: String,
-}
\ No newline at end of file
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series_id: Option<ObjectId>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub occurrence_start: Option<bson::DateTime>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub left_at: Option<bson::DateTime>,
+}
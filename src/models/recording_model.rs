@@ -0,0 +1,26 @@
+use mongodb::bson::{self, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RecordingTrack {
+    pub file_name: String,
+    pub user_id: ObjectId,
+    pub kind: String,
+    pub codec: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Recording {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub _id: Option<ObjectId>,
+
+    pub room_code: String,
+    pub host_id: ObjectId,
+    pub started_at: bson::DateTime,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ended_at: Option<bson::DateTime>,
+
+    #[serde(default)]
+    pub tracks: Vec<RecordingTrack>,
+}
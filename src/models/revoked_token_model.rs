@@ -0,0 +1,13 @@
+use mongodb::bson::{self, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+// An access token ended by logging out, keyed by its `jti`. The entry is only
+// needed until the token would have expired anyway, when a TTL index drops it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RevokedToken {
+    #[serde(rename = "_id")]
+    pub jti: String,
+
+    pub user_id: ObjectId,
+    pub expires_at: bson::DateTime,
+}
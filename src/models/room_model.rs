@@ -1,9 +1,17 @@
-use mongodb::bson::oid::ObjectId;
-use serde::{Serialize, Deserialize};
+use mongodb::bson::{self, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RoomMode {
+    #[default]
+    Mesh,
+    Sfu,
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Room {
-    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")] 
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
     pub _id: Option<ObjectId>,
 
     pub host_id: ObjectId,
@@ -11,4 +19,16 @@ pub struct Room {
 
     #[serde(default)]
     pub participants_id: Vec<ObjectId>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series_id: Option<ObjectId>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub occurrence_start: Option<bson::DateTime>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passcode: Option<String>,
+
+    #[serde(default)]
+    pub mode: RoomMode,
 }
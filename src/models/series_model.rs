@@ -0,0 +1,256 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc, Weekday};
+use mongodb::bson::{self, oid::ObjectId};
+use serde::{Deserialize, Serialize};
+
+// Participants may enter an occurrence this long before it is scheduled to start.
+pub const EARLY_JOIN_MINUTES: i64 = 15;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum RecurrenceRule {
+    Daily {
+        interval: u32,
+    },
+    Weekly {
+        interval: u32,
+        weekdays: Vec<Weekday>,
+    },
+    Custom {
+        starts: Vec<bson::DateTime>,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ExceptionAction {
+    Skip,
+    Move { start: bson::DateTime },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SeriesException {
+    pub occurrence_start: bson::DateTime,
+    pub action: ExceptionAction,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Series {
+    #[serde(rename = "_id", skip_serializing_if = "Option::is_none")]
+    pub _id: Option<ObjectId>,
+
+    pub host_id: ObjectId,
+    pub code: String,
+    pub title: String,
+    pub rule: RecurrenceRule,
+    pub starts_at: bson::DateTime,
+    pub duration_minutes: u32,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<bson::DateTime>,
+
+    #[serde(default)]
+    pub exceptions: Vec<SeriesException>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passcode: Option<String>,
+}
+
+impl RecurrenceRule {
+    pub fn is_valid(&self) -> bool {
+        match self {
+            RecurrenceRule::Daily { interval } => *interval > 0,
+            RecurrenceRule::Weekly { interval, weekdays } => *interval > 0 && !weekdays.is_empty(),
+            RecurrenceRule::Custom { starts } => !starts.is_empty(),
+        }
+    }
+
+    fn matches_day(&self, day: NaiveDate, first_day: NaiveDate) -> bool {
+        match self {
+            RecurrenceRule::Daily { interval } => {
+                (day - first_day).num_days() % i64::from(*interval) == 0
+            }
+            RecurrenceRule::Weekly { interval, weekdays } => {
+                let first_monday =
+                    first_day - Duration::days(first_day.weekday().num_days_from_monday().into());
+                let week = (day - first_monday).num_days() / 7;
+                weekdays.contains(&day.weekday()) && week % i64::from(*interval) == 0
+            }
+            RecurrenceRule::Custom { .. } => false,
+        }
+    }
+}
+
+impl Series {
+    /// Start of the occurrence that can be joined at `now`, if any.
+    pub fn active_occurrence(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let early = Duration::minutes(EARLY_JOIN_MINUTES);
+        let length = Duration::minutes(self.duration_minutes.into());
+
+        self.occurrences_between(now - length, now + early)
+            .into_iter()
+            .filter(|start| *start - early <= now && now < *start + length)
+            .max()
+    }
+
+    /// All occurrence starts in `[from, to]` after skips and moves are applied.
+    pub fn occurrences_between(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Vec<DateTime<Utc>> {
+        let mut starts: Vec<DateTime<Utc>> = self
+            .scheduled_between(from, to)
+            .into_iter()
+            .filter(|start| {
+                !self
+                    .exceptions
+                    .iter()
+                    .any(|exception| exception.occurrence_start.to_chrono() == *start)
+            })
+            .collect();
+
+        for exception in &self.exceptions {
+            if let ExceptionAction::Move { start } = exception.action {
+                let start = start.to_chrono();
+                if from <= start && start <= to {
+                    starts.push(start);
+                }
+            }
+        }
+
+        starts.sort();
+        starts.dedup();
+        starts
+    }
+
+    /// Whether the rule itself (ignoring exceptions) produces an occurrence at `start`.
+    pub fn is_scheduled(&self, start: DateTime<Utc>) -> bool {
+        self.scheduled_between(start, start).contains(&start)
+    }
+
+    fn scheduled_between(&self, from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<DateTime<Utc>> {
+        let first = self.starts_at.to_chrono();
+        let until = self.until.map(|until| until.to_chrono());
+        let in_range = |start: &DateTime<Utc>| {
+            from <= *start && *start <= to && until.is_none_or(|until| *start <= until)
+        };
+
+        if let RecurrenceRule::Custom { starts } = &self.rule {
+            return starts
+                .iter()
+                .map(|start| start.to_chrono())
+                .filter(in_range)
+                .collect();
+        }
+
+        let mut starts = vec![];
+        let mut day = from.date_naive().max(first.date_naive());
+        while day <= to.date_naive() {
+            let start = day.and_time(first.time()).and_utc();
+            if in_range(&start) && self.rule.matches_day(day, first.date_naive()) {
+                starts.push(start);
+            }
+            day = match day.succ_opt() {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        starts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 1, day, hour, minute, 0).unwrap()
+    }
+
+    // Monday 5 January 2026, 09:00 for 60 minutes.
+    fn series(rule: RecurrenceRule) -> Series {
+        Series {
+            _id: None,
+            host_id: ObjectId::new(),
+            code: "123456".to_string(),
+            title: "Standup".to_string(),
+            rule,
+            starts_at: bson::DateTime::from_chrono(at(5, 9, 0)),
+            duration_minutes: 60,
+            until: None,
+            exceptions: vec![],
+            passcode: None,
+        }
+    }
+
+    fn days(starts: Vec<DateTime<Utc>>) -> Vec<u32> {
+        starts.iter().map(|start| start.day()).collect()
+    }
+
+    #[test]
+    fn daily_series_honor_the_interval() {
+        let series = series(RecurrenceRule::Daily { interval: 2 });
+
+        let starts = series.occurrences_between(at(1, 0, 0), at(12, 23, 59));
+        assert_eq!(days(starts), vec![5, 7, 9, 11]);
+        assert!(series.is_scheduled(at(7, 9, 0)));
+        assert!(!series.is_scheduled(at(6, 9, 0)));
+        assert!(!series.is_scheduled(at(7, 10, 0)));
+    }
+
+    #[test]
+    fn weekly_series_skip_off_weeks() {
+        let series = series(RecurrenceRule::Weekly {
+            interval: 2,
+            weekdays: vec![Weekday::Mon, Weekday::Thu],
+        });
+
+        let starts = series.occurrences_between(at(1, 0, 0), at(31, 23, 59));
+        assert_eq!(days(starts), vec![5, 8, 19, 22]);
+    }
+
+    #[test]
+    fn exceptions_skip_and_move_occurrences() {
+        let mut series = series(RecurrenceRule::Daily { interval: 1 });
+        series.exceptions = vec![
+            SeriesException {
+                occurrence_start: bson::DateTime::from_chrono(at(6, 9, 0)),
+                action: ExceptionAction::Skip,
+            },
+            SeriesException {
+                occurrence_start: bson::DateTime::from_chrono(at(7, 9, 0)),
+                action: ExceptionAction::Move {
+                    start: bson::DateTime::from_chrono(at(7, 14, 0)),
+                },
+            },
+        ];
+
+        let starts = series.occurrences_between(at(5, 0, 0), at(8, 23, 59));
+        assert_eq!(starts, vec![at(5, 9, 0), at(7, 14, 0), at(8, 9, 0)]);
+        assert_eq!(series.active_occurrence(at(7, 9, 30)), None);
+        assert_eq!(series.active_occurrence(at(7, 14, 30)), Some(at(7, 14, 0)));
+    }
+
+    #[test]
+    fn occurrences_stop_at_until() {
+        let mut series = series(RecurrenceRule::Daily { interval: 1 });
+        series.until = Some(bson::DateTime::from_chrono(at(7, 9, 0)));
+
+        let starts = series.occurrences_between(at(1, 0, 0), at(31, 23, 59));
+        assert_eq!(days(starts), vec![5, 6, 7]);
+        assert_eq!(series.active_occurrence(at(8, 9, 30)), None);
+    }
+
+    #[test]
+    fn occurrences_open_early_and_close_after_their_length() {
+        let series = series(RecurrenceRule::Daily { interval: 1 });
+
+        assert_eq!(series.active_occurrence(at(6, 8, 44)), None);
+        assert_eq!(series.active_occurrence(at(6, 8, 45)), Some(at(6, 9, 0)));
+        assert_eq!(series.active_occurrence(at(6, 9, 59)), Some(at(6, 9, 0)));
+        assert_eq!(series.active_occurrence(at(6, 10, 0)), None);
+        assert_eq!(series.active_occurrence(at(4, 8, 50)), None);
+    }
+}
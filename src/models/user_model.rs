@@ -1,6 +1,8 @@
 use mongodb::bson::oid::ObjectId;
 use serde::{Serialize, Deserialize};
 
+use crate::{error::AppError, utils::validation::{normalize_email, validate_username, PasswordPolicy}};
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct User {
     #[serde(rename = "_id", skip_serializing_if = "Option::is_none")] 
@@ -10,6 +12,14 @@ pub struct User {
     pub email: String,
     pub password: String,
 
+    #[serde(default)]
+    pub verified: bool,
+
+    // Bumped by logging out everywhere; access tokens carrying an older
+    // version are rejected.
+    #[serde(default)]
+    pub token_version: u32,
+
     // #[serde(skip_serializing_if = "Option::is_none")] 
     // pub refresh_token: Option<String>,
 }
@@ -21,6 +31,27 @@ pub struct RegisterUser {
     pub password: String
 }
 
+// A registration that passed validation: the username is trimmed and the
+// email normalized.
+#[derive(Debug)]
+pub struct NewUser {
+    pub username: String,
+    pub email: String,
+    pub password: String,
+}
+
+impl RegisterUser {
+    pub fn validate(self, policy: &PasswordPolicy) -> Result<NewUser, AppError> {
+        let invalid = |message: &str| AppError::Validation(message.to_string());
+
+        let username = validate_username(&self.username).map_err(invalid)?;
+        let email = normalize_email(&self.email).map_err(invalid)?;
+        policy.check(&self.password, &username, &email).map_err(invalid)?;
+
+        Ok(NewUser { username, email, password: self.password })
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct LoginUser {
     pub email: String,
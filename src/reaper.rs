@@ -0,0 +1,106 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use mongodb::bson::oid::ObjectId;
+use tokio::time::{self, Instant, MissedTickBehavior};
+use tracing::{error, info};
+
+use crate::{
+    config::Config,
+    db::connection::Database,
+    sfu::Sfu,
+    ws::{AppState, forget_room, stop_recording},
+};
+
+// Closes rooms whose members have all been disconnected for longer than
+// `room_idle_timeout`. Idle time is tracked in memory, so after a restart every
+// room gets a full timeout before it can be reaped.
+pub async fn run(db: Arc<Database>, ws_state: Arc<AppState>, sfu: Arc<Sfu>, config: Arc<Config>) {
+    let mut idle_since: HashMap<ObjectId, Instant> = HashMap::new();
+    let mut interval = time::interval(config.reaper_interval);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        interval.tick().await;
+
+        let rooms = match Database::get_all_rooms(db.clone()).await {
+            Ok(rooms) => rooms,
+            Err(err) => {
+                error!(error = %err, "Reaper failed to load rooms");
+                continue;
+            }
+        };
+
+        let mut connected: HashSet<ObjectId> = {
+            let user_sockets = ws_state.user_sockets.lock().await;
+            let sockets = ws_state.sockets.lock().await;
+            user_sockets
+                .iter()
+                .filter(|(_, socket_id)| sockets.contains_key(socket_id))
+                .map(|(user_id, _)| *user_id)
+                .collect()
+        };
+
+        // Members connected to other instances keep the room open too. If the
+        // broker cannot tell, skip this round rather than reap live rooms.
+        let members: Vec<ObjectId> = rooms
+            .iter()
+            .flat_map(|room| std::iter::once(room.host_id).chain(room.participants_id.clone()))
+            .filter(|user_id| !connected.contains(user_id))
+            .collect();
+        match ws_state.broker.online(&members).await {
+            Ok(online) => connected.extend(online),
+            Err(err) => {
+                error!(error = %err, "Reaper failed to look up presence");
+                continue;
+            }
+        }
+
+        let now = Instant::now();
+        let mut seen = HashSet::with_capacity(rooms.len());
+
+        for room in rooms {
+            let Some(room_id) = room._id else {
+                continue;
+            };
+            seen.insert(room_id);
+
+            let occupied = connected.contains(&room.host_id)
+                || room.participants_id.iter().any(|id| connected.contains(id));
+            if occupied {
+                idle_since.remove(&room_id);
+                continue;
+            }
+
+            let since = *idle_since.entry(room_id).or_insert(now);
+            if now.duration_since(since) < config.room_idle_timeout {
+                continue;
+            }
+
+            if config.reaper_dry_run {
+                info!(room = %room.code, "Reaper would close idle room");
+                continue;
+            }
+
+            if let Err(err) = Database::delete_room_by_id(db.clone(), room_id).await {
+                error!(room = %room.code, error = %err, "Reaper failed to close room");
+                continue;
+            }
+            if let Err(err) = Database::finish_participants(db.clone(), &room.code).await {
+                error!(room = %room.code, error = %err, "Reaper failed to finish participants");
+            }
+            stop_recording(&ws_state, &sfu, &room).await;
+            sfu.close_room(&ws_state, &room.code).await;
+
+            forget_room(&ws_state, &room.code).await;
+            idle_since.remove(&room_id);
+            ws_state.metrics.rooms_reaped.inc();
+            let total = ws_state.metrics.rooms_reaped.get();
+            info!(room = %room.code, total, "Reaper closed idle room");
+        }
+
+        idle_since.retain(|room_id, _| seen.contains(room_id));
+    }
+}
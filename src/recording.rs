@@ -0,0 +1,189 @@
+mod webm;
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Instant,
+};
+
+use mongodb::bson::oid::ObjectId;
+use tokio::{
+    fs::{self, File},
+    io::{AsyncWriteExt, BufWriter},
+};
+use webrtc::{
+    media::{Sample, io::sample_builder::SampleBuilder},
+    rtp::{
+        codecs::{opus::OpusPacket, vp8::Vp8Packet},
+        packet::Packet,
+    },
+    track::track_remote::TrackRemote,
+};
+
+use crate::models::recording_model::RecordingTrack;
+
+use self::webm::{Codec, Muxer, vp8_keyframe_size};
+
+const MAX_LATE_PACKETS: u16 = 128;
+
+pub fn track_path(base_dir: &Path, recording_id: ObjectId, file_name: &str) -> PathBuf {
+    base_dir.join(recording_id.to_hex()).join(file_name)
+}
+
+// A recording in progress. Every published track gets its own WebM file, all
+// timed from the moment the recording started so they can be lined up later.
+pub struct ActiveRecording {
+    pub id: ObjectId,
+    dir: PathBuf,
+    started: Instant,
+    tracks: Mutex<Vec<RecordingTrack>>,
+}
+
+impl ActiveRecording {
+    pub async fn create(base_dir: &Path, id: ObjectId) -> io::Result<Self> {
+        let dir = base_dir.join(id.to_hex());
+        fs::create_dir_all(&dir).await?;
+
+        Ok(ActiveRecording {
+            id,
+            dir,
+            started: Instant::now(),
+            tracks: Mutex::new(vec![]),
+        })
+    }
+
+    pub fn tracks(&self) -> Vec<RecordingTrack> {
+        self.tracks.lock().unwrap().clone()
+    }
+
+    // Returns `None` for codecs the muxer cannot write.
+    pub async fn track_writer(
+        &self,
+        user_id: ObjectId,
+        remote: &TrackRemote,
+    ) -> io::Result<Option<TrackWriter>> {
+        let codec_params = remote.codec();
+        let Some(codec) = Codec::from_mime_type(&codec_params.capability.mime_type) else {
+            return Ok(None);
+        };
+
+        let file_name = {
+            let mut tracks = self.tracks.lock().unwrap();
+            let file_name = format!(
+                "{}-{}-{}.webm",
+                tracks.len(),
+                user_id.to_hex(),
+                codec.kind()
+            );
+            tracks.push(RecordingTrack {
+                file_name: file_name.clone(),
+                user_id,
+                kind: codec.kind().to_string(),
+                codec: codec.name().to_string(),
+            });
+            file_name
+        };
+
+        let file = File::create(self.dir.join(file_name)).await?;
+        let clock_rate = codec_params.capability.clock_rate;
+        let builder = match codec {
+            Codec::Vp8 => Depacketizer::Vp8(SampleBuilder::new(
+                MAX_LATE_PACKETS,
+                Vp8Packet::default(),
+                clock_rate,
+            )),
+            Codec::Opus => {
+                Depacketizer::Opus(SampleBuilder::new(MAX_LATE_PACKETS, OpusPacket, clock_rate))
+            }
+        };
+
+        Ok(Some(TrackWriter {
+            file: BufWriter::new(file),
+            muxer: Muxer::new(codec),
+            codec,
+            builder,
+            clock_rate: clock_rate.max(1) as u64,
+            offset_ms: self.started.elapsed().as_millis() as u64,
+            last_timestamp: None,
+            ticks: 0,
+            started: false,
+        }))
+    }
+}
+
+enum Depacketizer {
+    Vp8(SampleBuilder<Vp8Packet>),
+    Opus(SampleBuilder<OpusPacket>),
+}
+
+impl Depacketizer {
+    fn push(&mut self, packet: Packet) {
+        match self {
+            Depacketizer::Vp8(builder) => builder.push(packet),
+            Depacketizer::Opus(builder) => builder.push(packet),
+        }
+    }
+
+    fn pop(&mut self) -> Option<Sample> {
+        match self {
+            Depacketizer::Vp8(builder) => builder.pop(),
+            Depacketizer::Opus(builder) => builder.pop(),
+        }
+    }
+}
+
+pub struct TrackWriter {
+    file: BufWriter<File>,
+    muxer: Muxer,
+    codec: Codec,
+    builder: Depacketizer,
+    clock_rate: u64,
+    offset_ms: u64,
+    last_timestamp: Option<u32>,
+    ticks: i64,
+    started: bool,
+}
+
+impl TrackWriter {
+    pub async fn write_rtp(&mut self, packet: &Packet) -> io::Result<()> {
+        self.builder.push(packet.clone());
+
+        while let Some(sample) = self.builder.pop() {
+            let (keyframe, size) = match self.codec {
+                Codec::Vp8 => match vp8_keyframe_size(&sample.data) {
+                    Some(size) => (true, size),
+                    None => (false, (0, 0)),
+                },
+                Codec::Opus => (true, (0, 0)),
+            };
+
+            // Video can only be decoded from a keyframe, which also carries
+            // the frame size the header needs.
+            if !self.started {
+                if !keyframe {
+                    continue;
+                }
+                self.file.write_all(&self.muxer.header(size)).await?;
+                self.started = true;
+            }
+
+            if let Some(last) = self.last_timestamp {
+                self.ticks += sample.packet_timestamp.wrapping_sub(last) as i32 as i64;
+            }
+            self.last_timestamp = Some(sample.packet_timestamp);
+
+            let elapsed_ms = self.ticks.max(0) as u64 * 1000 / self.clock_rate;
+            let block = self
+                .muxer
+                .block(self.offset_ms + elapsed_ms, keyframe, &sample.data);
+            self.file.write_all(&block).await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn finish(mut self) -> io::Result<()> {
+        self.file.flush().await
+    }
+}
@@ -0,0 +1,246 @@
+// Minimal live-style WebM muxer: one track per file, unknown-size segment and
+// clusters, so nothing has to be rewritten once a recording stops.
+
+const EBML: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const SEEK_PRE_ROLL: u32 = 0x56BB;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+
+const CLUSTER: u32 = 0x1F43_B675;
+const CLUSTER_TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+
+const AUDIO_CLUSTER_MS: u64 = 5_000;
+const KEYFRAME_CLUSTER_MS: u64 = 1_000;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Vp8,
+    Opus,
+}
+
+impl Codec {
+    pub fn from_mime_type(mime_type: &str) -> Option<Self> {
+        match mime_type.to_ascii_lowercase().as_str() {
+            "video/vp8" => Some(Codec::Vp8),
+            "audio/opus" => Some(Codec::Opus),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Vp8 => "vp8",
+            Codec::Opus => "opus",
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Codec::Vp8 => "video",
+            Codec::Opus => "audio",
+        }
+    }
+
+    fn codec_id(&self) -> &'static str {
+        match self {
+            Codec::Vp8 => "V_VP8",
+            Codec::Opus => "A_OPUS",
+        }
+    }
+}
+
+// Returns the frame size of a VP8 keyframe, or `None` for interframes.
+pub fn vp8_keyframe_size(frame: &[u8]) -> Option<(u16, u16)> {
+    if frame.len() < 10 || frame[0] & 0x01 != 0 || frame[3..6] != [0x9D, 0x01, 0x2A] {
+        return None;
+    }
+
+    let width = u16::from_le_bytes([frame[6], frame[7]]) & 0x3FFF;
+    let height = u16::from_le_bytes([frame[8], frame[9]]) & 0x3FFF;
+    Some((width, height))
+}
+
+pub struct Muxer {
+    codec: Codec,
+    cluster_start: Option<u64>,
+}
+
+impl Muxer {
+    pub fn new(codec: Codec) -> Self {
+        Muxer {
+            codec,
+            cluster_start: None,
+        }
+    }
+
+    // `size` is the video frame size and is ignored for audio tracks.
+    pub fn header(&self, size: (u16, u16)) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        element(
+            &mut out,
+            EBML,
+            &[
+                uint_element(EBML_VERSION, 1),
+                uint_element(EBML_READ_VERSION, 1),
+                uint_element(EBML_MAX_ID_LENGTH, 4),
+                uint_element(EBML_MAX_SIZE_LENGTH, 8),
+                bytes_element(DOC_TYPE, b"webm"),
+                uint_element(DOC_TYPE_VERSION, 4),
+                uint_element(DOC_TYPE_READ_VERSION, 2),
+            ]
+            .concat(),
+        );
+
+        write_id(&mut out, SEGMENT);
+        out.extend_from_slice(&UNKNOWN_SIZE);
+
+        element(
+            &mut out,
+            INFO,
+            &[
+                uint_element(TIMESTAMP_SCALE, 1_000_000),
+                bytes_element(MUXING_APP, b"telesync"),
+                bytes_element(WRITING_APP, b"telesync"),
+            ]
+            .concat(),
+        );
+
+        let mut entry = [
+            uint_element(TRACK_NUMBER, 1),
+            uint_element(TRACK_UID, 1),
+            bytes_element(CODEC_ID, self.codec.codec_id().as_bytes()),
+        ]
+        .concat();
+        match self.codec {
+            Codec::Vp8 => {
+                entry.extend(uint_element(TRACK_TYPE, 1));
+                entry.extend(bytes_element(
+                    VIDEO,
+                    &[
+                        uint_element(PIXEL_WIDTH, size.0.into()),
+                        uint_element(PIXEL_HEIGHT, size.1.into()),
+                    ]
+                    .concat(),
+                ));
+            }
+            Codec::Opus => {
+                entry.extend(uint_element(TRACK_TYPE, 2));
+                entry.extend(bytes_element(CODEC_PRIVATE, &opus_head()));
+                entry.extend(uint_element(SEEK_PRE_ROLL, 80_000_000));
+                entry.extend(bytes_element(
+                    AUDIO,
+                    &[
+                        bytes_element(SAMPLING_FREQUENCY, &48_000f64.to_be_bytes()),
+                        uint_element(CHANNELS, 2),
+                    ]
+                    .concat(),
+                ));
+            }
+        }
+        element(&mut out, TRACKS, &bytes_element(TRACK_ENTRY, &entry));
+
+        out
+    }
+
+    // Opens a new cluster when the relative timestamp would overflow, and on
+    // video keyframes or every few seconds of audio so players can seek.
+    pub fn block(&mut self, timestamp_ms: u64, keyframe: bool, frame: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        let cluster_start = match self.cluster_start {
+            Some(start)
+                if timestamp_ms >= start
+                    && timestamp_ms - start <= i16::MAX as u64
+                    && !(keyframe && timestamp_ms - start >= KEYFRAME_CLUSTER_MS)
+                    && !(self.codec == Codec::Opus && timestamp_ms - start >= AUDIO_CLUSTER_MS) =>
+            {
+                start
+            }
+            _ => {
+                write_id(&mut out, CLUSTER);
+                out.extend_from_slice(&UNKNOWN_SIZE);
+                out.extend(uint_element(CLUSTER_TIMESTAMP, timestamp_ms));
+                self.cluster_start = Some(timestamp_ms);
+                timestamp_ms
+            }
+        };
+
+        let relative = (timestamp_ms - cluster_start) as i16;
+        let mut block = Vec::with_capacity(frame.len() + 4);
+        block.push(0x81);
+        block.extend_from_slice(&relative.to_be_bytes());
+        block.push(if keyframe { 0x80 } else { 0x00 });
+        block.extend_from_slice(frame);
+        element(&mut out, SIMPLE_BLOCK, &block);
+
+        out
+    }
+}
+
+fn opus_head() -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1);
+    head.push(2);
+    head.extend_from_slice(&0u16.to_le_bytes());
+    head.extend_from_slice(&48_000u32.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes());
+    head.push(0);
+    head
+}
+
+fn write_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|byte| **byte == 0).count();
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+// Sizes are always written as 8-byte varints; a few wasted bytes per element
+// keep the encoder trivial.
+fn element(out: &mut Vec<u8>, id: u32, payload: &[u8]) {
+    write_id(out, id);
+    let mut size = (payload.len() as u64).to_be_bytes();
+    size[0] = 0x01;
+    out.extend_from_slice(&size);
+    out.extend_from_slice(payload);
+}
+
+fn bytes_element(id: u32, payload: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    element(&mut out, id, payload);
+    out
+}
+
+fn uint_element(id: u32, value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|byte| **byte == 0).count().min(7);
+    bytes_element(id, &bytes[skip..])
+}
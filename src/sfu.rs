@@ -0,0 +1,613 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Weak,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use dashmap::{DashMap, mapref::entry::Entry};
+use mongodb::bson::oid::ObjectId;
+use serde_json::json;
+use tokio::sync::{Mutex, watch};
+use tracing::{error, info, warn};
+use webrtc::{
+    api::{
+        API, APIBuilder, interceptor_registry::register_default_interceptors,
+        media_engine::MediaEngine,
+    },
+    error::Error,
+    ice_transport::{
+        ice_candidate::{RTCIceCandidate, RTCIceCandidateInit},
+        ice_server::RTCIceServer,
+    },
+    interceptor::registry::Registry,
+    peer_connection::{
+        RTCPeerConnection, configuration::RTCConfiguration,
+        peer_connection_state::RTCPeerConnectionState,
+        sdp::session_description::RTCSessionDescription, signaling_state::RTCSignalingState,
+    },
+    rtcp::payload_feedbacks::picture_loss_indication::PictureLossIndication,
+    rtp_transceiver::{rtp_codec::RTPCodecType, rtp_sender::RTCRtpSender},
+    track::{
+        track_local::{TrackLocal, TrackLocalWriter, track_local_static_rtp::TrackLocalStaticRTP},
+        track_remote::TrackRemote,
+    },
+};
+
+use crate::{
+    config::Config,
+    db::connection::Database,
+    recording::{ActiveRecording, TrackWriter},
+    ws::{AppState, send_frame_to_user},
+};
+
+// Peer id used in `from`/`to` of signaling frames exchanged with the server's
+// own peer connection in SFU rooms.
+pub const SFU_PEER_ID: ObjectId = ObjectId::from_bytes([0; 12]);
+
+const PLI_INTERVAL: Duration = Duration::from_secs(3);
+
+struct Publication {
+    publisher: ObjectId,
+    key: String,
+    track: Arc<TrackLocalStaticRTP>,
+}
+
+struct SfuPeer {
+    user_id: ObjectId,
+    pc: Arc<RTCPeerConnection>,
+    senders: Mutex<HashMap<String, Arc<RTCRtpSender>>>,
+    negotiation: Mutex<()>,
+    renegotiate_pending: AtomicBool,
+}
+
+// Mesh rooms only use the server peer to record, so their tracks are not
+// forwarded to anyone.
+struct SfuRoom {
+    forward: bool,
+    peers: Mutex<HashMap<ObjectId, Arc<SfuPeer>>>,
+    publications: Mutex<Vec<Publication>>,
+    recording: watch::Sender<Option<Arc<ActiveRecording>>>,
+}
+
+impl SfuRoom {
+    fn new(forward: bool, recording: Option<Arc<ActiveRecording>>) -> Self {
+        SfuRoom {
+            forward,
+            peers: Mutex::new(HashMap::new()),
+            publications: Mutex::new(vec![]),
+            recording: watch::Sender::new(recording),
+        }
+    }
+}
+
+pub struct Sfu {
+    api: API,
+    db: Arc<Database>,
+    ice_servers: Vec<RTCIceServer>,
+    rooms: DashMap<String, Arc<SfuRoom>>,
+    recordings: DashMap<String, Arc<ActiveRecording>>,
+}
+
+impl Sfu {
+    pub fn new(config: &Config, db: Arc<Database>) -> Result<Self, Error> {
+        let mut media_engine = MediaEngine::default();
+        media_engine.register_default_codecs()?;
+        let registry = register_default_interceptors(Registry::new(), &mut media_engine)?;
+
+        let api = APIBuilder::new()
+            .with_media_engine(media_engine)
+            .with_interceptor_registry(registry)
+            .build();
+
+        let ice_servers = if config.stun_urls.is_empty() {
+            vec![]
+        } else {
+            vec![RTCIceServer {
+                urls: config.stun_urls.clone(),
+                ..Default::default()
+            }]
+        };
+
+        Ok(Sfu {
+            api,
+            db,
+            ice_servers,
+            rooms: DashMap::new(),
+            recordings: DashMap::new(),
+        })
+    }
+
+    pub async fn handle_signal(
+        self: &Arc<Self>,
+        ws_state: Arc<AppState>,
+        room_code: &str,
+        user_id: ObjectId,
+        message_type: &str,
+        item: serde_json::Value,
+        forward: bool,
+    ) -> Result<(), Error> {
+        let room = if message_type == "offer" {
+            self.offer_room(room_code, forward)
+        } else {
+            match self.rooms.get(room_code) {
+                Some(room) => room.clone(),
+                None => return Ok(()),
+            }
+        };
+
+        match message_type {
+            "offer" => {
+                let description: RTCSessionDescription =
+                    serde_json::from_value(item).map_err(|err| Error::new(err.to_string()))?;
+                let mut room = room;
+                let peer = loop {
+                    match self
+                        .get_or_create_peer(&ws_state, &room, room_code, user_id)
+                        .await?
+                    {
+                        Some(peer) => break peer,
+                        // Retired by `remove_peer` after it was looked up.
+                        None => room = self.offer_room(room_code, forward),
+                    }
+                };
+
+                {
+                    let _guard = peer.negotiation.lock().await;
+                    peer.pc.set_remote_description(description).await?;
+                    let answer = peer.pc.create_answer(None).await?;
+                    peer.pc.set_local_description(answer.clone()).await?;
+                    send_signal(&ws_state, user_id, "answer", json!(answer)).await;
+                }
+
+                subscribe_to_existing(&room, &peer).await?;
+                if peer.renegotiate_pending.load(Ordering::SeqCst) {
+                    renegotiate(&ws_state, &peer).await;
+                }
+            }
+            "answer" => {
+                let description: RTCSessionDescription =
+                    serde_json::from_value(item).map_err(|err| Error::new(err.to_string()))?;
+                let peer = room
+                    .peers
+                    .lock()
+                    .await
+                    .get(&user_id)
+                    .cloned()
+                    .ok_or(Error::ErrConnectionClosed)?;
+
+                {
+                    let _guard = peer.negotiation.lock().await;
+                    peer.pc.set_remote_description(description).await?;
+                }
+
+                if peer.renegotiate_pending.load(Ordering::SeqCst) {
+                    renegotiate(&ws_state, &peer).await;
+                }
+            }
+            "ice-candidate" => {
+                let candidate: RTCIceCandidateInit =
+                    serde_json::from_value(item).map_err(|err| Error::new(err.to_string()))?;
+                if candidate.candidate.is_empty() {
+                    return Ok(());
+                }
+
+                let peer = room.peers.lock().await.get(&user_id).cloned();
+                if let Some(peer) = peer {
+                    peer.pc.add_ice_candidate(candidate).await?;
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn recording(&self, room_code: &str) -> Option<Arc<ActiveRecording>> {
+        self.recordings
+            .get(room_code)
+            .map(|recording| recording.clone())
+    }
+
+    // Returns false if the room is already being recorded.
+    pub fn start_recording(&self, room_code: &str, recording: Arc<ActiveRecording>) -> bool {
+        match self.recordings.entry(room_code.to_owned()) {
+            Entry::Occupied(_) => return false,
+            Entry::Vacant(entry) => {
+                entry.insert(recording.clone());
+            }
+        }
+
+        if let Some(room) = self.rooms.get(room_code) {
+            room.recording.send_replace(Some(recording));
+        }
+        true
+    }
+
+    pub fn stop_recording(&self, room_code: &str) -> Option<Arc<ActiveRecording>> {
+        let (_, recording) = self.recordings.remove(room_code)?;
+
+        if let Some(room) = self.rooms.get(room_code) {
+            room.recording.send_replace(None);
+        }
+        Some(recording)
+    }
+
+    // Stops the room's recording and saves its tracks, returning `None` if the
+    // room was not being recorded.
+    pub async fn finish_recording(&self, room_code: &str) -> Option<Arc<ActiveRecording>> {
+        let recording = self.stop_recording(room_code)?;
+
+        if let Err(err) =
+            Database::finish_recording(self.db.clone(), recording.id, &recording.tracks()).await
+        {
+            error!(recording_id = %recording.id, error = %err, "Failed to save recording");
+        }
+        Some(recording)
+    }
+
+    pub async fn close_room(&self, ws_state: &AppState, room_code: &str) {
+        let Some(room) = self.rooms.get(room_code).map(|room| room.clone()) else {
+            return;
+        };
+
+        let peers: Vec<ObjectId> = room.peers.lock().await.keys().copied().collect();
+        for user_id in peers {
+            self.remove_peer(ws_state, room_code, user_id).await;
+        }
+    }
+
+    pub async fn remove_peer(&self, ws_state: &AppState, room_code: &str, user_id: ObjectId) {
+        let Some(room) = self.rooms.get(room_code).map(|room| room.clone()) else {
+            return;
+        };
+
+        let peer = room.peers.lock().await.remove(&user_id);
+        if let Some(peer) = peer
+            && let Err(err) = peer.pc.close().await
+        {
+            warn!(%user_id, error = %err, "Failed to close SFU peer");
+        }
+
+        let removed: Vec<String> = {
+            let mut publications = room.publications.lock().await;
+            let removed = publications
+                .iter()
+                .filter(|publication| publication.publisher == user_id)
+                .map(|publication| publication.key.clone())
+                .collect();
+            publications.retain(|publication| publication.publisher != user_id);
+            removed
+        };
+
+        unsubscribe(ws_state, &room, &removed).await;
+
+        // Holding the peers lock keeps `get_or_create_peer` from joining the
+        // room while it is retired; a newer room under the same code stays.
+        let retired = {
+            let peers = room.peers.lock().await;
+            peers.is_empty()
+                && self
+                    .rooms
+                    .remove_if(room_code, |_, current| Arc::ptr_eq(current, &room))
+                    .is_some()
+        };
+
+        // Nobody is left to record.
+        if retired && let Some(recording) = self.finish_recording(room_code).await {
+            info!(room = room_code, recording_id = %recording.id, "Recording stopped, room empty");
+        }
+    }
+
+    fn offer_room(&self, room_code: &str, forward: bool) -> Arc<SfuRoom> {
+        self.rooms
+            .entry(room_code.to_owned())
+            .or_insert_with(|| Arc::new(SfuRoom::new(forward, self.recording(room_code))))
+            .clone()
+    }
+
+    async fn get_or_create_peer(
+        self: &Arc<Self>,
+        ws_state: &Arc<AppState>,
+        room: &Arc<SfuRoom>,
+        room_code: &str,
+        user_id: ObjectId,
+    ) -> Result<Option<Arc<SfuPeer>>, Error> {
+        let mut peers = room.peers.lock().await;
+        if let Some(peer) = peers.get(&user_id) {
+            return Ok(Some(peer.clone()));
+        }
+
+        // Returns `None` if `remove_peer` retired the room in the meantime.
+        let current = self
+            .rooms
+            .get(room_code)
+            .is_some_and(|current| Arc::ptr_eq(&current, room));
+        if !current {
+            return Ok(None);
+        }
+
+        let configuration = RTCConfiguration {
+            ice_servers: self.ice_servers.clone(),
+            ..Default::default()
+        };
+        let pc = Arc::new(self.api.new_peer_connection(configuration).await?);
+
+        let ice_state = ws_state.clone();
+        pc.on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+            let ws_state = ice_state.clone();
+            Box::pin(async move {
+                if let Some(candidate) = candidate
+                    && let Ok(init) = candidate.to_json()
+                {
+                    send_signal(&ws_state, user_id, "ice-candidate", json!(init)).await;
+                }
+            })
+        }));
+
+        let track_state = ws_state.clone();
+        let track_room = Arc::downgrade(room);
+        let track_pc = Arc::downgrade(&pc);
+        pc.on_track(Box::new(move |track, _, _| {
+            let ws_state = track_state.clone();
+            let room = track_room.clone();
+            let pc = track_pc.clone();
+            Box::pin(async move {
+                if let Some(room) = room.upgrade() {
+                    tokio::spawn(publish(ws_state, room, pc, user_id, track));
+                }
+            })
+        }));
+
+        let sfu = Arc::downgrade(self);
+        let state_ws = ws_state.clone();
+        let code = room_code.to_owned();
+        pc.on_peer_connection_state_change(Box::new(move |state: RTCPeerConnectionState| {
+            let sfu = sfu.clone();
+            let ws_state = state_ws.clone();
+            let code = code.clone();
+            Box::pin(async move {
+                if state == RTCPeerConnectionState::Failed
+                    && let Some(sfu) = sfu.upgrade()
+                {
+                    sfu.remove_peer(&ws_state, &code, user_id).await;
+                }
+            })
+        }));
+
+        let peer = Arc::new(SfuPeer {
+            user_id,
+            pc,
+            senders: Mutex::new(HashMap::new()),
+            negotiation: Mutex::new(()),
+            renegotiate_pending: AtomicBool::new(false),
+        });
+        peers.insert(user_id, peer.clone());
+
+        Ok(Some(peer))
+    }
+}
+
+async fn send_signal(
+    ws_state: &AppState,
+    user_id: ObjectId,
+    message_type: &str,
+    item: serde_json::Value,
+) {
+    let response = json!({
+        "message_type": message_type,
+        "item": item,
+        "from": SFU_PEER_ID,
+        "user_id": user_id,
+    });
+    send_frame_to_user(ws_state, &user_id, &response).await;
+}
+
+// Sends a server offer once the client has no negotiation in flight; otherwise
+// the offer is deferred until the pending answer arrives.
+async fn renegotiate(ws_state: &AppState, peer: &SfuPeer) {
+    let _guard = peer.negotiation.lock().await;
+    if peer.pc.signaling_state() != RTCSignalingState::Stable {
+        peer.renegotiate_pending.store(true, Ordering::SeqCst);
+        return;
+    }
+    peer.renegotiate_pending.store(false, Ordering::SeqCst);
+
+    let offer = match peer.pc.create_offer(None).await {
+        Ok(offer) => offer,
+        Err(err) => {
+            warn!(user_id = %peer.user_id, error = %err, "Failed to create SFU offer");
+            return;
+        }
+    };
+    if let Err(err) = peer.pc.set_local_description(offer.clone()).await {
+        warn!(user_id = %peer.user_id, error = %err, "Failed to apply SFU offer");
+        return;
+    }
+
+    send_signal(ws_state, peer.user_id, "offer", json!(offer)).await;
+}
+
+async fn add_subscription(
+    peer: &SfuPeer,
+    key: &str,
+    track: &Arc<TrackLocalStaticRTP>,
+) -> Result<(), Error> {
+    let mut senders = peer.senders.lock().await;
+    if senders.contains_key(key) {
+        return Ok(());
+    }
+
+    let sender = peer
+        .pc
+        .add_track(Arc::clone(track) as Arc<dyn TrackLocal + Send + Sync>)
+        .await?;
+
+    // RTCP has to be drained for the interceptors (NACK, reports) to run.
+    let rtcp_sender = sender.clone();
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 1500];
+        while rtcp_sender.read(&mut buf).await.is_ok() {}
+    });
+
+    senders.insert(key.to_owned(), sender);
+    peer.renegotiate_pending.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+async fn subscribe_to_existing(room: &SfuRoom, peer: &SfuPeer) -> Result<(), Error> {
+    if !room.forward {
+        return Ok(());
+    }
+
+    let publications: Vec<(String, Arc<TrackLocalStaticRTP>)> = room
+        .publications
+        .lock()
+        .await
+        .iter()
+        .filter(|publication| publication.publisher != peer.user_id)
+        .map(|publication| (publication.key.clone(), publication.track.clone()))
+        .collect();
+
+    for (key, track) in publications {
+        add_subscription(peer, &key, &track).await?;
+    }
+
+    Ok(())
+}
+
+async fn unsubscribe(ws_state: &AppState, room: &SfuRoom, keys: &[String]) {
+    if keys.is_empty() {
+        return;
+    }
+
+    let peers: Vec<Arc<SfuPeer>> = room.peers.lock().await.values().cloned().collect();
+    for peer in peers {
+        let mut changed = false;
+        {
+            let mut senders = peer.senders.lock().await;
+            for key in keys {
+                if let Some(sender) = senders.remove(key) {
+                    if let Err(err) = peer.pc.remove_track(&sender).await {
+                        warn!(user_id = %peer.user_id, error = %err, "Failed to remove SFU track");
+                    }
+                    changed = true;
+                }
+            }
+        }
+
+        if changed {
+            renegotiate(ws_state, &peer).await;
+        }
+    }
+}
+
+async fn publish(
+    ws_state: Arc<AppState>,
+    room: Arc<SfuRoom>,
+    pc: Weak<RTCPeerConnection>,
+    publisher: ObjectId,
+    remote: Arc<TrackRemote>,
+) {
+    // Subscribers find the owner of a forwarded track through its stream id.
+    let key = format!("{}:{}", publisher.to_hex(), remote.id());
+    let local = Arc::new(TrackLocalStaticRTP::new(
+        remote.codec().capability,
+        remote.id(),
+        publisher.to_hex(),
+    ));
+
+    room.publications.lock().await.push(Publication {
+        publisher,
+        key: key.clone(),
+        track: local.clone(),
+    });
+
+    let peers: Vec<Arc<SfuPeer>> = room
+        .peers
+        .lock()
+        .await
+        .values()
+        .filter(|peer| room.forward && peer.user_id != publisher)
+        .cloned()
+        .collect();
+    for peer in peers {
+        match add_subscription(&peer, &key, &local).await {
+            Ok(()) => renegotiate(&ws_state, &peer).await,
+            Err(err) => warn!(user_id = %peer.user_id, error = %err, "Failed to forward track"),
+        }
+    }
+
+    // Periodic keyframe requests let late subscribers start decoding quickly.
+    if remote.kind() == RTPCodecType::Video {
+        let media_ssrc = remote.ssrc();
+        let pli_pc = pc.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(PLI_INTERVAL);
+            loop {
+                interval.tick().await;
+                let Some(pc) = pli_pc.upgrade() else {
+                    break;
+                };
+                let pli = PictureLossIndication {
+                    sender_ssrc: 0,
+                    media_ssrc,
+                };
+                if pc.write_rtcp(&[Box::new(pli)]).await.is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let mut recording = room.recording.subscribe();
+    recording.mark_changed();
+    let mut writer: Option<TrackWriter> = None;
+
+    while let Ok((packet, _)) = remote.read_rtp().await {
+        if recording.has_changed().unwrap_or(false) {
+            finish_writer(writer.take(), publisher).await;
+            let active = recording.borrow_and_update().clone();
+            if let Some(active) = active {
+                writer = match active.track_writer(publisher, &remote).await {
+                    Ok(writer) => writer,
+                    Err(err) => {
+                        error!(user_id = %publisher, error = %err, "Failed to record track");
+                        None
+                    }
+                };
+            }
+        }
+
+        if let Some(track_writer) = writer.as_mut()
+            && let Err(err) = track_writer.write_rtp(&packet).await
+        {
+            error!(user_id = %publisher, error = %err, "Failed to write recording");
+            writer = None;
+        }
+
+        if let Err(err) = local.write_rtp(&packet).await
+            && err != Error::ErrClosedPipe
+        {
+            break;
+        }
+    }
+
+    finish_writer(writer, publisher).await;
+
+    room.publications
+        .lock()
+        .await
+        .retain(|publication| publication.key != key);
+    unsubscribe(&ws_state, &room, &[key]).await;
+}
+
+async fn finish_writer(writer: Option<TrackWriter>, publisher: ObjectId) {
+    if let Some(writer) = writer
+        && let Err(err) = writer.finish().await
+    {
+        error!(user_id = %publisher, error = %err, "Failed to finish recording");
+    }
+}
@@ -0,0 +1,86 @@
+#[cfg(feature = "otel")]
+mod otel;
+
+use axum::{body::Body, extract::MatchedPath, http::Request};
+use serde_json::Value;
+use tracing::{Span, info_span};
+use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::config::Config;
+
+// Without the `otel` feature nothing is exported and client trace context is
+// ignored; these stand in so callers do not need their own `cfg`s.
+#[cfg(not(feature = "otel"))]
+mod otel {
+    use axum::http::HeaderMap;
+    use serde_json::Value;
+    use tracing::Span;
+    use tracing_subscriber::layer::Identity;
+
+    use crate::config::Config;
+
+    pub struct Guard;
+
+    pub fn install(_config: &Config) -> (Identity, Guard) {
+        (Identity::new(), Guard)
+    }
+
+    pub fn continue_from_headers(_span: &Span, _headers: &HeaderMap) {}
+
+    pub fn continue_from_frame(_span: &Span, _frame: &Value) {}
+}
+
+pub use self::otel::Guard;
+
+// Log lines carry ids, room codes and message types, never request bodies or
+// headers, so access tokens, passcodes and invite links stay out of the logs.
+// Spans are exported over OTLP as well when the `otel` feature is built and
+// `OTEL_EXPORTER_OTLP_ENDPOINT` is set. Keep the guard alive until shutdown so
+// buffered spans are flushed.
+pub fn init(config: &Config) -> Guard {
+    let filter = EnvFilter::try_new(&config.log_level)
+        .unwrap_or_else(|err| panic!("❌ LOG_LEVEL in .env is not valid: {}", err));
+    let (exporter, guard) = otel::install(config);
+    let registry = tracing_subscriber::registry().with(filter).with(exporter);
+
+    if config.log_json {
+        registry
+            .with(
+                fmt::layer()
+                    .json()
+                    .with_current_span(true)
+                    .with_span_list(true),
+            )
+            .init();
+    } else {
+        registry.with(fmt::layer()).init();
+    }
+
+    #[cfg(not(feature = "otel"))]
+    if config.otlp_endpoint.is_some() {
+        tracing::warn!(
+            "OTEL_EXPORTER_OTLP_ENDPOINT is set but the server was built without the otel feature"
+        );
+    }
+
+    guard
+}
+
+// Only the method and route template are recorded: query strings and headers
+// can carry tokens. A `traceparent` header puts the span in the client's trace.
+pub fn http_span(request: &Request<Body>) -> Span {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(MatchedPath::as_str)
+        .unwrap_or("unmatched");
+    let span = info_span!("http", method = %request.method(), route);
+    otel::continue_from_headers(&span, request.headers());
+    span
+}
+
+// Clients that want a WebSocket message in their trace send `traceparent`, and
+// optionally `tracestate`, as top-level fields of the frame.
+pub fn continue_frame_trace(span: &Span, frame: &Value) {
+    otel::continue_from_frame(span, frame);
+}
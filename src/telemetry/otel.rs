@@ -0,0 +1,281 @@
+use axum::http::HeaderMap;
+use opentelemetry::{
+    Context,
+    propagation::{Extractor, TextMapPropagator},
+    trace::{TraceContextExt, TracerProvider as _},
+};
+use opentelemetry_otlp::SpanExporter;
+use opentelemetry_sdk::{
+    Resource,
+    propagation::TraceContextPropagator,
+    trace::{SdkTracerProvider, Tracer},
+};
+use serde_json::Value;
+use tracing::{Span, Subscriber, warn};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::config::Config;
+
+// Shuts the provider down on drop, exporting whatever is still batched.
+pub struct Guard(Option<SdkTracerProvider>);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.0.take()
+            && let Err(err) = provider.shutdown()
+        {
+            warn!(error = %err, "Failed to flush spans");
+        }
+    }
+}
+
+// The exporter reads the endpoint, headers and timeout from the standard
+// `OTEL_EXPORTER_OTLP_*` variables itself.
+pub fn install<S>(config: &Config) -> (Option<OpenTelemetryLayer<S, Tracer>>, Guard)
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    if config.otlp_endpoint.is_none() {
+        return (None, Guard(None));
+    }
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .build()
+        .unwrap_or_else(|err| panic!("❌ Failed to set up the OTLP exporter: {}", err));
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .build();
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("telesync"));
+
+    (Some(layer), Guard(Some(provider)))
+}
+
+struct HeaderCarrier<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderCarrier<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+struct FrameCarrier<'a>(&'a Value);
+
+impl Extractor for FrameCarrier<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(Value::as_str)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0
+            .as_object()
+            .map(|fields| fields.keys().map(String::as_str).collect())
+            .unwrap_or_default()
+    }
+}
+
+// A missing or malformed `traceparent` leaves the span under its local parent.
+fn continue_trace(span: &Span, carrier: &dyn Extractor) {
+    let context = TraceContextPropagator::new().extract_with_context(&Context::new(), carrier);
+    if context.span().span_context().is_valid() {
+        let _ = span.set_parent(context);
+    }
+}
+
+pub fn continue_from_headers(span: &Span, headers: &HeaderMap) {
+    continue_trace(span, &HeaderCarrier(headers));
+}
+
+pub fn continue_from_frame(span: &Span, frame: &Value) {
+    continue_trace(span, &FrameCarrier(frame));
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request};
+    use opentelemetry::{
+        SpanId, TraceId,
+        trace::{SpanKind, Status},
+    };
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SpanData};
+    use serde_json::json;
+    use tracing::{Instrument, dispatcher::DefaultGuard, info_span};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+    use crate::{metrics::Metrics, telemetry};
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+    const CLIENT_TRACE: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const CLIENT_SPAN: &str = "00f067aa0ba902b7";
+
+    // Exports every span to memory as soon as it closes, for spans recorded on
+    // this thread while the capture is alive.
+    struct Capture {
+        exporter: InMemorySpanExporter,
+        _provider: SdkTracerProvider,
+        _subscriber: DefaultGuard,
+    }
+
+    impl Capture {
+        fn start() -> Self {
+            let exporter = InMemorySpanExporter::default();
+            let provider = SdkTracerProvider::builder()
+                .with_simple_exporter(exporter.clone())
+                .build();
+            let subscriber = tracing_subscriber::registry()
+                .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+            Capture {
+                exporter,
+                _provider: provider,
+                _subscriber: tracing::subscriber::set_default(subscriber),
+            }
+        }
+
+        fn span(&self, name: &str) -> SpanData {
+            self.exporter
+                .get_finished_spans()
+                .unwrap()
+                .into_iter()
+                .find(|span| span.name == name)
+                .unwrap_or_else(|| panic!("no {} span exported", name))
+        }
+    }
+
+    fn attribute(span: &SpanData, key: &str) -> Option<String> {
+        span.attributes
+            .iter()
+            .find(|attribute| attribute.key.as_str() == key)
+            .map(|attribute| attribute.value.as_str().into_owned())
+    }
+
+    fn client_trace() -> TraceId {
+        TraceId::from_hex(CLIENT_TRACE).unwrap()
+    }
+
+    fn client_span() -> SpanId {
+        SpanId::from_hex(CLIENT_SPAN).unwrap()
+    }
+
+    #[test]
+    fn http_requests_continue_the_client_trace() {
+        let capture = Capture::start();
+        let request = Request::builder()
+            .uri("/room/create")
+            .header("traceparent", TRACEPARENT)
+            .body(Body::empty())
+            .unwrap();
+
+        drop(telemetry::http_span(&request));
+
+        let span = capture.span("http");
+        assert_eq!(span.span_context.trace_id(), client_trace());
+        assert_eq!(span.parent_span_id, client_span());
+    }
+
+    #[test]
+    fn frames_continue_the_client_trace() {
+        let capture = Capture::start();
+        let frame = json!({ "type": "join-room", "code": "123456", "traceparent": TRACEPARENT });
+
+        let _connection = info_span!("ws_connection").entered();
+        let span = info_span!("ws_message", message_type = "join-room");
+        telemetry::continue_frame_trace(&span, &frame);
+        drop(span);
+
+        let span = capture.span("ws_message");
+        assert_eq!(span.span_context.trace_id(), client_trace());
+        assert_eq!(span.parent_span_id, client_span());
+        assert_eq!(
+            attribute(&span, "message_type").as_deref(),
+            Some("join-room")
+        );
+    }
+
+    #[test]
+    fn frames_without_valid_context_stay_in_the_connection_trace() {
+        let capture = Capture::start();
+
+        let connection = info_span!("ws_connection");
+        connection.in_scope(|| {
+            for frame in [
+                json!({ "type": "heartbeat" }),
+                json!({ "type": "heartbeat", "traceparent": "not-a-traceparent" }),
+                json!({ "type": "heartbeat", "traceparent": 42 }),
+            ] {
+                let span = info_span!("ws_message", message_type = "heartbeat");
+                telemetry::continue_frame_trace(&span, &frame);
+            }
+        });
+        drop(connection);
+
+        let connection = capture.span("ws_connection");
+        let messages: Vec<SpanData> = capture
+            .exporter
+            .get_finished_spans()
+            .unwrap()
+            .into_iter()
+            .filter(|span| span.name == "ws_message")
+            .collect();
+        assert_eq!(messages.len(), 3);
+        for message in messages {
+            assert_eq!(
+                message.span_context.trace_id(),
+                connection.span_context.trace_id()
+            );
+            assert_eq!(message.parent_span_id, connection.span_context.span_id());
+        }
+    }
+
+    #[tokio::test]
+    async fn db_operations_are_client_spans_under_the_caller() {
+        let capture = Capture::start();
+        let metrics = Metrics::new();
+
+        let message = info_span!("ws_message", message_type = "join-room");
+        telemetry::continue_frame_trace(&message, &json!({ "traceparent": TRACEPARENT }));
+        let found = metrics
+            .observe_db("get_room_by_code", async { Ok::<_, ()>(true) })
+            .instrument(message.clone())
+            .await;
+        drop(message);
+        assert_eq!(found, Ok(true));
+
+        let message = capture.span("ws_message");
+        let span = capture.span("get_room_by_code");
+        assert_eq!(span.span_kind, SpanKind::Client);
+        assert_eq!(span.status, Status::Unset);
+        assert_eq!(span.span_context.trace_id(), client_trace());
+        assert_eq!(span.parent_span_id, message.span_context.span_id());
+        assert_eq!(attribute(&span, "db.system").as_deref(), Some("mongodb"));
+        assert_eq!(
+            attribute(&span, "db.operation").as_deref(),
+            Some("get_room_by_code")
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_db_operations_are_marked_as_errors() {
+        let capture = Capture::start();
+        let metrics = Metrics::new();
+
+        let result = metrics
+            .observe_db("create_room", async { Err::<(), _>("duplicate key") })
+            .await;
+        assert!(result.is_err());
+
+        let span = capture.span("create_room");
+        assert!(matches!(span.status, Status::Error { .. }));
+    }
+}
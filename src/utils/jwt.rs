@@ -2,6 +2,7 @@ use serde::{Serialize, Deserialize};
 use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey, errors::Error};
 use chrono::{Utc, Duration};
 use std::env;
+use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct AccessClaims {
@@ -9,6 +10,11 @@ pub struct AccessClaims {
     pub username: String,
     pub email: String,
     pub exp: usize,
+    // Identifies this token on the revocation list after a logout.
+    pub jti: String,
+    // The user's token version when this was issued; logging out everywhere
+    // bumps it, voiding every older token.
+    pub ver: u32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -17,13 +23,15 @@ pub struct RefreshClaims {
     pub exp: usize,
 }
 
-pub fn generate_access_token(user_id: &str, username: &str, email: &str) -> String {
+pub fn generate_access_token(user_id: &str, username: &str, email: &str, version: u32) -> String {
     let expiration = Utc::now() + Duration::hours(2);
     let access_claims = AccessClaims {
         sub: user_id.to_owned(),
         username: username.to_owned(),
         email: email.to_owned(),
         exp: expiration.timestamp() as usize,
+        jti: Uuid::new_v4().to_string(),
+        ver: version,
     };
     let secret = env::var("ACCESS_TOKEN_SECRET").expect("Access token secret not found in .env");
 
@@ -62,6 +70,7 @@ pub fn generate_refresh_token(user_id: &str) -> String {
     ).expect("Failed to generate refresh token.")
 }
 
+#[allow(dead_code)]
 pub fn verify_refresh_token(token: &str) -> Option<RefreshClaims> {
     let secret = env::var("REFRESH_TOKEN_SECRET").expect("Refresh token secret not found in .env");
     decode::<RefreshClaims>(
@@ -72,3 +81,36 @@ pub fn verify_refresh_token(token: &str) -> Option<RefreshClaims> {
     .ok()
     .map(|data| data.claims)
 }
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InviteClaims {
+    pub jti: String,
+    pub room: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    pub exp: usize,
+}
+
+pub fn generate_invite_token(secret: &str, invite_id: &str, room_code: &str, email: Option<&str>, exp: usize) -> String {
+    let invite_claims = InviteClaims {
+        jti: invite_id.to_owned(),
+        room: room_code.to_owned(),
+        email: email.map(str::to_owned),
+        exp,
+    };
+    encode(
+        &Header::default(),
+        &invite_claims,
+        &EncodingKey::from_secret(secret.as_ref()),
+    ).expect("Failed to generate invite token.")
+}
+
+pub fn verify_invite_token(secret: &str, token: &str) -> Result<InviteClaims, Error> {
+    let token_data = decode::<InviteClaims>(
+        token,
+        &DecodingKey::from_secret(secret.as_ref()),
+        &Validation::default(),
+    )?;
+
+    Ok(token_data.claims)
+}
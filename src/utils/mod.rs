@@ -1,2 +1,6 @@
 pub mod bcrypt;
-pub mod jwt;
\ No newline at end of file
+pub mod email_token;
+pub mod jwt;
+pub mod rate_limit;
+pub mod turn;
+pub mod validation;
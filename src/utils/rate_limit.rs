@@ -0,0 +1,175 @@
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+
+const MAX_TRACKED_KEYS: usize = 10_000;
+
+struct Attempts {
+    failures: u32,
+    window_start: Instant,
+    locked_until: Option<Instant>,
+}
+
+// Counts failed attempts per key and locks the key out once `max_failures`
+// is reached inside `window`.
+pub struct AttemptLimiter {
+    attempts: DashMap<String, Attempts>,
+    max_failures: u32,
+    window: Duration,
+    lockout: Duration,
+}
+
+impl AttemptLimiter {
+    pub fn new(max_failures: u32, window: Duration, lockout: Duration) -> Self {
+        AttemptLimiter {
+            attempts: DashMap::new(),
+            max_failures,
+            window,
+            lockout,
+        }
+    }
+
+    pub fn is_locked(&self, key: &str) -> bool {
+        let now = Instant::now();
+        self.attempts
+            .get(key)
+            .and_then(|attempts| attempts.locked_until)
+            .is_some_and(|until| until > now)
+    }
+
+    pub fn record_failure(&self, key: &str) {
+        let now = Instant::now();
+        if self.attempts.len() > MAX_TRACKED_KEYS {
+            self.attempts.retain(|_, attempts| {
+                now.duration_since(attempts.window_start) <= self.window
+                    || attempts.locked_until.is_some_and(|until| until > now)
+            });
+        }
+
+        let mut attempts = self.attempts.entry(key.to_owned()).or_insert(Attempts {
+            failures: 0,
+            window_start: now,
+            locked_until: None,
+        });
+
+        if now.duration_since(attempts.window_start) > self.window {
+            attempts.failures = 0;
+            attempts.window_start = now;
+            attempts.locked_until = None;
+        }
+
+        attempts.failures += 1;
+        if attempts.failures >= self.max_failures {
+            attempts.locked_until = Some(now + self.lockout);
+        }
+    }
+
+    pub fn reset(&self, key: &str) {
+        self.attempts.remove(key);
+    }
+}
+
+// Classic token bucket: `burst` tokens at most, refilled at `rate_per_sec`.
+pub struct TokenBucket {
+    capacity: f64,
+    rate_per_sec: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(rate_per_sec: u32, burst: u32) -> Self {
+        TokenBucket {
+            capacity: burst.max(1) as f64,
+            rate_per_sec: rate_per_sec as f64,
+            tokens: burst.max(1) as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    pub fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate_per_sec).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+
+    use super::*;
+
+    const SHORT: Duration = Duration::from_millis(50);
+
+    #[test]
+    fn keys_lock_after_max_failures() {
+        let limiter = AttemptLimiter::new(3, Duration::from_secs(60), Duration::from_secs(60));
+
+        limiter.record_failure("ip:10.0.0.1");
+        limiter.record_failure("ip:10.0.0.1");
+        assert!(!limiter.is_locked("ip:10.0.0.1"));
+
+        limiter.record_failure("ip:10.0.0.1");
+        assert!(limiter.is_locked("ip:10.0.0.1"));
+        assert!(!limiter.is_locked("ip:10.0.0.2"));
+
+        limiter.reset("ip:10.0.0.1");
+        assert!(!limiter.is_locked("ip:10.0.0.1"));
+    }
+
+    #[test]
+    fn lockouts_expire() {
+        let limiter = AttemptLimiter::new(1, Duration::from_secs(60), SHORT);
+
+        limiter.record_failure("user:a");
+        assert!(limiter.is_locked("user:a"));
+
+        sleep(SHORT * 2);
+        assert!(!limiter.is_locked("user:a"));
+    }
+
+    #[test]
+    fn failures_outside_the_window_are_forgotten() {
+        let limiter = AttemptLimiter::new(2, SHORT, Duration::from_secs(60));
+
+        limiter.record_failure("user:a");
+        sleep(SHORT * 2);
+        limiter.record_failure("user:a");
+        assert!(!limiter.is_locked("user:a"));
+
+        limiter.record_failure("user:a");
+        assert!(limiter.is_locked("user:a"));
+    }
+
+    #[test]
+    fn buckets_allow_a_burst_then_refuse() {
+        let mut bucket = TokenBucket::new(1, 3);
+
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+    }
+
+    #[test]
+    fn buckets_refill_at_their_rate_up_to_the_burst() {
+        let mut bucket = TokenBucket::new(100, 2);
+        while bucket.try_take() {}
+
+        // 100 per second is one token every 10ms.
+        sleep(Duration::from_millis(15));
+        assert!(bucket.try_take());
+
+        sleep(Duration::from_millis(200));
+        assert!(bucket.try_take());
+        assert!(bucket.try_take());
+        assert!(!bucket.try_take());
+    }
+}
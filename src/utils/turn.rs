@@ -0,0 +1,68 @@
+use base64::{Engine, engine::general_purpose::STANDARD};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha1::Sha1;
+
+use crate::config::Config;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct IceServer {
+    pub urls: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+// TURN REST API credentials as understood by coturn's `use-auth-secret`:
+// the username is `<expiry>:<user>` and the password is the base64 HMAC-SHA1
+// of that username keyed with the shared secret.
+pub fn turn_credentials(secret: &str, user_id: &str, expires_at: usize) -> (String, String) {
+    let username = format!("{}:{}", expires_at, user_id);
+    let mut mac =
+        Hmac::<Sha1>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(username.as_bytes());
+    let credential = STANDARD.encode(mac.finalize().into_bytes());
+
+    (username, credential)
+}
+
+pub fn ice_servers(config: &Config, user_id: &str, expires_at: usize) -> Vec<IceServer> {
+    let mut servers = vec![];
+
+    if !config.stun_urls.is_empty() {
+        servers.push(IceServer {
+            urls: config.stun_urls.clone(),
+            username: None,
+            credential: None,
+        });
+    }
+
+    if let Some(secret) = &config.turn_secret
+        && !config.turn_urls.is_empty()
+    {
+        let (username, credential) = turn_credentials(secret, user_id, expires_at);
+        servers.push(IceServer {
+            urls: config.turn_urls.clone(),
+            username: Some(username),
+            credential: Some(credential),
+        });
+    }
+
+    servers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credentials_match_coturn_rest_api() {
+        let (username, credential) =
+            turn_credentials("north-wind", "65a1b2c3d4e5f60718293a4b", 1_700_000_000);
+
+        assert_eq!(username, "1700000000:65a1b2c3d4e5f60718293a4b");
+        // printf '%s' "$username" | openssl dgst -sha1 -hmac north-wind -binary | base64
+        assert_eq!(credential, "nJUUs5+KUL088uQSRO0uJTwFYsU=");
+    }
+}
@@ -0,0 +1,202 @@
+use std::{collections::HashSet, sync::LazyLock};
+
+// Keeps the bcrypt input intact: bcrypt ignores everything after 72 bytes.
+const MAX_PASSWORD_BYTES: usize = 72;
+const MAX_EMAIL_LEN: usize = 254;
+const MAX_EMAIL_LOCAL_LEN: usize = 64;
+const USERNAME_LEN: std::ops::RangeInclusive<usize> = 3..=32;
+
+static BREACHED_PASSWORDS: LazyLock<HashSet<&'static str>> = LazyLock::new(|| {
+    include_str!("breached_passwords.txt")
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+});
+
+// Trims and lowercases the address, so lookups and the unique index see one
+// spelling per mailbox. The syntax check is deliberately loose: it rejects
+// obvious typos, and only a verification mail proves an address works.
+pub fn normalize_email(email: &str) -> Result<String, &'static str> {
+    let email = email.trim().to_lowercase();
+    let invalid = "Enter a valid email address.";
+
+    if email.len() > MAX_EMAIL_LEN || email.chars().any(char::is_whitespace) {
+        return Err(invalid);
+    }
+    let Some((local, domain)) = email.split_once('@') else {
+        return Err(invalid);
+    };
+    if local.is_empty()
+        || local.len() > MAX_EMAIL_LOCAL_LEN
+        || local.starts_with('.')
+        || local.ends_with('.')
+        || local.contains("..")
+        || local.contains(['<', '>', '(', ')', '[', ']', ',', ';', ':', '\\', '"'])
+    {
+        return Err(invalid);
+    }
+
+    let labels: Vec<&str> = domain.split('.').collect();
+    let valid_label = |label: &&str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+    };
+    if labels.len() < 2 || !labels.iter().all(valid_label) {
+        return Err(invalid);
+    }
+
+    Ok(email)
+}
+
+// Usernames are shown to other participants, so they are kept to a
+// predictable character set.
+pub fn validate_username(username: &str) -> Result<String, &'static str> {
+    let username = username.trim();
+
+    if !USERNAME_LEN.contains(&username.chars().count()) {
+        return Err("Username must be 3 to 32 characters.");
+    }
+    if !username.starts_with(|c: char| c.is_alphanumeric()) {
+        return Err("Username must start with a letter or digit.");
+    }
+    if !username
+        .chars()
+        .all(|c| c.is_alphanumeric() || matches!(c, '_' | '.' | '-'))
+    {
+        return Err("Username may only contain letters, digits, '_', '.' and '-'.");
+    }
+
+    Ok(username.to_string())
+}
+
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub check_breached: bool,
+}
+
+impl PasswordPolicy {
+    pub fn check(&self, password: &str, username: &str, email: &str) -> Result<(), &'static str> {
+        if password.chars().count() < self.min_length {
+            return Err("Password is too short.");
+        }
+        if password.len() > MAX_PASSWORD_BYTES {
+            return Err("Password must be at most 72 bytes.");
+        }
+
+        let lowered = password.to_lowercase();
+        if lowered == username.to_lowercase() || lowered == email {
+            return Err("Password must not match your username or email.");
+        }
+        if self.check_breached && BREACHED_PASSWORDS.contains(lowered.as_str()) {
+            return Err("This password appears in known data breaches, choose another.");
+        }
+
+        Ok(())
+    }
+}
+
+pub const INVALID_ROOM_CODE: &str = "Room codes are six digits.";
+
+pub fn is_valid_room_code(code: &str) -> bool {
+    code.len() == 6 && code.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POLICY: PasswordPolicy = PasswordPolicy {
+        min_length: 10,
+        check_breached: true,
+    };
+
+    #[test]
+    fn emails_are_normalized() {
+        assert_eq!(
+            normalize_email("  Alice.Smith@Example.COM "),
+            Ok("alice.smith@example.com".to_string())
+        );
+        assert_eq!(
+            normalize_email("bob+rooms@mail.example.org"),
+            Ok("bob+rooms@mail.example.org".to_string())
+        );
+    }
+
+    #[test]
+    fn malformed_emails_are_rejected() {
+        for email in [
+            "",
+            "alice",
+            "alice@",
+            "@example.com",
+            "alice@example",
+            "alice@@example.com",
+            "al ice@example.com",
+            "alice@-example.com",
+            "alice@example..com",
+            ".alice@example.com",
+            "alice..smith@example.com",
+            "<alice>@example.com",
+        ] {
+            assert!(normalize_email(email).is_err(), "accepted {:?}", email);
+        }
+    }
+
+    #[test]
+    fn usernames_follow_the_rules() {
+        assert_eq!(validate_username(" alice_01 "), Ok("alice_01".to_string()));
+        assert_eq!(validate_username("zoë.k"), Ok("zoë.k".to_string()));
+
+        for username in [
+            "",
+            "ab",
+            "_alice",
+            "alice smith",
+            "alice<script>",
+            &"a".repeat(33),
+        ] {
+            assert!(
+                validate_username(username).is_err(),
+                "accepted {:?}",
+                username
+            );
+        }
+    }
+
+    #[test]
+    fn passwords_follow_the_policy() {
+        let check = |password: &str| POLICY.check(password, "alice", "alice@example.com");
+
+        assert!(check("correct horse battery").is_ok());
+        assert_eq!(check("short"), Err("Password is too short."));
+        assert!(check(&"x".repeat(73)).is_err());
+        assert!(check("ALICE@example.com").is_err());
+        assert!(check("Password123").is_err());
+        assert!(check("qwertyuiop").is_err());
+    }
+
+    #[test]
+    fn breach_checks_can_be_disabled() {
+        let policy = PasswordPolicy {
+            min_length: 8,
+            check_breached: false,
+        };
+        assert!(
+            policy
+                .check("password123", "alice", "alice@example.com")
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn room_codes_are_six_digits() {
+        assert!(is_valid_room_code("123456"));
+        assert!(!is_valid_room_code("12345"));
+        assert!(!is_valid_room_code("12345a"));
+        assert!(!is_valid_room_code("１２３４５６"));
+    }
+}
@@ -1,40 +1,164 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
 
 use axum::{
     extract::{
-        State,
-        ws::{Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, State,
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade, close_code},
     },
-    response::Response,
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
+use chrono::{DateTime, Utc};
 use futures_util::SinkExt as FuturesSinkExt;
-use futures_util::{
-    StreamExt,
-    stream::{SplitSink, SplitStream},
-};
-use mongodb::bson::oid::ObjectId;
+use futures_util::{Sink, StreamExt, stream::SplitStream};
+use mongodb::bson::{self, oid::ObjectId};
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
 use tokio::task;
+use tracing::{Instrument, Span, debug, error, field, info, info_span, warn};
 use uuid::Uuid;
 
 use crate::{
-    SharedState,
+    SharedState, api,
+    broker::{AccessError, AccessState, Broker, Envelope, Grant, Payload, Replay},
+    config::Config,
     db::connection::Database,
-    models::{room_model::Room, user_model::User},
-    utils::jwt::{AccessClaims, verify_access_token},
+    error::{AppError, ErrorCode},
+    metrics::Metrics,
+    models::{
+        audit_model::{AuditAction, AuditEvent},
+        recording_model::Recording,
+        room_model::{Room, RoomMode},
+        user_model::User,
+    },
+    recording::ActiveRecording,
+    sfu::{SFU_PEER_ID, Sfu},
+    telemetry,
+    utils::{
+        bcrypt::verify_password,
+        jwt::{InviteClaims, verify_invite_token},
+        rate_limit::{AttemptLimiter, TokenBucket},
+        turn::{IceServer, ice_servers},
+        validation::{INVALID_ROOM_CODE, is_valid_room_code},
+    },
+};
+
+mod codec;
+mod input;
+mod relay;
+mod signaling;
+
+use self::{
+    codec::{Encoding, MSGPACK_PROTOCOL},
+    input::InputEvent,
+    relay::InputQueue,
 };
 
-#[derive(Clone)]
+// Longest time-limited remote-control grant.
+const MAX_GRANT_SECS: u64 = 24 * 60 * 60;
+
+const TARGET_OVERLOADED: &str = "The controlled machine is not keeping up; input was dropped.";
+
+// Message types the server handles. Anything else is counted as `unknown` so
+// clients cannot create metric series.
+const MESSAGE_TYPES: &[&str] = &[
+    "heartbeat",
+    "presence",
+    "join-room",
+    "resume",
+    "request-accepted",
+    "offer",
+    "answer",
+    "ice-candidate",
+    "mouse-move",
+    "mouse-click",
+    "key-press",
+    "scroll",
+    "clipboard",
+    "message",
+    "screen-sharing-started",
+    "screen-sharing-stopped",
+    "video-started",
+    "video-stopped",
+    "start-recording",
+    "stop-recording",
+    "leave-room",
+    "request-access",
+    "allowed-access",
+    "rejected-access",
+    "revoke-access",
+];
+
+// Boxed so sockets can be stood in for without a real connection in tests.
+pub type SocketSink = Pin<Box<dyn Sink<Message, Error = axum::Error> + Send>>;
+pub type SocketSender = Arc<Mutex<SocketSink>>;
+
+pub struct Session {
+    pub user_id: ObjectId,
+    pub room_code: String,
+    pub token_exp: usize,
+    // The access token's `jti`, so logging it out closes this socket.
+    pub token_id: String,
+    pub mouse_events: u64,
+    pub key_events: u64,
+}
+
 pub struct AppState {
     pub user_sockets: Arc<Mutex<HashMap<ObjectId, Uuid>>>,
-    pub sockets: Arc<Mutex<HashMap<Uuid, Arc<Mutex<SplitSink<WebSocket, Message>>>>>>,
+    pub sockets: Arc<Mutex<HashMap<Uuid, SocketSender>>>,
+    pub sessions: Arc<Mutex<HashMap<Uuid, Session>>>,
+    // Sessions of guests waiting for the host to admit them.
+    pub pending: Mutex<HashMap<Uuid, Session>>,
+    pub passcode_attempts: AttemptLimiter,
+    pub input_queues: Mutex<HashMap<Uuid, Arc<InputQueue>>>,
+    pub encodings: Mutex<HashMap<Uuid, Encoding>>,
+    pub last_seen: Mutex<HashMap<Uuid, DateTime<Utc>>>,
+    pub instance_id: Uuid,
+    pub broker: Arc<dyn Broker>,
+    pub metrics: Arc<Metrics>,
+}
+
+impl AppState {
+    pub fn new(
+        broker: Arc<dyn Broker>,
+        passcode_attempts: AttemptLimiter,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        AppState {
+            user_sockets: Arc::new(Mutex::new(HashMap::new())),
+            sockets: Arc::new(Mutex::new(HashMap::new())),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            pending: Mutex::new(HashMap::new()),
+            passcode_attempts,
+            input_queues: Mutex::new(HashMap::new()),
+            encodings: Mutex::new(HashMap::new()),
+            last_seen: Mutex::new(HashMap::new()),
+            instance_id: Uuid::new_v4(),
+            broker,
+            metrics,
+        }
+    }
 }
 
 #[derive(Deserialize)]
 struct JoinRoomData {
     access_token: String,
     code: String,
+    invite: Option<String>,
+    passcode: Option<String>,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    message_type: String,
+    code: ErrorCode,
+    message: String,
 }
 
 #[derive(Serialize)]
@@ -42,6 +166,35 @@ struct JoinRoomResponse {
     message_type: String,
     user_id: ObjectId,
     username: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ice_servers: Option<Vec<IceServer>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<RoomMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resume_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ResumeData {
+    access_token: String,
+    resume_token: String,
+    last_seq: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct SessionResumedResponse {
+    message_type: String,
+    user_id: ObjectId,
+    username: String,
+    code: String,
+    host: bool,
+    ice_servers: Vec<IceServer>,
+    mode: RoomMode,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resume_token: Option<String>,
+    last_seq: u64,
+    missed: usize,
+    replay_complete: bool,
 }
 
 #[derive(Deserialize, Serialize, Clone)]
@@ -75,6 +228,12 @@ struct RequestAcceptedResponse {
     user_id: ObjectId,
     participants: Vec<Participant>,
     host: Host,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ice_servers: Option<Vec<IceServer>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mode: Option<RoomMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    resume_token: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -89,8 +248,7 @@ struct RequestAcceptedResponseTOParticipants {
 #[derive(Deserialize)]
 struct RtcConnectionData {
     item: serde_json::Value,
-    to: ObjectId,
-    user_id: ObjectId,
+    to: Option<ObjectId>,
 }
 
 #[derive(Serialize)]
@@ -101,40 +259,31 @@ struct RtcConnectionResponse {
     user_id: ObjectId,
 }
 
-#[derive(Deserialize)]
-struct MouseMoveData {
-    x: f64,
-    y: f64,
-    to: ObjectId,
+#[derive(Serialize)]
+struct PresenceEntry {
+    user_id: ObjectId,
+    online: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_seen: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize)]
-struct MouseMoveResponse {
+struct PresenceResponse {
     message_type: String,
-    x: f64,
-    y: f64,
-}
-
-#[derive(Deserialize)]
-struct KeyPressData {
-    key: String,
-    to: ObjectId,
+    members: Vec<PresenceEntry>,
 }
 
 #[derive(Serialize)]
-struct KeyPressResponse {
+struct HeartbeatResponse {
     message_type: String,
-    key: String,
-}
-
-#[derive(Deserialize)]
-struct MouseClickData {
-    to: ObjectId,
+    server_time: DateTime<Utc>,
 }
 
 #[derive(Serialize)]
-struct MouseClickResponse {
+struct InputResponse<'a> {
     message_type: String,
+    #[serde(flatten)]
+    event: &'a InputEvent,
 }
 
 #[derive(Deserialize)]
@@ -170,7 +319,6 @@ struct VideoResponse {
 #[derive(Deserialize)]
 struct LeaveRoomData {
     code: String,
-    user_id: ObjectId,
 }
 
 #[derive(Serialize)]
@@ -186,930 +334,2161 @@ struct ParticipantLeft {
     user: ObjectId,
 }
 
+#[derive(Serialize)]
+struct RecordingResponse {
+    message_type: String,
+    recording_id: ObjectId,
+}
+
 #[derive(Deserialize)]
 struct RequestAccessData {
     to: ObjectId,
-    from: ObjectId,
     username: String,
 }
 
-#[derive(Serialize)]
-struct RequestAccessResponse {
-    message_type: String,
+#[derive(Deserialize)]
+struct AccessData {
     user_id: ObjectId,
-    username: String,
+    #[serde(default)]
+    duration_secs: Option<u64>,
 }
 
 #[derive(Deserialize)]
-struct AccessData {
-    code: String,
+struct RevokeAccessData {
     user_id: ObjectId,
-    username: String,
 }
 
+// `user_id` is always the controlling user and `target` the controlled one.
 #[derive(Serialize)]
 struct AccessResponse {
     message_type: String,
     user_id: ObjectId,
     username: String,
+    target: ObjectId,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<i64>,
+}
+
+fn message_label(message_type: &str) -> &'static str {
+    MESSAGE_TYPES
+        .iter()
+        .find(|known| **known == message_type)
+        .copied()
+        .unwrap_or("unknown")
+}
+
+fn is_member(room: &Room, user_id: &ObjectId) -> bool {
+    room.host_id == *user_id || room.participants_id.contains(user_id)
+}
+
+async fn session_identity(ws_state: &AppState, socket_id: &Uuid) -> Option<(ObjectId, String)> {
+    let sessions = ws_state.sessions.lock().await;
+    sessions
+        .get(socket_id)
+        .map(|session| (session.user_id, session.room_code.clone()))
+}
+
+async fn send_message(ws_state: &AppState, socket_id: &Uuid, message: Message) {
+    let sender_arc = {
+        let sockets = ws_state.sockets.lock().await;
+        sockets.get(socket_id).cloned()
+    };
+
+    if let Some(sender_arc) = sender_arc {
+        let mut sender = sender_arc.lock().await;
+        if let Err(err) = sender.send(message).await {
+            ws_state.metrics.ws_send_failures.inc();
+            debug!(%socket_id, error = %err, "Failed to send message");
+        }
+    }
+}
+
+async fn send_to_socket(ws_state: &AppState, socket_id: &Uuid, text: &str) {
+    send_message(ws_state, socket_id, Message::Text(text.to_owned().into())).await;
+}
+
+// Asks every client to reconnect, by then to another instance. Each socket is
+// cleaned up once its close handshake ends.
+pub async fn close_all(ws_state: &AppState) {
+    let socket_ids: Vec<Uuid> = ws_state.sockets.lock().await.keys().copied().collect();
+    info!(sockets = socket_ids.len(), "Closing sockets");
+
+    for socket_id in socket_ids {
+        let frame = CloseFrame {
+            code: close_code::RESTART,
+            reason: "Server restarting".into(),
+        };
+        send_message(ws_state, &socket_id, Message::Close(Some(frame))).await;
+    }
+}
+
+// Closes the user's socket on whichever instance holds it: only the one joined
+// with `token_id` when given, otherwise any. Cleanup runs once the close
+// handshake ends, as for `close_all`.
+pub async fn close_user_sockets(ws_state: &AppState, user_id: ObjectId, token_id: Option<String>) {
+    publish(ws_state, vec![user_id], Payload::Revoke { token_id }).await;
+}
+
+async fn socket_encoding(ws_state: &AppState, socket_id: &Uuid) -> Encoding {
+    let encodings = ws_state.encodings.lock().await;
+    encodings.get(socket_id).copied().unwrap_or_default()
+}
+
+// For hot paths only: encodes the frame the way the user's socket negotiated.
+pub(crate) async fn send_frame_to_user<T: Serialize>(
+    ws_state: &AppState,
+    user_id: &ObjectId,
+    frame: &T,
+) {
+    let sender_id = {
+        let user_sockets = ws_state.user_sockets.lock().await;
+        user_sockets.get(user_id).cloned()
+    };
+
+    match sender_id {
+        Some(sender_id) => {
+            let encoding = socket_encoding(ws_state, &sender_id).await;
+            send_message(ws_state, &sender_id, encoding.encode(frame)).await;
+        }
+        None => {
+            let frame = serde_json::to_value(frame).unwrap();
+            publish(ws_state, vec![*user_id], Payload::Frame { frame }).await;
+        }
+    }
+}
+
+async fn send_to_user(ws_state: &AppState, user_id: &ObjectId, text: &str) {
+    let sender_id = {
+        let user_sockets = ws_state.user_sockets.lock().await;
+        user_sockets.get(user_id).cloned()
+    };
+
+    match sender_id {
+        Some(sender_id) => send_to_socket(ws_state, &sender_id, text).await,
+        None => {
+            let text = text.to_owned();
+            publish(ws_state, vec![*user_id], Payload::Text { text }).await;
+        }
+    }
+}
+
+// Members connected here are sent to directly; the rest share one envelope.
+async fn send_to_room(ws_state: &AppState, room: &Room, text: &str) {
+    let mut remote = vec![];
+    for user_id in std::iter::once(&room.host_id).chain(&room.participants_id) {
+        let sender_id = ws_state.user_sockets.lock().await.get(user_id).cloned();
+        match sender_id {
+            Some(sender_id) => send_to_socket(ws_state, &sender_id, text).await,
+            None => remote.push(*user_id),
+        }
+    }
+
+    let text = text.to_owned();
+    publish(ws_state, remote, Payload::Text { text }).await;
+}
+
+// Hands messages for users who are not connected here to the other instances.
+async fn publish(ws_state: &AppState, recipients: Vec<ObjectId>, payload: Payload) {
+    if recipients.is_empty() {
+        return;
+    }
+
+    let envelope = Envelope {
+        recipients,
+        payload,
+    };
+    if let Err(err) = ws_state.broker.publish(&envelope).await {
+        error!(error = %err, "Failed to publish to the broker");
+    }
+}
+
+// Delivers what any instance published to the recipients connected here,
+// subscribing again whenever the broker connection is lost.
+pub async fn run_deliveries(ws_state: Arc<AppState>) {
+    loop {
+        match ws_state.broker.subscribe().await {
+            Ok(mut deliveries) => {
+                while let Some(envelope) = deliveries.recv().await {
+                    deliver(&ws_state, envelope).await;
+                }
+                error!("Lost the broker subscription, resubscribing");
+            }
+            Err(err) => error!(error = %err, "Failed to subscribe to the broker"),
+        }
+
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+async fn deliver(ws_state: &AppState, envelope: Envelope) {
+    for user_id in &envelope.recipients {
+        let Some(sender_id) = ws_state.user_sockets.lock().await.get(user_id).cloned() else {
+            continue;
+        };
+
+        match &envelope.payload {
+            Payload::Text { text } => send_to_socket(ws_state, &sender_id, text).await,
+            Payload::Frame { frame } => {
+                let encoding = socket_encoding(ws_state, &sender_id).await;
+                send_message(ws_state, &sender_id, encoding.encode(frame)).await;
+            }
+            Payload::Input {
+                sender: controller,
+                coalesce,
+                frame,
+            } => {
+                let Some(queue) = input_queue(ws_state, user_id).await else {
+                    continue;
+                };
+                if !queue.push(*controller, *coalesce, queue.encoding.encode(frame)) {
+                    let response = ErrorResponse {
+                        message_type: "error".to_string(),
+                        code: ErrorCode::TargetOverloaded,
+                        message: TARGET_OVERLOADED.to_string(),
+                    };
+                    let response_text = serde_json::to_string(&response).unwrap();
+                    send_to_user(ws_state, controller, &response_text).await;
+                }
+            }
+            Payload::Revoke { token_id } => {
+                let signed_in_with = ws_state
+                    .sessions
+                    .lock()
+                    .await
+                    .get(&sender_id)
+                    .map(|session| session.token_id.clone());
+                if token_id.is_none() || signed_in_with == *token_id {
+                    let frame = CloseFrame {
+                        code: close_code::POLICY,
+                        reason: "Signed out".into(),
+                    };
+                    send_message(ws_state, &sender_id, Message::Close(Some(frame))).await;
+                }
+            }
+        }
+    }
+}
+
+async fn set_online(ws_state: &AppState, user_id: ObjectId) {
+    if let Err(err) = ws_state
+        .broker
+        .set_online(user_id, ws_state.instance_id)
+        .await
+    {
+        warn!(%user_id, error = %err, "Failed to publish presence");
+    }
+}
+
+async fn issue_resume_token(
+    ws_state: &AppState,
+    user_id: ObjectId,
+    room_code: &str,
+) -> Option<String> {
+    match ws_state.broker.issue_resume_token(user_id, room_code).await {
+        Ok(token) => Some(token),
+        Err(err) => {
+            warn!(%user_id, error = %err, "Failed to issue a resume token");
+            None
+        }
+    }
+}
+
+pub(crate) async fn forget_room(ws_state: &AppState, room_code: &str) {
+    if let Err(err) = ws_state.broker.forget_room(room_code).await {
+        warn!(room = room_code, error = %err, "Failed to drop resume state");
+    }
+}
+
+// Room events are buffered so members who reconnect can catch up on them.
+async fn broadcast<T: Serialize>(ws_state: &AppState, room: &Room, event: &T) {
+    let text = record(ws_state, &room.code, None, event).await;
+    send_to_room(ws_state, room, &text).await;
+}
+
+async fn send_room_event<T: Serialize>(
+    ws_state: &AppState,
+    room_code: &str,
+    user_id: &ObjectId,
+    event: &T,
+) {
+    let text = record(ws_state, room_code, Some(*user_id), event).await;
+    send_to_user(ws_state, user_id, &text).await;
+}
+
+// Without the broker the event still goes out, just unsequenced and missing
+// from replays.
+async fn record<T: Serialize>(
+    ws_state: &AppState,
+    room_code: &str,
+    recipient: Option<ObjectId>,
+    event: &T,
+) -> String {
+    let event = serde_json::to_value(event).unwrap();
+    match ws_state
+        .broker
+        .record_room_event(room_code, recipient, &event)
+        .await
+    {
+        Ok(text) => text,
+        Err(err) => {
+            warn!(room = room_code, error = %err, "Failed to record room event");
+            event.to_string()
+        }
+    }
+}
+
+fn audit(db: &Arc<Database>, event: AuditEvent) {
+    let db = db.clone();
+    task::spawn(async move {
+        if let Err(err) = Database::insert_audit_event(db, event).await {
+            error!(error = %err, "Failed to write audit event");
+        }
+    });
+}
+
+// Makes the session of an admitted socket live, so it counts towards presence,
+// is resumed after a disconnect and shows up in the audit log.
+async fn start_session(ws_state: &AppState, db: &Arc<Database>, socket_id: Uuid, session: Session) {
+    let user_id = session.user_id;
+    ws_state.user_sockets.lock().await.insert(user_id, socket_id);
+    set_online(ws_state, user_id).await;
+
+    let mut event = AuditEvent::new(AuditAction::SessionStarted, user_id);
+    event.room_code = Some(session.room_code.clone());
+
+    let previous = ws_state.sessions.lock().await.insert(socket_id, session);
+    if let Some(previous) = previous {
+        audit_session_end(db, previous);
+    }
+    audit(db, event);
+}
+
+// Guests are reachable while they wait, so the host's answer gets to them, but
+// stay offline until admitted.
+async fn await_admission(ws_state: &AppState, socket_id: Uuid, session: Session) {
+    let user_id = session.user_id;
+    ws_state.user_sockets.lock().await.insert(user_id, socket_id);
+    ws_state.pending.lock().await.insert(socket_id, session);
 }
 
-pub async fn handler(ws: WebSocketUpgrade, State(state): State<SharedState>) -> Response {
-    ws.on_upgrade(|socket| handle_socket(socket, state))
+fn audit_session_end(db: &Arc<Database>, session: Session) {
+    let mut event = AuditEvent::new(AuditAction::SessionEnded, session.user_id);
+    event.room_code = Some(session.room_code);
+    event.mouse_events = Some(session.mouse_events);
+    event.key_events = Some(session.key_events);
+    audit(db, event);
 }
 
-async fn handle_socket(socket: WebSocket, state: SharedState) {
+// Every transition is announced to both sides and written to the audit log.
+async fn send_access_event(
+    ws_state: &AppState,
+    db: &Arc<Database>,
+    state: AccessState,
+    actor: ObjectId,
+    (controller, target): (ObjectId, ObjectId),
+    grant: &Grant,
+) {
+    let action = match state {
+        AccessState::Requested => AuditAction::AccessRequested,
+        AccessState::Granted => AuditAction::AccessGranted,
+        AccessState::Denied => AuditAction::AccessRejected,
+        AccessState::Revoked => AuditAction::AccessRevoked,
+        AccessState::Expired => AuditAction::AccessExpired,
+    };
+    let mut event = AuditEvent::new(action, actor);
+    event.target = Some(if actor == controller {
+        target
+    } else {
+        controller
+    });
+    event.room_code = Some(grant.room_code.clone());
+    audit(db, event);
+
+    let expires_at = grant
+        .expires_at
+        .filter(|_| state == AccessState::Granted)
+        .map(|at| at.timestamp());
+    let response = AccessResponse {
+        message_type: state.message_type().to_string(),
+        user_id: controller,
+        username: grant.username.clone(),
+        target,
+        expires_at,
+    };
+    send_room_event(ws_state, &grant.room_code, &controller, &response).await;
+    send_room_event(ws_state, &grant.room_code, &target, &response).await;
+}
+
+fn schedule_expiry(
+    ws_state: Arc<AppState>,
+    db: Arc<Database>,
+    controller: ObjectId,
+    target: ObjectId,
+    expires_at: DateTime<Utc>,
+) {
+    task::spawn(async move {
+        let delay = (expires_at - Utc::now()).to_std().unwrap_or_default();
+        tokio::time::sleep(delay).await;
+        let grant = ws_state
+            .broker
+            .expire_access((controller, target), expires_at)
+            .await;
+        let grant = grant.unwrap_or_else(|err| {
+            error!(error = %err, "Failed to expire a remote-control grant");
+            None
+        });
+        if let Some(grant) = grant {
+            send_access_event(
+                &ws_state,
+                &db,
+                AccessState::Expired,
+                target,
+                (controller, target),
+                &grant,
+            )
+            .await;
+        }
+    });
+}
+
+// Ends every grant or pending request involving a user who left.
+async fn release_access(ws_state: &AppState, db: &Arc<Database>, user_id: ObjectId) {
+    let released = match ws_state.broker.release_access(user_id).await {
+        Ok(released) => released,
+        Err(err) => {
+            error!(%user_id, error = %err, "Failed to release remote-control grants");
+            return;
+        }
+    };
+    for (key, grant) in released {
+        send_access_event(ws_state, db, AccessState::Revoked, user_id, key, &grant).await;
+    }
+}
+
+// Returns the sender if it currently holds a grant to control `target`.
+async fn authorize_control(
+    ws_state: &AppState,
+    db: &Arc<Database>,
+    socket_id: &Uuid,
+    target: ObjectId,
+) -> Option<ObjectId> {
+    let Some((controller, _)) = session_identity(ws_state, socket_id).await else {
+        send_error(
+            ws_state,
+            socket_id,
+            ErrorCode::NotInRoom,
+            "Join a room before sending control events.",
+        )
+        .await;
+        return None;
+    };
+
+    let result = ws_state.broker.check_access((controller, target)).await;
+    match result {
+        Ok(()) => Some(controller),
+        Err((err, expired)) => {
+            if let Some(grant) = expired {
+                send_access_event(
+                    ws_state,
+                    db,
+                    AccessState::Expired,
+                    target,
+                    (controller, target),
+                    &grant,
+                )
+                .await;
+            }
+            send_access_error(ws_state, socket_id, err).await;
+            None
+        }
+    }
+}
+
+async fn send_access_error(ws_state: &AppState, socket_id: &Uuid, err: AccessError) {
+    if let AccessError::Broker(err) = &err {
+        error!(error = %err, "Failed to reach the broker for remote control");
+    }
+    send_error(ws_state, socket_id, err.code(), err.message()).await;
+}
+
+// Queues input on the target's socket when it is connected here, and
+// otherwise hands it to whichever instance holds the target. Returns false if
+// the target is not keeping up.
+async fn relay_input<T: Serialize>(
+    ws_state: &AppState,
+    controller: ObjectId,
+    target: ObjectId,
+    coalesce: bool,
+    frame: &T,
+) -> bool {
+    match input_queue(ws_state, &target).await {
+        Some(queue) => queue.push(controller, coalesce, queue.encoding.encode(frame)),
+        None => {
+            let payload = Payload::Input {
+                sender: controller,
+                coalesce,
+                frame: serde_json::to_value(frame).unwrap(),
+            };
+            publish(ws_state, vec![target], payload).await;
+            true
+        }
+    }
+}
+
+// Full keystroke and clipboard logging for compliance; other input is only
+// counted.
+fn keystroke_audit(event: &InputEvent, controller: ObjectId) -> Option<AuditEvent> {
+    match event {
+        InputEvent::KeyPress(key_press) => {
+            let mut audit_event = AuditEvent::new(AuditAction::KeyPress, controller);
+            audit_event.key = Some(key_press.key.clone());
+            Some(audit_event)
+        }
+        InputEvent::Clipboard(clipboard) => {
+            let mut audit_event = AuditEvent::new(AuditAction::ClipboardPaste, controller);
+            audit_event.text = Some(clipboard.text.clone());
+            Some(audit_event)
+        }
+        _ => None,
+    }
+}
+
+// `online` holds the users the broker knows to be connected to any instance.
+// Last-seen times are only tracked for sockets on this one.
+async fn presence_of(
+    ws_state: &AppState,
+    user_id: ObjectId,
+    online: &HashSet<ObjectId>,
+) -> PresenceEntry {
+    let socket_id = ws_state.user_sockets.lock().await.get(&user_id).cloned();
+    let last_seen = match socket_id {
+        Some(socket_id) => ws_state.last_seen.lock().await.get(&socket_id).copied(),
+        None => None,
+    };
+
+    PresenceEntry {
+        user_id,
+        online: socket_id.is_some() || online.contains(&user_id),
+        last_seen,
+    }
+}
+
+async fn input_queue(ws_state: &AppState, user_id: &ObjectId) -> Option<Arc<InputQueue>> {
+    let socket_id = ws_state.user_sockets.lock().await.get(user_id).cloned()?;
+
+    let mut queues = ws_state.input_queues.lock().await;
+    if let Some(queue) = queues.get(&socket_id) {
+        return Some(queue.clone());
+    }
+
+    let sink = ws_state.sockets.lock().await.get(&socket_id).cloned()?;
+    let encoding = socket_encoding(ws_state, &socket_id).await;
+    let queue = InputQueue::spawn(sink, encoding);
+    queues.insert(socket_id, queue.clone());
+    Some(queue)
+}
+
+async fn count_relayed_input(ws_state: &AppState, socket_id: &Uuid, key: bool) {
+    let mut sessions = ws_state.sessions.lock().await;
+    if let Some(session) = sessions.get_mut(socket_id) {
+        if key {
+            session.key_events += 1;
+        } else {
+            session.mouse_events += 1;
+        }
+    }
+}
+
+async fn send_error(ws_state: &AppState, socket_id: &Uuid, code: ErrorCode, message: &str) {
+    let response = ErrorResponse {
+        message_type: "error".to_string(),
+        code,
+        message: message.to_string(),
+    };
+    let response_text = serde_json::to_string(&response).unwrap();
+    send_to_socket(ws_state, socket_id, &response_text).await;
+}
+
+async fn send_app_error(ws_state: &AppState, socket_id: &Uuid, err: AppError) {
+    err.log();
+    send_error(ws_state, socket_id, err.code(), err.message()).await;
+}
+
+async fn send_user_error(ws_state: &AppState, user_id: &ObjectId, err: AppError) {
+    err.log();
+    let response = ErrorResponse {
+        message_type: "error".to_string(),
+        code: err.code(),
+        message: err.message().to_string(),
+    };
+    let response_text = serde_json::to_string(&response).unwrap();
+    send_to_user(ws_state, user_id, &response_text).await;
+}
+
+// Checks the passcode of a protected room, locking out both the user and the
+// address they connect from after repeated wrong guesses.
+async fn check_passcode(
+    ws_state: &AppState,
+    socket_id: &Uuid,
+    ip: IpAddr,
+    user_id: ObjectId,
+    hashed: &str,
+    passcode: Option<String>,
+) -> bool {
+    let attempts = &ws_state.passcode_attempts;
+    let user_key = format!("user:{}", user_id);
+    let ip_key = format!("ip:{}", ip);
+
+    if attempts.is_locked(&user_key) || attempts.is_locked(&ip_key) {
+        send_error(
+            ws_state,
+            socket_id,
+            ErrorCode::TooManyAttempts,
+            "Too many wrong passcodes, try again later.",
+        )
+        .await;
+        return false;
+    }
+
+    let Some(passcode) = passcode else {
+        send_error(
+            ws_state,
+            socket_id,
+            ErrorCode::PasscodeRequired,
+            "This room needs a passcode.",
+        )
+        .await;
+        return false;
+    };
+
+    let hashed = hashed.to_owned();
+    let matches = task::spawn_blocking(move || verify_password(&passcode, &hashed))
+        .await
+        .is_ok_and(|result| result.unwrap_or(false));
+
+    if !matches {
+        attempts.record_failure(&user_key);
+        attempts.record_failure(&ip_key);
+        send_error(
+            ws_state,
+            socket_id,
+            ErrorCode::InvalidPasscode,
+            "Incorrect passcode.",
+        )
+        .await;
+        return false;
+    }
+
+    attempts.reset(&user_key);
+    attempts.reset(&ip_key);
+    true
+}
+
+// Returns false if the participant could not be added; the joining user has
+// been sent an error frame by then.
+async fn admit_participant(
+    db: &Arc<Database>,
+    ws_state: &AppState,
+    config: &Config,
+    room: &Room,
+    data: RequestAcceptedData,
+) -> bool {
+    match Database::add_participant_to_room(
+        db.clone(),
+        &data.code,
+        data.user_id,
+        config.max_participants_per_room,
+    )
+    .await
+    {
+        Ok(true) => {}
+        Ok(false) => {
+            let response = ErrorResponse {
+                message_type: "error".to_string(),
+                code: ErrorCode::RoomFull,
+                message: "The room has reached its participant limit.".to_string(),
+            };
+            let response_text = serde_json::to_string(&response).unwrap();
+            send_to_user(ws_state, &data.user_id, &response_text).await;
+            send_to_user(ws_state, &data.host.id, &response_text).await;
+            return false;
+        }
+        Err(err) => {
+            send_user_error(ws_state, &data.user_id, err.into()).await;
+            return false;
+        }
+    }
+
+    if let Err(err) = Database::add_participant(db.clone(), room, data.user_id).await {
+        send_user_error(ws_state, &data.user_id, err.into()).await;
+        return false;
+    }
+
+    for participant in &data.participants {
+        let response_to_participants = RequestAcceptedResponseTOParticipants {
+            message_type: "new-participant".to_string(),
+            user_id: data.user_id,
+            username: data.username.clone(),
+            participant: participant.id,
+            host: data.host.clone(),
+        };
+        send_room_event(
+            ws_state,
+            &data.code,
+            &participant.id,
+            &response_to_participants,
+        )
+        .await;
+    }
+    debug!(user_id = %data.user_id, "Announced new participant to the room");
+
+    let response_to_host = RequestAcceptedResponseTOParticipants {
+        message_type: "new-participant".to_string(),
+        user_id: data.user_id,
+        username: data.username.clone(),
+        participant: data.host.id,
+        host: data.host.clone(),
+    };
+    send_room_event(ws_state, &data.code, &data.host.id, &response_to_host).await;
+
+    let mode = match Database::get_room_by_code(db.clone(), &data.code).await {
+        Ok(Some(room)) => {
+            ws_state
+                .metrics
+                .room_participants
+                .observe((room.participants_id.len() + 1) as f64);
+            Some(room.mode)
+        }
+        _ => None,
+    };
+
+    let pending = {
+        let socket_id = ws_state.user_sockets.lock().await.get(&data.user_id).cloned();
+        let mut pending = ws_state.pending.lock().await;
+        socket_id
+            .filter(|socket_id| {
+                pending
+                    .get(socket_id)
+                    .is_some_and(|session| session.room_code == data.code)
+            })
+            .and_then(|socket_id| Some((socket_id, pending.remove(&socket_id)?)))
+    };
+    let token_exp = pending.as_ref().map(|(_, session)| session.token_exp);
+    if let Some((socket_id, session)) = pending {
+        start_session(ws_state, db, socket_id, session).await;
+    }
+    let resume_token = match token_exp {
+        Some(_) => issue_resume_token(ws_state, data.user_id, &data.code).await,
+        None => None,
+    };
+
+    let response = RequestAcceptedResponse {
+        message_type: "participant-joined".to_string(),
+        user_id: data.user_id,
+        username: data.username,
+        participants: data.participants,
+        host: data.host,
+        ice_servers: token_exp.map(|exp| ice_servers(config, &data.user_id.to_hex(), exp)),
+        mode,
+        resume_token,
+    };
+    let response_text = serde_json::to_string(&response).unwrap();
+    send_to_user(ws_state, &data.user_id, &response_text).await;
+
+    info!(user_id = %data.user_id, room = %data.code, "Participant admitted");
+    true
+}
+
+async fn redeem_invite(
+    db: &Arc<Database>,
+    config: &Config,
+    token: &str,
+    code: &str,
+    user: &User,
+) -> Option<ObjectId> {
+    let claims: InviteClaims = verify_invite_token(&config.invite_secret, token).ok()?;
+
+    if claims.room != code {
+        return None;
+    }
+
+    if let Some(email) = &claims.email
+        && *email != user.email.trim().to_lowercase()
+    {
+        return None;
+    }
+
+    let invite_id = ObjectId::parse_str(&claims.jti).ok()?;
+
+    match Database::redeem_invite(db.clone(), invite_id, code).await {
+        Ok(Some(_)) => Some(invite_id),
+        _ => None,
+    }
+}
+
+// Invited users bypass the host's approval, so the server assembles the
+// admission that the host client would otherwise send as `request-accepted`.
+// Returns false if the user was not admitted, so the caller can give back the
+// invite use it redeemed.
+async fn admit_invited_user(
+    db: &Arc<Database>,
+    ws_state: &AppState,
+    config: &Config,
+    room: &Room,
+    user_id: ObjectId,
+    user: User,
+) -> bool {
+    let host = match Database::get_user_by_id(db.clone(), room.host_id).await {
+        Ok(Some(host)) => host,
+        Ok(None) => {
+            send_user_error(ws_state, &user_id, AppError::NotFound("Host not found.")).await;
+            return false;
+        }
+        Err(err) => {
+            send_user_error(ws_state, &user_id, err.into()).await;
+            return false;
+        }
+    };
+
+    let mut participants = vec![];
+    for participant_id in room.participants_id.iter().filter(|id| **id != user_id) {
+        if let Ok(Some(participant)) = Database::get_user_by_id(db.clone(), *participant_id).await {
+            participants.push(Participant {
+                username: participant.username,
+                id: *participant_id,
+                video: false,
+            });
+        }
+    }
+
+    let data = RequestAcceptedData {
+        username: user.username,
+        user_id,
+        participants,
+        code: room.code.clone(),
+        host: Host {
+            username: host.username,
+            id: room.host_id,
+            video: false,
+            screen: false,
+        },
+    };
+
+    admit_participant(db, ws_state, config, room, data).await
+}
+
+// Returns false if the room was not being recorded.
+pub(crate) async fn stop_recording(ws_state: &AppState, sfu: &Sfu, room: &Room) -> bool {
+    let Some(recording) = sfu.finish_recording(&room.code).await else {
+        return false;
+    };
+
+    // Mesh rooms only connected to the server to be recorded.
+    if room.mode == RoomMode::Mesh {
+        sfu.close_room(ws_state, &room.code).await;
+    }
+
+    let response = RecordingResponse {
+        message_type: "recording-stopped".to_string(),
+        recording_id: recording.id,
+    };
+    broadcast(ws_state, room, &response).await;
+
+    true
+}
+
+pub async fn handler(
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    State(state): State<SharedState>,
+) -> Response {
+    if state.lifecycle.is_draining() {
+        return StatusCode::SERVICE_UNAVAILABLE.into_response();
+    }
+
+    ws.protocols([MSGPACK_PROTOCOL])
+        .on_upgrade(move |socket| handle_socket(socket, addr.ip(), state))
+}
+
+async fn handle_socket(socket: WebSocket, ip: IpAddr, state: SharedState) {
+    let encoding = Encoding::from_protocol(socket.protocol().and_then(|value| value.to_str().ok()));
     let (sender, receiver) = socket.split();
-    let db = state.db.clone();
-    let ws_state = state.ws_state.clone();
 
     let socket_id = Uuid::new_v4();
 
-    {
-        let mut sockets = ws_state.sockets.lock().await;
-        sockets.insert(socket_id, Arc::new(Mutex::new(sender)));
+    if encoding != Encoding::Json {
+        let mut encodings = state.ws_state.encodings.lock().await;
+        encodings.insert(socket_id, encoding);
     }
 
-    task::spawn(handle_rooms(receiver, socket_id, db, ws_state));
+    {
+        let mut sockets = state.ws_state.sockets.lock().await;
+        sockets.insert(socket_id, Arc::new(Mutex::new(Box::pin(sender))));
+    }
+    state.ws_state.metrics.ws_connections.inc();
+
+    // `user_id` and `room` are filled in once the socket joins a room.
+    let span = info_span!(
+        "ws_connection",
+        %socket_id,
+        user_id = field::Empty,
+        room = field::Empty
+    );
+    task::spawn(handle_rooms(receiver, socket_id, ip, encoding, state).instrument(span));
 }
 
 async fn handle_rooms(
     mut receiver: SplitStream<WebSocket>,
     socket_id: Uuid,
-    db: Arc<Database>,
-    ws_state: Arc<AppState>,
+    ip: IpAddr,
+    encoding: Encoding,
+    state: SharedState,
 ) {
-    while let Some(result) = receiver.next().await {
-        match result {
-            Ok(Message::Text(text)) => {
-                if let Ok(json) = serde_json::from_str::<serde_json::Value>(&text) {
-                    if let Some(message_type) = json["type"].as_str() {
-                        match message_type {
-                            "join-room" => {
-                                let data: JoinRoomData =
-                                    match serde_json::from_value(json["data"].clone()) {
-                                        Ok(d) => d,
-                                        Err(_) => continue,
-                                    };
-
-                                let token = data.access_token;
-
-                                let claim: AccessClaims = match verify_access_token(&token) {
-                                    Ok(claim) => claim,
-                                    Err(_) => continue,
-                                };
-
-                                let oid: ObjectId = match ObjectId::parse_str(&claim.sub) {
-                                    Ok(id) => id,
-                                    Err(_) => continue,
-                                };
-
-                                {
-                                    let mut user_sockets = ws_state.user_sockets.lock().await;
-                                    user_sockets.insert(oid, socket_id);
-                                }
-
-                                let room: Room = match Database::get_room_by_code(
-                                    db.clone(),
-                                    &data.code,
-                                )
-                                .await
-                                {
-                                    Ok(Some(room)) => room,
-                                    _ => continue,
-                                };
-
-                                let user: User =
-                                    match Database::get_user_by_id(db.clone(), oid).await {
-                                        Ok(Some(user)) => user,
-                                        _ => continue,
-                                    };
-
-                                let response: JoinRoomResponse;
-                                let host_id = if oid == room.host_id {
-                                    response = JoinRoomResponse {
-                                        message_type: "host-joined".to_string(),
-                                        user_id: oid,
-                                        username: user.username,
-                                    };
-                                    oid
-                                } else {
-                                    let host =
-                                        match Database::get_user_by_id(db.clone(), room.host_id)
-                                            .await
-                                        {
-                                            Ok(Some(host)) => host,
-                                            _ => continue,
-                                        };
-
-                                    let host_id = match host._id {
-                                        Some(id) => id,
-                                        None => continue,
-                                    };
-
-                                    response = JoinRoomResponse {
-                                        message_type: "join-request".to_string(),
-                                        user_id: oid,
-                                        username: user.username,
-                                    };
-                                    host_id
-                                };
-
-                                let response_text = serde_json::to_string(&response).unwrap();
-
-                                let sender_id = {
-                                    let user_sockets = ws_state.user_sockets.lock().await;
-                                    user_sockets.get(&host_id).cloned()
-                                };
-
-                                if let Some(sender_id) = sender_id {
-                                    let sender_arc = {
-                                        let sockets = ws_state.sockets.lock().await;
-                                        sockets.get(&sender_id).cloned()
-                                    };
-
-                                    if let Some(sender_arc) = sender_arc {
-                                        let mut sender = sender_arc.lock().await;
-                                        if let Err(err) =
-                                            sender.send(Message::Text(response_text.into())).await
-                                        {
-                                            eprintln!(
-                                                "Failed to send message to user {}: {}",
-                                                sender_id, err
-                                            );
-                                        }
-                                    }
-                                }
-
-                                println!("data send to user");
-                            }
-                            "request-accepted" => {
-                                println!("request recieved");
-                                let data: RequestAcceptedData =
-                                    match serde_json::from_value(json["data"].clone()) {
-                                        Ok(d) => d,
-                                        Err(_) => continue,
-                                    };
-
-                                if let Err(_) = Database::add_participant_to_room(
-                                    db.clone(),
-                                    &data.code,
-                                    data.user_id,
-                                )
-                                .await
-                                {
-                                    continue;
-                                }
-
-                                if let Err(_) = Database::add_participant(
-                                    db.clone(),
-                                    data.code.clone(),
-                                    data.user_id,
-                                )
-                                .await
-                                {
-                                    continue;
-                                }
-
-                                let user_sockets = ws_state.user_sockets.lock().await.clone();
-
-                                for participant in &data.participants {
-                                    let response_to_participants =
-                                        RequestAcceptedResponseTOParticipants {
-                                            message_type: "new-participant".to_string(),
-                                            user_id: data.user_id,
-                                            username: data.username.clone(),
-                                            participant: participant.id,
-                                            host: data.host.clone(),
-                                        };
-                                    let response_to_participant_text =
-                                        serde_json::to_string(&response_to_participants).unwrap();
-                                    if let Some(sender_id) = user_sockets.get(&participant.id) {
-                                        let sender_arc = {
-                                            let sockets = ws_state.sockets.lock().await;
-                                            sockets.get(sender_id).cloned()
-                                        };
-
-                                        if let Some(sender_arc) = sender_arc {
-                                            let mut sender = sender_arc.lock().await;
-                                            if let Err(err) = sender
-                                                .send(Message::Text(
-                                                    response_to_participant_text.clone().into(),
-                                                ))
-                                                .await
-                                            {
-                                                eprintln!(
-                                                    "Failed to send message to user {}: {}",
-                                                    sender_id, err
-                                                );
-                                            }
-                                        }
-                                    }
-                                }
-                                println!("response sent to participants");
-
-                                let response_to_host = RequestAcceptedResponseTOParticipants {
-                                    message_type: "new-participant".to_string(),
-                                    user_id: data.user_id,
-                                    username: data.username.clone(),
-                                    participant: data.host.id,
-                                    host: data.host.clone(),
-                                };
-                                let response_to_host_text =
-                                    serde_json::to_string(&response_to_host).unwrap();
-
-                                if let Some(sender_id) = user_sockets.get(&data.host.id) {
-                                    let sender_arc = {
-                                        let sockets = ws_state.sockets.lock().await;
-                                        sockets.get(sender_id).cloned()
-                                    };
-
-                                    if let Some(sender_arc) = sender_arc {
-                                        let mut sender = sender_arc.lock().await;
-                                        if let Err(err) = sender
-                                            .send(Message::Text(
-                                                response_to_host_text.clone().into(),
-                                            ))
-                                            .await
-                                        {
-                                            eprintln!(
-                                                "Failed to send message to user {}: {}",
-                                                sender_id, err
-                                            );
-                                        }
-                                    }
-                                }
-
-                                let response = RequestAcceptedResponse {
-                                    message_type: "participant-joined".to_string(),
-                                    user_id: data.user_id,
-                                    username: data.username.clone(),
-                                    participants: data.participants.clone(),
-                                    host: data.host.clone(),
-                                };
-                                let response_text = serde_json::to_string(&response).unwrap();
-
-                                if let Some(sender_id) = user_sockets.get(&data.user_id) {
-                                    let sender_arc = {
-                                        let sockets = ws_state.sockets.lock().await;
-                                        sockets.get(sender_id).cloned()
-                                    };
-
-                                    if let Some(sender_arc) = sender_arc {
-                                        let mut sender = sender_arc.lock().await;
-                                        if let Err(err) =
-                                            sender.send(Message::Text(response_text.into())).await
-                                        {
-                                            eprintln!(
-                                                "Failed to send message to user {}: {}",
-                                                sender_id, err
-                                            );
-                                        }
-                                    }
-                                }
-
-                                println!("response sent")
-                            }
-                            "offer" | "answer" | "ice-candidate" => {
-                                let data: RtcConnectionData =
-                                    match serde_json::from_value(json["data"].clone()) {
-                                        Ok(d) => d,
-                                        Err(_) => {
-                                            println!("err");
-                                            continue;
-                                        }
-                                    };
-
-                                let response = RtcConnectionResponse {
-                                    message_type: message_type.to_string(),
-                                    item: data.item,
-                                    from: data.user_id,
-                                    user_id: data.to,
-                                };
-                                let response_text = serde_json::to_string(&response).unwrap();
-
-                                let sender_id = {
-                                    let user_sockets = ws_state.user_sockets.lock().await;
-                                    user_sockets.get(&data.to).cloned()
-                                };
-
-                                if let Some(sender_id) = sender_id {
-                                    let sender_arc = {
-                                        let sockets = ws_state.sockets.lock().await;
-                                        sockets.get(&sender_id).cloned()
-                                    };
-
-                                    if let Some(sender_arc) = sender_arc {
-                                        let mut sender = sender_arc.lock().await;
-                                        if let Err(err) =
-                                            sender.send(Message::Text(response_text.into())).await
-                                        {
-                                            eprintln!(
-                                                "Failed to send message to user {}: {}",
-                                                sender_id, err
-                                            );
-                                        }
-                                    }
-                                }
-                            }
-
-                            "mouse-move" => {
-                                let data: MouseMoveData =
-                                    match serde_json::from_value(json["data"].clone()) {
-                                        Ok(d) => d,
-                                        Err(_) => {
-                                            println!("err");
-                                            continue;
-                                        }
-                                    };
-
-                                let response: MouseMoveResponse = MouseMoveResponse {
-                                    message_type: "mouse-move".to_string(),
-                                    x: data.x,
-                                    y: data.y,
-                                };
-
-                                let response_text = serde_json::to_string(&response).unwrap();
-
-                                let sender_id = {
-                                    let user_sockets = ws_state.user_sockets.lock().await;
-                                    user_sockets.get(&data.to).cloned()
-                                };
-
-                                if let Some(sender_id) = sender_id {
-                                    let sender_arc = {
-                                        let sockets = ws_state.sockets.lock().await;
-                                        sockets.get(&sender_id).cloned()
-                                    };
-
-                                    if let Some(sender_arc) = sender_arc {
-                                        let mut sender = sender_arc.lock().await;
-                                        if let Err(err) =
-                                            sender.send(Message::Text(response_text.into())).await
-                                        {
-                                            eprintln!(
-                                                "Failed to send message to user {}: {}",
-                                                sender_id, err
-                                            );
-                                        }
-                                    }
-                                }
-                            }
-
-                            "key-press" => {
-                                let data: KeyPressData =
-                                    match serde_json::from_value(json["data"].clone()) {
-                                        Ok(d) => d,
-                                        Err(_) => {
-                                            println!("err");
-                                            continue;
-                                        }
-                                    };
-
-                                let response: KeyPressResponse = KeyPressResponse {
-                                    message_type: "key-press".to_string(),
-                                    key: data.key,
-                                };
-
-                                let response_text = serde_json::to_string(&response).unwrap();
-
-                                let sender_id = {
-                                    let user_sockets = ws_state.user_sockets.lock().await;
-                                    user_sockets.get(&data.to).cloned()
-                                };
-
-                                if let Some(sender_id) = sender_id {
-                                    let sender_arc = {
-                                        let sockets = ws_state.sockets.lock().await;
-                                        sockets.get(&sender_id).cloned()
-                                    };
-
-                                    if let Some(sender_arc) = sender_arc {
-                                        let mut sender = sender_arc.lock().await;
-                                        if let Err(err) =
-                                            sender.send(Message::Text(response_text.into())).await
-                                        {
-                                            eprintln!(
-                                                "Failed to send message to user {}: {}",
-                                                sender_id, err
-                                            );
-                                        }
-                                    }
-                                }
-                            }
-
-                            "mouse-click" => {
-                                let data: MouseClickData =
-                                    match serde_json::from_value(json["data"].clone()) {
-                                        Ok(d) => d,
-                                        Err(_) => {
-                                            println!("err");
-                                            continue;
-                                        }
-                                    };
-
-                                let response: MouseClickResponse = MouseClickResponse {
-                                    message_type: "mouse-click".to_string(),
-                                };
-
-                                let response_text = serde_json::to_string(&response).unwrap();
-
-                                let sender_id = {
-                                    let user_sockets = ws_state.user_sockets.lock().await;
-                                    user_sockets.get(&data.to).cloned()
-                                };
-
-                                if let Some(sender_id) = sender_id {
-                                    let sender_arc = {
-                                        let sockets = ws_state.sockets.lock().await;
-                                        sockets.get(&sender_id).cloned()
-                                    };
-
-                                    if let Some(sender_arc) = sender_arc {
-                                        let mut sender = sender_arc.lock().await;
-                                        if let Err(err) =
-                                            sender.send(Message::Text(response_text.into())).await
-                                        {
-                                            eprintln!(
-                                                "Failed to send message to user {}: {}",
-                                                sender_id, err
-                                            );
-                                        }
-                                    }
-                                }
-                            }
-
-                            "message" => {
-                                let data: MessageData =
-                                    match serde_json::from_value(json["data"].clone()) {
-                                        Ok(d) => d,
-                                        Err(_) => {
-                                            println!("err");
-                                            continue;
-                                        }
-                                    };
-
-                                let room: Room = match Database::get_room_by_code(
-                                    db.clone(),
-                                    &data.code,
-                                )
-                                .await
-                                {
-                                    Ok(Some(room)) => room,
-                                    _ => continue,
-                                };
-
-                                let response: MessageResponse = MessageResponse {
-                                    message_type: "message".to_string(),
-                                    message: data.message,
-                                    username: data.username,
-                                    id: data.id,
-                                };
-
-                                let response_text = serde_json::to_string(&response).unwrap();
-
-                                let user_sockets = ws_state.user_sockets.lock().await.clone();
-
-                                for participant in &room.participants_id {
-                                    if let Some(sender_id) = user_sockets.get(&participant) {
-                                        let sender_arc = {
-                                            let sockets = ws_state.sockets.lock().await;
-                                            sockets.get(sender_id).cloned()
-                                        };
-
-                                        if let Some(sender_arc) = sender_arc {
-                                            let mut sender = sender_arc.lock().await;
-                                            if let Err(err) = sender
-                                                .send(Message::Text(response_text.clone().into()))
-                                                .await
-                                            {
-                                                eprintln!(
-                                                    "Failed to send message to user {}: {}",
-                                                    sender_id, err
-                                                );
-                                            }
-                                        }
-                                    }
-                                }
-
-                                if let Some(sender_id) = user_sockets.get(&room.host_id) {
-                                    let sender_arc = {
-                                        let sockets = ws_state.sockets.lock().await;
-                                        sockets.get(sender_id).cloned()
-                                    };
-
-                                    if let Some(sender_arc) = sender_arc {
-                                        let mut sender = sender_arc.lock().await;
-                                        if let Err(err) =
-                                            sender.send(Message::Text(response_text.into())).await
-                                        {
-                                            eprintln!(
-                                                "Failed to send message to user {}: {}",
-                                                sender_id, err
-                                            );
-                                        }
-                                    }
-                                }
-                            }
-                            "screen-sharing-started" | "screen-sharing-stopped" => {
-                                let data: VideoData =
-                                    match serde_json::from_value(json["data"].clone()) {
-                                        Ok(d) => d,
-                                        Err(_) => {
-                                            println!("err");
-                                            continue;
-                                        }
-                                    };
-
-                                let room: Room = match Database::get_room_by_code(
-                                    db.clone(),
-                                    &data.code,
-                                )
-                                .await
-                                {
-                                    Ok(Some(room)) => room,
-                                    _ => continue,
-                                };
-                                let response: VideoResponse = VideoResponse {
-                                    message_type: message_type.to_string(),
-                                    user_id: data.user_id,
-                                    host: data.host,
-                                };
-
-                                let response_text = serde_json::to_string(&response).unwrap();
-
-                                let user_sockets = ws_state.user_sockets.lock().await.clone();
-
-                                for participant in &room.participants_id {
-                                    if let Some(sender_id) = user_sockets.get(&participant) {
-                                        let sender_arc = {
-                                            let sockets = ws_state.sockets.lock().await;
-                                            sockets.get(sender_id).cloned()
-                                        };
-
-                                        if let Some(sender_arc) = sender_arc {
-                                            let mut sender = sender_arc.lock().await;
-                                            if let Err(err) = sender
-                                                .send(Message::Text(response_text.clone().into()))
-                                                .await
-                                            {
-                                                eprintln!(
-                                                    "Failed to send message to user {}: {}",
-                                                    sender_id, err
-                                                );
-                                            }
-                                        }
-                                    }
-                                }
-
-                                if let Some(sender_id) = user_sockets.get(&room.host_id) {
-                                    let sender_arc = {
-                                        let sockets = ws_state.sockets.lock().await;
-                                        sockets.get(sender_id).cloned()
-                                    };
-
-                                    if let Some(sender_arc) = sender_arc {
-                                        let mut sender = sender_arc.lock().await;
-                                        if let Err(err) =
-                                            sender.send(Message::Text(response_text.into())).await
-                                        {
-                                            eprintln!(
-                                                "Failed to send message to user {}: {}",
-                                                sender_id, err
-                                            );
-                                        }
-                                    }
-                                }
-                            }
-
-                            "video-started" | "video-stopped" => {
-                                let data: VideoData =
-                                    match serde_json::from_value(json["data"].clone()) {
-                                        Ok(d) => d,
-                                        Err(_) => {
-                                            println!("err");
-                                            continue;
-                                        }
-                                    };
-
-                                let room: Room = match Database::get_room_by_code(
-                                    db.clone(),
-                                    &data.code,
-                                )
-                                .await
-                                {
-                                    Ok(Some(room)) => room,
-                                    _ => continue,
-                                };
-                                let response: VideoResponse = VideoResponse {
-                                    message_type: message_type.to_string(),
-                                    user_id: data.user_id,
-                                    host: data.host,
-                                };
-
-                                let response_text = serde_json::to_string(&response).unwrap();
-
-                                let user_sockets = ws_state.user_sockets.lock().await.clone();
-
-                                for participant in &room.participants_id {
-                                    if let Some(sender_id) = user_sockets.get(&participant) {
-                                        let sender_arc = {
-                                            let sockets = ws_state.sockets.lock().await;
-                                            sockets.get(sender_id).cloned()
-                                        };
-
-                                        if let Some(sender_arc) = sender_arc {
-                                            let mut sender = sender_arc.lock().await;
-                                            if let Err(err) = sender
-                                                .send(Message::Text(response_text.clone().into()))
-                                                .await
-                                            {
-                                                eprintln!(
-                                                    "Failed to send message to user {}: {}",
-                                                    sender_id, err
-                                                );
-                                            }
-                                        }
-                                    }
-                                }
-
-                                if let Some(sender_id) = user_sockets.get(&room.host_id) {
-                                    let sender_arc = {
-                                        let sockets = ws_state.sockets.lock().await;
-                                        sockets.get(sender_id).cloned()
-                                    };
-
-                                    if let Some(sender_arc) = sender_arc {
-                                        let mut sender = sender_arc.lock().await;
-                                        if let Err(err) =
-                                            sender.send(Message::Text(response_text.into())).await
-                                        {
-                                            eprintln!(
-                                                "Failed to send message to user {}: {}",
-                                                sender_id, err
-                                            );
-                                        }
-                                    }
-                                }
-                            }
-
-                            "leave-room" => {
-                                let data: LeaveRoomData =
-                                    match serde_json::from_value(json["data"].clone()) {
-                                        Ok(d) => d,
-                                        Err(_) => {
-                                            println!("err");
-                                            continue;
-                                        }
-                                    };
-
-                                let room: Room = match Database::get_room_by_code(
-                                    db.clone(),
-                                    &data.code,
-                                )
-                                .await
-                                {
-                                    Ok(Some(room)) => room,
-                                    _ => continue,
-                                };
-
-                                let response_text: String;
-
-                                if data.user_id == room.host_id {
-                                    if room.participants_id.len() == 0 {
-                                        match Database::delete_room(db.clone(), &data.code).await {
-                                            Ok(_) => println!("Room deleted"),
-                                            Err(_) => continue,
-                                        };
-                                        continue;
-                                    }
-                                    let user_id = room.participants_id[0];
-                                    match Database::remove_participant_from_room(
-                                        db.clone(),
-                                        &data.code,
-                                        user_id,
-                                    )
-                                    .await
-                                    {
-                                        Ok(_) => println!("Participant removed"),
-                                        Err(_) => continue,
-                                    };
-                                    match Database::update_host_id(db.clone(), &data.code, user_id)
-                                        .await
-                                    {
-                                        Ok(_) => println!("Host changed"),
-                                        Err(_) => continue,
-                                    };
-                                    let user: User =
-                                        match Database::get_user_by_id(db.clone(), user_id).await {
-                                            Ok(Some(user)) => user,
-                                            _ => continue,
-                                        };
-                                    let response = HostLeftresponse {
-                                        message_type: "host-left".to_string(),
-                                        host: user_id,
-                                        username: user.username,
-                                    };
-
-                                    response_text = serde_json::to_string(&response).unwrap();
-                                } else {
-                                    match Database::remove_participant_from_room(
-                                        db.clone(),
-                                        &data.code,
-                                        data.user_id,
-                                    )
-                                    .await
-                                    {
-                                        Ok(_) => println!("Participant removed"),
-                                        Err(_) => continue,
-                                    };
-
-                                    let response = ParticipantLeft {
-                                        message_type: "participant-left".to_string(),
-                                        user: data.user_id,
-                                    };
-
-                                    response_text = serde_json::to_string(&response).unwrap();
-                                }
-
-                                let user_sockets = ws_state.user_sockets.lock().await.clone();
-
-                                for participant in &room.participants_id {
-                                    if let Some(sender_id) = user_sockets.get(&participant) {
-                                        let sender_arc = {
-                                            let sockets = ws_state.sockets.lock().await;
-                                            sockets.get(sender_id).cloned()
-                                        };
-
-                                        if let Some(sender_arc) = sender_arc {
-                                            let mut sender = sender_arc.lock().await;
-                                            if let Err(err) = sender
-                                                .send(Message::Text(response_text.clone().into()))
-                                                .await
-                                            {
-                                                eprintln!(
-                                                    "Failed to send message to user {}: {}",
-                                                    sender_id, err
-                                                );
-                                            }
-                                        }
-                                    }
-                                }
-
-                                if let Some(sender_id) = user_sockets.get(&room.host_id) {
-                                    let sender_arc = {
-                                        let sockets = ws_state.sockets.lock().await;
-                                        sockets.get(sender_id).cloned()
-                                    };
-
-                                    if let Some(sender_arc) = sender_arc {
-                                        let mut sender = sender_arc.lock().await;
-                                        if let Err(err) =
-                                            sender.send(Message::Text(response_text.into())).await
-                                        {
-                                            eprintln!(
-                                                "Failed to send message to user {}: {}",
-                                                sender_id, err
-                                            );
-                                        }
-                                    }
-                                }
-                            }
-                            "request-access" => {
-                                let data: RequestAccessData =
-                                    match serde_json::from_value(json["data"].clone()) {
-                                        Ok(d) => d,
-                                        Err(_) => {
-                                            println!("err");
-                                            continue;
-                                        }
-                                    };
-
-                                let response: RequestAccessResponse = RequestAccessResponse {
-                                    message_type: "request-access".to_string(),
-                                    user_id: data.from,
-                                    username: data.username,
-                                };
-
-                                let response_text = serde_json::to_string(&response).unwrap();
-
-                                let user_sockets = ws_state.user_sockets.lock().await.clone();
-
-                                if let Some(sender_id) = user_sockets.get(&data.to) {
-                                    let sender_arc = {
-                                        let sockets = ws_state.sockets.lock().await;
-                                        sockets.get(sender_id).cloned()
-                                    };
-
-                                    if let Some(sender_arc) = sender_arc {
-                                        let mut sender = sender_arc.lock().await;
-                                        if let Err(err) =
-                                            sender.send(Message::Text(response_text.into())).await
-                                        {
-                                            eprintln!(
-                                                "Failed to send message to user {}: {}",
-                                                sender_id, err
-                                            );
-                                        }
-                                    }
-                                }
-                            }
-
-                            "allowed-access" => {
-                                let data: AccessData =
-                                    match serde_json::from_value(json["data"].clone()) {
-                                        Ok(d) => d,
-                                        Err(_) => {
-                                            println!("err");
-                                            continue;
-                                        }
-                                    };
-
-                                let room: Room = match Database::get_room_by_code(
-                                    db.clone(),
-                                    &data.code,
-                                )
-                                .await
-                                {
-                                    Ok(Some(room)) => room,
-                                    _ => continue,
-                                };
-
-                                let response: AccessResponse = AccessResponse {
-                                    message_type: "allowed-access".to_string(),
-                                    user_id: data.user_id,
-                                    username: data.username,
-                                };
-                                let response_text = serde_json::to_string(&response).unwrap();
-                                let user_sockets = ws_state.user_sockets.lock().await.clone();
-
-                                for participant in &room.participants_id {
-                                    if let Some(sender_id) = user_sockets.get(&participant) {
-                                        let sender_arc = {
-                                            let sockets = ws_state.sockets.lock().await;
-                                            sockets.get(sender_id).cloned()
-                                        };
-
-                                        if let Some(sender_arc) = sender_arc {
-                                            let mut sender = sender_arc.lock().await;
-                                            if let Err(err) = sender
-                                                .send(Message::Text(response_text.clone().into()))
-                                                .await
-                                            {
-                                                eprintln!(
-                                                    "Failed to send message to user {}: {}",
-                                                    sender_id, err
-                                                );
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-
-                            "rejected-access" => {
-                                let data: AccessData =
-                                    match serde_json::from_value(json["data"].clone()) {
-                                        Ok(d) => d,
-                                        Err(_) => {
-                                            println!("err");
-                                            continue;
-                                        }
-                                    };
-
-                                let response: AccessResponse = AccessResponse {
-                                    message_type: "allowed-access".to_string(),
-                                    user_id: data.user_id,
-                                    username: data.username,
-                                };
-                                let response_text = serde_json::to_string(&response).unwrap();
-
-                                let user_sockets = ws_state.user_sockets.lock().await.clone();
-
-                                if let Some(sender_id) = user_sockets.get(&data.user_id) {
-                                    let sender_arc = {
-                                        let sockets = ws_state.sockets.lock().await;
-                                        sockets.get(sender_id).cloned()
-                                    };
-
-                                    if let Some(sender_arc) = sender_arc {
-                                        let mut sender = sender_arc.lock().await;
-                                        if let Err(err) =
-                                            sender.send(Message::Text(response_text.into())).await
-                                        {
-                                            eprintln!(
-                                                "Failed to send message to user {}: {}",
-                                                sender_id, err
-                                            );
-                                        }
-                                    }
-                                }
-                            }
-                            _ => continue,
-                        }
-                    }
+    let db = state.db.clone();
+    let ws_state = state.ws_state.clone();
+    let config = state.config.clone();
+    let sfu = state.sfu.clone();
+    let mut conn = Connection {
+        socket_id,
+        ip,
+        span: Span::current(),
+        input_limit: TokenBucket::new(config.input_rate_per_sec, config.input_burst),
+    };
+
+    // Any inbound frame counts as a sign of life. Browsers cannot answer
+    // protocol pings themselves, so they send `heartbeat` messages instead.
+    let mut ping = tokio::time::interval(config.ping_interval);
+    ping.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut missed_pongs = 0;
+    let mut last_seen = Utc::now();
+
+    loop {
+        let result = tokio::select! {
+            result = receiver.next() => match result {
+                Some(result) => result,
+                None => break,
+            },
+            _ = ping.tick() => {
+                if missed_pongs >= config.max_missed_pongs {
+                    info!("Closing unresponsive socket");
+                    break;
+                }
+                missed_pongs += 1;
+                ws_state.last_seen.lock().await.insert(socket_id, last_seen);
+
+                // Presence entries expire unless refreshed.
+                if let Some((user_id, _)) = session_identity(&ws_state, &socket_id).await
+                    && ws_state.user_sockets.lock().await.get(&user_id) == Some(&socket_id)
+                {
+                    set_online(&ws_state, user_id).await;
                 }
+                send_message(&ws_state, &socket_id, Message::Ping(Default::default())).await;
+                continue;
+            }
+        };
+
+        missed_pongs = 0;
+        last_seen = Utc::now();
+
+        let frame = match result {
+            Ok(Message::Text(text)) => serde_json::from_str::<serde_json::Value>(&text).ok(),
+            Ok(Message::Binary(bytes)) if encoding == Encoding::MessagePack => {
+                codec::decode(&bytes)
+            }
+            Ok(Message::Close(_)) | Err(_) => break,
+            _ => None,
+        };
+
+        match frame {
+            Some(json) => {
+                let Some(message_type) = json["type"].as_str() else {
+                    continue;
+                };
+
+                ws_state
+                    .metrics
+                    .ws_messages
+                    .with_label_values(&[message_label(message_type)])
+                    .inc();
+                // Observed when dropped, however the arm below exits.
+                let _join_timer = match message_type {
+                    "join-room" | "request-accepted" | "resume" => Some(
+                        ws_state
+                            .metrics
+                            .ws_join_duration
+                            .with_label_values(&[message_type])
+                            .start_timer(),
+                    ),
+                    _ => None,
+                };
+
+                // Built here rather than by `#[instrument]` so the client's trace
+                // context can be attached before the span is first entered.
+                let span = info_span!("ws_message", message_type = message_label(message_type));
+                telemetry::continue_frame_trace(&span, &json);
+                handle_message(&state, &mut conn, last_seen, message_type, &json)
+                    .instrument(span)
+                    .await;
             }
             _ => continue,
         }
     }
+
+    // A guest who was never admitted has no presence or resume state to
+    // clean up.
+    let waiting = ws_state.pending.lock().await.remove(&socket_id).is_some();
+    let session = ws_state.sessions.lock().await.remove(&socket_id);
+    let live = session.is_some();
+    if let Some(session) = session {
+        sfu.remove_peer(&ws_state, &session.room_code, session.user_id)
+            .await;
+        release_access(&ws_state, &db, session.user_id).await;
+
+        // Nothing changes for the room while the user is still connected on
+        // another socket.
+        let current = ws_state
+            .user_sockets
+            .lock()
+            .await
+            .get(&session.user_id)
+            .cloned();
+        if current.is_none_or(|id| id == socket_id) {
+            if let Err(err) = ws_state
+                .broker
+                .suspend_resume_token(session.user_id, &session.room_code)
+                .await
+            {
+                warn!(error = %err, "Failed to suspend the resume token");
+            }
+
+            if let Ok(Some(room)) = Database::get_room_by_code(db.clone(), &session.room_code).await
+            {
+                let response = PresenceResponse {
+                    message_type: "presence".to_string(),
+                    members: vec![PresenceEntry {
+                        user_id: session.user_id,
+                        online: false,
+                        last_seen: Some(last_seen),
+                    }],
+                };
+                let response_text = serde_json::to_string(&response).unwrap();
+                send_to_room(&ws_state, &room, &response_text).await;
+
+                // Only the host can stop a recording, so it must not outlive
+                // their connection.
+                if room.host_id == session.user_id {
+                    stop_recording(&ws_state, &sfu, &room).await;
+                }
+            }
+        }
+
+        audit_session_end(&db, session);
+    }
+
+    ws_state.last_seen.lock().await.remove(&socket_id);
+    ws_state.encodings.lock().await.remove(&socket_id);
+    let queue = ws_state.input_queues.lock().await.remove(&socket_id);
+    if let Some(queue) = queue {
+        queue.close();
+    }
+
+    ws_state.sockets.lock().await.remove(&socket_id);
+    ws_state.metrics.ws_connections.dec();
+
+    let mut offline = vec![];
+    ws_state.user_sockets.lock().await.retain(|user_id, id| {
+        if *id == socket_id {
+            offline.push(*user_id);
+            return false;
+        }
+        true
+    });
+    if waiting && !live {
+        offline.clear();
+    }
+    for user_id in offline {
+        if let Err(err) = ws_state
+            .broker
+            .set_offline(user_id, ws_state.instance_id)
+            .await
+        {
+            warn!(%user_id, error = %err, "Failed to clear presence");
+        }
+    }
+}
+
+// What the message handlers need to know about, or update on, their socket.
+struct Connection {
+    socket_id: Uuid,
+    ip: IpAddr,
+    // The `ws_connection` span, which records who joined which room.
+    span: Span,
+    input_limit: TokenBucket,
+}
+
+// Handles one client frame. Returning early drops the frame; the socket stays
+// open either way.
+async fn handle_message(
+    state: &SharedState,
+    conn: &mut Connection,
+    last_seen: DateTime<Utc>,
+    message_type: &str,
+    json: &serde_json::Value,
+) {
+    let db = state.db.clone();
+    let ws_state = state.ws_state.clone();
+    let config = state.config.clone();
+    let sfu = state.sfu.clone();
+    let socket_id = conn.socket_id;
+    let ip = conn.ip;
+    let connection = &conn.span;
+    let input_limit = &mut conn.input_limit;
+
+    match message_type {
+        "heartbeat" => {
+            ws_state.last_seen.lock().await.insert(socket_id, last_seen);
+
+            let response = HeartbeatResponse {
+                message_type: "heartbeat".to_string(),
+                server_time: last_seen,
+            };
+            let response_text = serde_json::to_string(&response).unwrap();
+            send_to_socket(&ws_state, &socket_id, &response_text).await;
+        }
+
+        "presence" => {
+            let Some((user_id, room_code)) = session_identity(&ws_state, &socket_id).await else {
+                return;
+            };
+
+            let room = match Database::get_room_by_code(db.clone(), &room_code).await {
+                Ok(Some(room)) if is_member(&room, &user_id) => room,
+                _ => return,
+            };
+
+            let member_ids: Vec<ObjectId> = std::iter::once(room.host_id)
+                .chain(room.participants_id.iter().copied())
+                .collect();
+            let online = match ws_state.broker.online(&member_ids).await {
+                Ok(online) => online,
+                Err(err) => {
+                    warn!(error = %err, "Failed to look up presence");
+                    HashSet::new()
+                }
+            };
+
+            let mut members = vec![];
+            for member_id in member_ids {
+                members.push(presence_of(&ws_state, member_id, &online).await);
+            }
+
+            let response = PresenceResponse {
+                message_type: "presence".to_string(),
+                members,
+            };
+            let response_text = serde_json::to_string(&response).unwrap();
+            send_to_socket(&ws_state, &socket_id, &response_text).await;
+        }
+
+        "join-room" => {
+            let data: JoinRoomData = match serde_json::from_value(json["data"].clone()) {
+                Ok(d) => d,
+                Err(_) => {
+                    let err = AppError::Validation("Malformed join-room message.".to_string());
+                    send_app_error(&ws_state, &socket_id, err).await;
+                    return;
+                }
+            };
+            if !is_valid_room_code(&data.code) {
+                let err = AppError::Validation(INVALID_ROOM_CODE.to_string());
+                send_app_error(&ws_state, &socket_id, err).await;
+                return;
+            }
+
+            let token = data.access_token;
+
+            let (oid, claim) = match api::authenticate(db.clone(), &token).await {
+                Ok(session) => session,
+                Err(err) => {
+                    send_app_error(&ws_state, &socket_id, err).await;
+                    return;
+                }
+            };
+            connection.record("user_id", field::display(oid));
+            connection.record("room", data.code.as_str());
+
+            // Joining is what opens the current occurrence of a series.
+            let room: Room = match Database::open_room(db.clone(), &data.code).await {
+                Ok(Some(room)) => room,
+                Ok(None) => {
+                    let err = AppError::NotFound("Room not found.");
+                    send_app_error(&ws_state, &socket_id, err).await;
+                    return;
+                }
+                Err(err) => {
+                    send_app_error(&ws_state, &socket_id, err.into()).await;
+                    return;
+                }
+            };
+
+            let user: User = match Database::get_user_by_id(db.clone(), oid).await {
+                Ok(Some(user)) => user,
+                Ok(None) => {
+                    let err = AppError::Unauthenticated;
+                    send_app_error(&ws_state, &socket_id, err).await;
+                    return;
+                }
+                Err(err) => {
+                    send_app_error(&ws_state, &socket_id, err.into()).await;
+                    return;
+                }
+            };
+
+            // The session only goes live once the user is admitted.
+            let session = Session {
+                user_id: oid,
+                room_code: data.code.clone(),
+                token_exp: claim.exp,
+                token_id: claim.jti.clone(),
+                mouse_events: 0,
+                key_events: 0,
+            };
+
+            let response: JoinRoomResponse;
+            let host_id = if oid == room.host_id {
+                if room.series_id.is_some()
+                    && let Err(err) = Database::add_participant(db.clone(), &room, oid).await
+                {
+                    send_app_error(&ws_state, &socket_id, err.into()).await;
+                    return;
+                }
+                start_session(&ws_state, &db, socket_id, session).await;
+
+                response = JoinRoomResponse {
+                    message_type: "host-joined".to_string(),
+                    user_id: oid,
+                    username: user.username,
+                    ice_servers: Some(ice_servers(&config, &claim.sub, claim.exp)),
+                    mode: Some(room.mode),
+                    resume_token: issue_resume_token(&ws_state, oid, &data.code).await,
+                };
+                oid
+            } else {
+                if !room.participants_id.contains(&oid)
+                    && room.participants_id.len() + 1 >= config.max_participants_per_room
+                {
+                    send_error(
+                        &ws_state,
+                        &socket_id,
+                        ErrorCode::RoomFull,
+                        "The room has reached its participant limit.",
+                    )
+                    .await;
+                    return;
+                }
+
+                if let Some(invite) = &data.invite
+                    && let Some(invite_id) =
+                        redeem_invite(&db, &config, invite, &data.code, &user).await
+                {
+                    await_admission(&ws_state, socket_id, session).await;
+                    if !admit_invited_user(&db, &ws_state, &config, &room, oid, user).await {
+                        let _ = Database::release_invite(db.clone(), invite_id).await;
+                    }
+                    return;
+                }
+
+                if let Some(hashed) = &room.passcode
+                    && !check_passcode(
+                        &ws_state,
+                        &socket_id,
+                        ip,
+                        oid,
+                        hashed,
+                        data.passcode.clone(),
+                    )
+                    .await
+                {
+                    return;
+                }
+
+                let host = match Database::get_user_by_id(db.clone(), room.host_id).await {
+                    Ok(Some(host)) => host,
+                    Ok(None) => {
+                        let err = AppError::NotFound("Host not found.");
+                        send_app_error(&ws_state, &socket_id, err).await;
+                        return;
+                    }
+                    Err(err) => {
+                        send_app_error(&ws_state, &socket_id, err.into()).await;
+                        return;
+                    }
+                };
+
+                let Some(host_id) = host._id else {
+                    let err = AppError::Internal("host has no id");
+                    send_app_error(&ws_state, &socket_id, err).await;
+                    return;
+                };
+                await_admission(&ws_state, socket_id, session).await;
+
+                response = JoinRoomResponse {
+                    message_type: "join-request".to_string(),
+                    user_id: oid,
+                    username: user.username,
+                    ice_servers: None,
+                    mode: None,
+                    resume_token: None,
+                };
+                host_id
+            };
+
+            // The host's own reply carries its resume token and
+            // must not be replayed.
+            if host_id == oid {
+                let response_text = serde_json::to_string(&response).unwrap();
+                send_to_socket(&ws_state, &socket_id, &response_text).await;
+            } else {
+                send_room_event(&ws_state, &data.code, &host_id, &response).await;
+            }
+
+            debug!("Join handled");
+        }
+        "resume" => {
+            let data: ResumeData = match serde_json::from_value(json["data"].clone()) {
+                Ok(d) => d,
+                Err(_) => {
+                    let err = AppError::Validation("Malformed resume message.".to_string());
+                    send_app_error(&ws_state, &socket_id, err).await;
+                    return;
+                }
+            };
+
+            let session = api::authenticate(db.clone(), &data.access_token).await;
+            let (oid, claim) = match session {
+                Ok(session) => session,
+                Err(err) => {
+                    send_app_error(&ws_state, &socket_id, err).await;
+                    return;
+                }
+            };
+            connection.record("user_id", field::display(oid));
+
+            let ticket = ws_state
+                .broker
+                .redeem_resume_token(&data.resume_token, oid)
+                .await
+                .unwrap_or_else(|err| {
+                    warn!(error = %err, "Failed to redeem the resume token");
+                    None
+                });
+
+            // Membership is checked again since the user may have
+            // been removed while they were away.
+            let room = match ticket {
+                Some(ticket) => {
+                    match Database::get_room_by_code(db.clone(), &ticket.room_code).await {
+                        Ok(Some(room)) if is_member(&room, &oid) => Some((room, ticket.last_seq)),
+                        _ => None,
+                    }
+                }
+                None => None,
+            };
+            let Some((room, ticket_seq)) = room else {
+                send_error(
+                    &ws_state,
+                    &socket_id,
+                    ErrorCode::ResumeFailed,
+                    "The session can no longer be resumed, join the room again.",
+                )
+                .await;
+                return;
+            };
+            connection.record("room", room.code.as_str());
+
+            let user: User = match Database::get_user_by_id(db.clone(), oid).await {
+                Ok(Some(user)) => user,
+                _ => return,
+            };
+
+            let session = Session {
+                user_id: oid,
+                room_code: room.code.clone(),
+                token_exp: claim.exp,
+                token_id: claim.jti.clone(),
+                mouse_events: 0,
+                key_events: 0,
+            };
+            start_session(&ws_state, &db, socket_id, session).await;
+
+            // Events recorded between registering the socket and
+            // taking the replay may arrive twice; clients drop
+            // frames whose `seq` they have already seen.
+            let after = data.last_seq.unwrap_or(ticket_seq);
+            let replay = ws_state
+                .broker
+                .replay_room_events(&room.code, oid, after)
+                .await
+                .unwrap_or_else(|err| {
+                    warn!(error = %err, "Failed to replay room events");
+                    Replay {
+                        events: vec![],
+                        complete: false,
+                    }
+                });
+            let resume_token = issue_resume_token(&ws_state, oid, &room.code).await;
+            let last_seq = ws_state.broker.last_seq().await.unwrap_or(after);
+
+            let response = SessionResumedResponse {
+                message_type: "session-resumed".to_string(),
+                user_id: oid,
+                username: user.username,
+                code: room.code.clone(),
+                host: room.host_id == oid,
+                ice_servers: ice_servers(&config, &claim.sub, claim.exp),
+                mode: room.mode,
+                resume_token,
+                last_seq,
+                missed: replay.events.len(),
+                replay_complete: replay.complete,
+            };
+            let response_text = serde_json::to_string(&response).unwrap();
+            send_to_socket(&ws_state, &socket_id, &response_text).await;
+
+            for text in replay.events {
+                send_to_socket(&ws_state, &socket_id, &text).await;
+            }
+        }
+        "request-accepted" => {
+            let data: RequestAcceptedData = match serde_json::from_value(json["data"].clone()) {
+                Ok(d) => d,
+                Err(_) => return,
+            };
+
+            // Admitting skips the passcode, lobby and capacity checks of
+            // `join-room`, so only the room's host may do it.
+            let sender = session_identity(&ws_state, &socket_id).await;
+            let room = match Database::get_room_by_code(db.clone(), &data.code).await {
+                Ok(Some(room)) if sender.is_some_and(|(user_id, _)| user_id == room.host_id) => {
+                    room
+                }
+                _ => {
+                    send_error(
+                        &ws_state,
+                        &socket_id,
+                        ErrorCode::NotHost,
+                        "Only the host can admit participants.",
+                    )
+                    .await;
+                    return;
+                }
+            };
+
+            admit_participant(&db, &ws_state, &config, &room, data).await;
+        }
+        "offer" | "answer" | "ice-candidate" => {
+            let data: RtcConnectionData = match serde_json::from_value(json["data"].clone()) {
+                Ok(d) => d,
+                Err(_) => {
+                    send_error(
+                        &ws_state,
+                        &socket_id,
+                        ErrorCode::InvalidSignal,
+                        "Signaling message is missing `item` or `to`.",
+                    )
+                    .await;
+                    return;
+                }
+            };
+
+            let item = match signaling::validate(message_type, data.item) {
+                Ok(item) => item,
+                Err(err) => {
+                    send_error(&ws_state, &socket_id, err.code(), err.message()).await;
+                    return;
+                }
+            };
+
+            let Some((from, room_code)) = session_identity(&ws_state, &socket_id).await else {
+                send_error(
+                    &ws_state,
+                    &socket_id,
+                    ErrorCode::NotInRoom,
+                    "Join a room before signaling.",
+                )
+                .await;
+                return;
+            };
+
+            let room = match Database::get_room_by_code(db.clone(), &room_code).await {
+                Ok(Some(room)) if is_member(&room, &from) => room,
+                _ => {
+                    send_error(
+                        &ws_state,
+                        &socket_id,
+                        ErrorCode::NotInRoom,
+                        "Signaling is only allowed between members of the same room.",
+                    )
+                    .await;
+                    return;
+                }
+            };
+
+            // SFU rooms negotiate with the server's peer instead of each other;
+            // mesh rooms only publish to it while they are being recorded.
+            let to_server = match data.to {
+                Some(to) => to == SFU_PEER_ID,
+                None => room.mode == RoomMode::Sfu,
+            };
+            if to_server {
+                if room.mode == RoomMode::Mesh
+                    && message_type == "offer"
+                    && sfu.recording(&room.code).is_none()
+                {
+                    send_error(
+                        &ws_state,
+                        &socket_id,
+                        ErrorCode::NotRecording,
+                        "The room is not being recorded.",
+                    )
+                    .await;
+                    return;
+                }
+
+                if let Err(err) = sfu
+                    .handle_signal(
+                        ws_state.clone(),
+                        &room.code,
+                        from,
+                        message_type,
+                        item,
+                        room.mode == RoomMode::Sfu,
+                    )
+                    .await
+                {
+                    warn!(user_id = %from, error = %err, "SFU signaling failed");
+                    send_error(
+                        &ws_state,
+                        &socket_id,
+                        ErrorCode::SfuError,
+                        "The media server rejected the signaling message.",
+                    )
+                    .await;
+                }
+                return;
+            }
+
+            let Some(to) = data.to.filter(|to| is_member(&room, to)) else {
+                send_error(
+                    &ws_state,
+                    &socket_id,
+                    ErrorCode::NotInRoom,
+                    "Signaling is only allowed between members of the same room.",
+                )
+                .await;
+                return;
+            };
+
+            let response = RtcConnectionResponse {
+                message_type: message_type.to_string(),
+                item,
+                from,
+                user_id: to,
+            };
+            send_frame_to_user(&ws_state, &to, &response).await;
+        }
+
+        "mouse-move" | "mouse-click" | "key-press" | "scroll" | "clipboard" => {
+            // Dropped moves are harmless since the next one carries the
+            // position; anything else is reported so the client can retry.
+            if !input_limit.try_take() {
+                if message_type != "mouse-move" {
+                    send_error(
+                        &ws_state,
+                        &socket_id,
+                        ErrorCode::RateLimited,
+                        "Too many input events; slow down.",
+                    )
+                    .await;
+                }
+                return;
+            }
+
+            let event = match input::parse(message_type, json["data"].clone()) {
+                Ok(event) => event,
+                Err(err) => {
+                    send_error(&ws_state, &socket_id, err.code(), err.message()).await;
+                    return;
+                }
+            };
+
+            let target = event.to();
+            let Some(controller) = authorize_control(&ws_state, &db, &socket_id, target).await
+            else {
+                return;
+            };
+
+            if config.audit_keystrokes
+                && let Some(mut audit_event) = keystroke_audit(&event, controller)
+            {
+                audit_event.target = Some(target);
+                audit_event.room_code = session_identity(&ws_state, &socket_id)
+                    .await
+                    .map(|(_, room_code)| room_code);
+                audit(&db, audit_event);
+            }
+
+            let response = InputResponse {
+                message_type: message_type.to_string(),
+                event: &event,
+            };
+            let coalesce = matches!(event, InputEvent::MouseMove(_));
+            if relay_input(&ws_state, controller, target, coalesce, &response).await {
+                count_relayed_input(&ws_state, &socket_id, event.is_key()).await;
+            } else {
+                send_error(
+                    &ws_state,
+                    &socket_id,
+                    ErrorCode::TargetOverloaded,
+                    TARGET_OVERLOADED,
+                )
+                .await;
+            }
+        }
+
+        "message" => {
+            let data: MessageData = match serde_json::from_value(json["data"].clone()) {
+                Ok(d) => d,
+                Err(_) => {
+                    debug!("Ignoring malformed payload");
+                    return;
+                }
+            };
+
+            let room: Room = match Database::get_room_by_code(db.clone(), &data.code).await {
+                Ok(Some(room)) => room,
+                _ => return,
+            };
+
+            let response: MessageResponse = MessageResponse {
+                message_type: "message".to_string(),
+                message: data.message,
+                username: data.username,
+                id: data.id,
+            };
+
+            broadcast(&ws_state, &room, &response).await;
+        }
+        "screen-sharing-started" | "screen-sharing-stopped" => {
+            let data: VideoData = match serde_json::from_value(json["data"].clone()) {
+                Ok(d) => d,
+                Err(_) => {
+                    debug!("Ignoring malformed payload");
+                    return;
+                }
+            };
+
+            let room: Room = match Database::get_room_by_code(db.clone(), &data.code).await {
+                Ok(Some(room)) => room,
+                _ => return,
+            };
+            let response: VideoResponse = VideoResponse {
+                message_type: message_type.to_string(),
+                user_id: data.user_id,
+                host: data.host,
+            };
+
+            broadcast(&ws_state, &room, &response).await;
+        }
+
+        "video-started" | "video-stopped" => {
+            let data: VideoData = match serde_json::from_value(json["data"].clone()) {
+                Ok(d) => d,
+                Err(_) => {
+                    debug!("Ignoring malformed payload");
+                    return;
+                }
+            };
+
+            let room: Room = match Database::get_room_by_code(db.clone(), &data.code).await {
+                Ok(Some(room)) => room,
+                _ => return,
+            };
+            let response: VideoResponse = VideoResponse {
+                message_type: message_type.to_string(),
+                user_id: data.user_id,
+                host: data.host,
+            };
+
+            broadcast(&ws_state, &room, &response).await;
+        }
+
+        "start-recording" | "stop-recording" => {
+            let Some((user_id, room_code)) = session_identity(&ws_state, &socket_id).await else {
+                send_error(
+                    &ws_state,
+                    &socket_id,
+                    ErrorCode::NotInRoom,
+                    "Join a room before recording.",
+                )
+                .await;
+                return;
+            };
+
+            let room = match Database::get_room_by_code(db.clone(), &room_code).await {
+                Ok(Some(room)) if room.host_id == user_id => room,
+                _ => {
+                    send_error(
+                        &ws_state,
+                        &socket_id,
+                        ErrorCode::NotHost,
+                        "Only the host can record the session.",
+                    )
+                    .await;
+                    return;
+                }
+            };
+
+            if message_type == "stop-recording" {
+                if !stop_recording(&ws_state, &sfu, &room).await {
+                    send_error(
+                        &ws_state,
+                        &socket_id,
+                        ErrorCode::NotRecording,
+                        "The room is not being recorded.",
+                    )
+                    .await;
+                }
+                return;
+            }
+
+            let recording_id = ObjectId::new();
+            let active = match ActiveRecording::create(&config.recordings_dir, recording_id).await {
+                Ok(active) => Arc::new(active),
+                Err(err) => {
+                    error!(error = %err, "Failed to create recording directory");
+                    send_error(
+                        &ws_state,
+                        &socket_id,
+                        ErrorCode::RecordingFailed,
+                        "The recording could not be started.",
+                    )
+                    .await;
+                    return;
+                }
+            };
+
+            if !sfu.start_recording(&room.code, active) {
+                send_error(
+                    &ws_state,
+                    &socket_id,
+                    ErrorCode::AlreadyRecording,
+                    "The room is already being recorded.",
+                )
+                .await;
+                return;
+            }
+
+            let recording = Recording {
+                _id: Some(recording_id),
+                room_code: room.code.clone(),
+                host_id: user_id,
+                started_at: bson::DateTime::now(),
+                ended_at: None,
+                tracks: vec![],
+            };
+            if Database::create_recording(db.clone(), recording)
+                .await
+                .is_err()
+            {
+                sfu.stop_recording(&room.code);
+                send_error(
+                    &ws_state,
+                    &socket_id,
+                    ErrorCode::RecordingFailed,
+                    "The recording could not be started.",
+                )
+                .await;
+                return;
+            }
+
+            let response = RecordingResponse {
+                message_type: "recording-started".to_string(),
+                recording_id,
+            };
+            broadcast(&ws_state, &room, &response).await;
+        }
+
+        "leave-room" => {
+            let data: LeaveRoomData = match serde_json::from_value(json["data"].clone()) {
+                Ok(d) => d,
+                Err(_) => {
+                    debug!("Ignoring malformed payload");
+                    return;
+                }
+            };
+
+            // Who is leaving comes from the socket's session, never from the
+            // payload.
+            let session = {
+                let mut sessions = ws_state.sessions.lock().await;
+                match sessions.get(&socket_id) {
+                    Some(session) if session.room_code == data.code => sessions.remove(&socket_id),
+                    _ => None,
+                }
+            };
+            let Some(session) = session else {
+                return;
+            };
+            let user_id = session.user_id;
+            release_access(&ws_state, &db, user_id).await;
+            audit_session_end(&db, session);
+
+            let room: Room = match Database::get_room_by_code(db.clone(), &data.code).await {
+                Ok(Some(room)) => room,
+                _ => return,
+            };
+
+            sfu.remove_peer(&ws_state, &room.code, user_id).await;
+
+            if let Err(err) = ws_state
+                .broker
+                .revoke_resume_token(user_id, &data.code)
+                .await
+            {
+                warn!(%user_id, error = %err, "Failed to revoke the resume token");
+            }
+
+            if user_id == room.host_id {
+                if room.participants_id.is_empty() {
+                    stop_recording(&ws_state, &sfu, &room).await;
+                    match Database::delete_room(db.clone(), &data.code).await {
+                        Ok(_) => info!(room = %data.code, "Room deleted"),
+                        Err(_) => return,
+                    };
+                    forget_room(&ws_state, &data.code).await;
+                    if Database::finish_participants(db.clone(), &data.code)
+                        .await
+                        .is_err()
+                    {
+                        return;
+                    }
+                    return;
+                }
+                let new_host = room.participants_id[0];
+                match Database::remove_participant_from_room(db.clone(), &data.code, new_host).await
+                {
+                    Ok(_) => info!(room = %data.code, "Participant removed"),
+                    Err(_) => return,
+                };
+                match Database::update_host_id(db.clone(), &data.code, new_host).await {
+                    Ok(_) => info!(room = %data.code, host = %new_host, "Host changed"),
+                    Err(_) => return,
+                };
+                let user: User = match Database::get_user_by_id(db.clone(), new_host).await {
+                    Ok(Some(user)) => user,
+                    _ => return,
+                };
+                let response = HostLeftresponse {
+                    message_type: "host-left".to_string(),
+                    host: new_host,
+                    username: user.username,
+                };
+
+                broadcast(&ws_state, &room, &response).await;
+            } else {
+                match Database::remove_participant_from_room(db.clone(), &data.code, user_id).await
+                {
+                    Ok(_) => info!(room = %data.code, "Participant removed"),
+                    Err(_) => return,
+                };
+
+                let response = ParticipantLeft {
+                    message_type: "participant-left".to_string(),
+                    user: user_id,
+                };
+
+                broadcast(&ws_state, &room, &response).await;
+            }
+        }
+        "request-access" => {
+            let data: RequestAccessData = match serde_json::from_value(json["data"].clone()) {
+                Ok(d) => d,
+                Err(_) => {
+                    debug!("Ignoring malformed payload");
+                    return;
+                }
+            };
+
+            let Some((controller, room_code)) = session_identity(&ws_state, &socket_id).await
+            else {
+                send_error(
+                    &ws_state,
+                    &socket_id,
+                    ErrorCode::NotInRoom,
+                    "Join a room before requesting access.",
+                )
+                .await;
+                return;
+            };
+
+            let room = match Database::get_room_by_code(db.clone(), &room_code).await {
+                Ok(Some(room))
+                    if controller != data.to
+                        && is_member(&room, &controller)
+                        && is_member(&room, &data.to) =>
+                {
+                    room
+                }
+                _ => {
+                    send_error(
+                        &ws_state,
+                        &socket_id,
+                        ErrorCode::NotInRoom,
+                        "Remote control is only possible within the same room.",
+                    )
+                    .await;
+                    return;
+                }
+            };
+
+            let key = (controller, data.to);
+            let grant = Grant {
+                state: AccessState::Requested,
+                room_code: room.code,
+                username: data.username,
+                expires_at: None,
+            };
+            let result = ws_state.broker.request_access(key, &grant).await;
+            match result {
+                Ok(()) => {
+                    send_access_event(
+                        &ws_state,
+                        &db,
+                        AccessState::Requested,
+                        controller,
+                        key,
+                        &grant,
+                    )
+                    .await;
+                }
+                Err(err) => {
+                    send_access_error(&ws_state, &socket_id, err).await;
+                }
+            }
+        }
+
+        "allowed-access" | "rejected-access" => {
+            let data: AccessData = match serde_json::from_value(json["data"].clone()) {
+                Ok(d) => d,
+                Err(_) => {
+                    debug!("Ignoring malformed payload");
+                    return;
+                }
+            };
+
+            let Some((target, _)) = session_identity(&ws_state, &socket_id).await else {
+                return;
+            };
+
+            let key = (data.user_id, target);
+            let result = if message_type == "allowed-access" {
+                let duration = data
+                    .duration_secs
+                    .map(|secs| Duration::from_secs(secs.clamp(1, MAX_GRANT_SECS)));
+                ws_state
+                    .broker
+                    .grant_access(key, duration)
+                    .await
+                    .map(|grant| (AccessState::Granted, grant))
+            } else {
+                ws_state
+                    .broker
+                    .deny_access(key)
+                    .await
+                    .map(|grant| (AccessState::Denied, grant))
+            };
+
+            match result {
+                Ok((state, grant)) => {
+                    send_access_event(&ws_state, &db, state, target, key, &grant).await;
+                    if let Some(expires_at) = grant.expires_at {
+                        schedule_expiry(
+                            ws_state.clone(),
+                            db.clone(),
+                            data.user_id,
+                            target,
+                            expires_at,
+                        );
+                    }
+                }
+                Err(err) => {
+                    send_access_error(&ws_state, &socket_id, err).await;
+                }
+            }
+        }
+
+        "revoke-access" => {
+            let data: RevokeAccessData = match serde_json::from_value(json["data"].clone()) {
+                Ok(d) => d,
+                Err(_) => {
+                    debug!("Ignoring malformed payload");
+                    return;
+                }
+            };
+
+            let Some((user_id, _)) = session_identity(&ws_state, &socket_id).await else {
+                return;
+            };
+
+            let result = ws_state.broker.revoke_access(user_id, data.user_id).await;
+            match result {
+                Ok((key, grant)) => {
+                    send_access_event(&ws_state, &db, AccessState::Revoked, user_id, key, &grant)
+                        .await;
+                }
+                Err(err) => {
+                    send_access_error(&ws_state, &socket_id, err).await;
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tokio::{sync::mpsc, time::timeout};
+
+    use super::*;
+    use crate::broker::MemoryBroker;
+
+    // Two instances sharing one in-process broker, each delivering what is
+    // published to it.
+    async fn instances() -> (Arc<AppState>, Arc<AppState>) {
+        let broker = MemoryBroker::new(Duration::from_secs(60), 16);
+        let instance = || {
+            let passcode_attempts =
+                AttemptLimiter::new(5, Duration::from_secs(60), Duration::from_secs(60));
+            Arc::new(AppState::new(
+                Arc::new(broker.clone()),
+                passcode_attempts,
+                Arc::new(Metrics::new()),
+            ))
+        };
+        let (a, b) = (instance(), instance());
+
+        tokio::spawn(run_deliveries(a.clone()));
+        tokio::spawn(run_deliveries(b.clone()));
+        // Lets both subscribe before anything is published.
+        task::yield_now().await;
+        (a, b)
+    }
+
+    // Registers a socket for the user and returns what gets sent to it.
+    async fn connect(ws_state: &AppState, user_id: ObjectId) -> mpsc::UnboundedReceiver<Message> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let sink = futures_util::sink::unfold(sender, |sender, message: Message| async move {
+            sender.send(message).map_err(axum::Error::new)?;
+            Ok::<_, axum::Error>(sender)
+        });
+
+        let socket_id = Uuid::new_v4();
+        ws_state
+            .sockets
+            .lock()
+            .await
+            .insert(socket_id, Arc::new(Mutex::new(Box::pin(sink))));
+        ws_state
+            .user_sockets
+            .lock()
+            .await
+            .insert(user_id, socket_id);
+        receiver
+    }
+
+    async fn next_text(socket: &mut mpsc::UnboundedReceiver<Message>) -> String {
+        let message = timeout(Duration::from_secs(1), socket.recv())
+            .await
+            .expect("nothing delivered")
+            .expect("socket closed");
+        match message {
+            Message::Text(text) => text.to_string(),
+            other => panic!("unexpected frame: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_reach_users_on_another_instance() {
+        let (a, b) = instances().await;
+        let alice = ObjectId::new();
+        let mut socket = connect(&b, alice).await;
+
+        send_to_user(&a, &alice, r#"{"message_type":"message"}"#).await;
+
+        assert_eq!(
+            next_text(&mut socket).await,
+            r#"{"message_type":"message"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn room_broadcasts_reach_every_instance() {
+        let (a, b) = instances().await;
+        let host = ObjectId::new();
+        let guest = ObjectId::new();
+        let mut host_socket = connect(&a, host).await;
+        let mut guest_socket = connect(&b, guest).await;
+        let room = Room {
+            _id: None,
+            host_id: host,
+            code: "123456".to_string(),
+            participants_id: vec![guest],
+            series_id: None,
+            occurrence_start: None,
+            passcode: None,
+            mode: RoomMode::default(),
+        };
+
+        broadcast(&a, &room, &json!({ "message_type": "message" })).await;
+
+        let expected = r#"{"seq":1,"message_type":"message"}"#;
+        assert_eq!(next_text(&mut host_socket).await, expected);
+        assert_eq!(next_text(&mut guest_socket).await, expected);
+    }
+
+    #[tokio::test]
+    async fn input_reaches_targets_on_another_instance() {
+        let (a, b) = instances().await;
+        let controller = ObjectId::new();
+        let target = ObjectId::new();
+        let mut socket = connect(&b, target).await;
+
+        let frame = json!({ "message_type": "mouse-move", "x": 1.0, "y": 2.0 });
+        assert!(relay_input(&a, controller, target, true, &frame).await);
+
+        let text = next_text(&mut socket).await;
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&text).unwrap(),
+            frame
+        );
+    }
+
+    #[tokio::test]
+    async fn grants_are_shared_between_instances() {
+        let (a, b) = instances().await;
+        let key = (ObjectId::new(), ObjectId::new());
+        let grant = Grant {
+            state: AccessState::Requested,
+            room_code: "123456".to_string(),
+            username: "alice".to_string(),
+            expires_at: None,
+        };
+
+        // The controller asks on A, the target answers on B.
+        assert!(a.broker.request_access(key, &grant).await.is_ok());
+        assert!(b.broker.grant_access(key, None).await.is_ok());
+        assert!(a.broker.check_access(key).await.is_ok());
+    }
 }
@@ -0,0 +1,81 @@
+use axum::extract::ws::Message;
+use serde::Serialize;
+use serde_json::Value;
+
+// Clients that offer this subprotocol may send MessagePack frames and get
+// input and signaling relays back as MessagePack. Frames have the same
+// `{type, data}` shape as JSON; everything else stays JSON text.
+pub const MSGPACK_PROTOCOL: &str = "telesync.msgpack";
+
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MessagePack,
+}
+
+impl Encoding {
+    pub fn from_protocol(protocol: Option<&str>) -> Self {
+        match protocol {
+            Some(MSGPACK_PROTOCOL) => Encoding::MessagePack,
+            _ => Encoding::Json,
+        }
+    }
+
+    pub fn encode<T: Serialize>(&self, value: &T) -> Message {
+        match self {
+            Encoding::Json => Message::Text(serde_json::to_string(value).unwrap().into()),
+            Encoding::MessagePack => {
+                Message::Binary(rmp_serde::to_vec_named(value).unwrap().into())
+            }
+        }
+    }
+}
+
+pub fn decode(bytes: &[u8]) -> Option<Value> {
+    rmp_serde::from_slice(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn frame() -> Value {
+        json!({
+            "type": "mouse-move",
+            "data": {"to": "65a1b2c3d4e5f60718293a4b", "x": 12.5, "y": -3.0, "modifiers": {"shift": true}},
+        })
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let Message::Text(text) = Encoding::Json.encode(&frame()) else {
+            panic!("JSON frames are sent as text");
+        };
+
+        assert_eq!(serde_json::from_str::<Value>(&text).unwrap(), frame());
+    }
+
+    #[test]
+    fn msgpack_round_trip() {
+        let Message::Binary(bytes) = Encoding::MessagePack.encode(&frame()) else {
+            panic!("MessagePack frames are sent as binary");
+        };
+
+        assert_eq!(decode(&bytes), Some(frame()));
+    }
+
+    #[test]
+    fn decode_rejects_garbage() {
+        assert_eq!(decode(&[0xc1]), None);
+    }
+
+    #[test]
+    fn protocol_selects_encoding() {
+        assert!(Encoding::from_protocol(Some(MSGPACK_PROTOCOL)) == Encoding::MessagePack);
+        assert!(Encoding::from_protocol(Some("other")) == Encoding::Json);
+        assert!(Encoding::from_protocol(None) == Encoding::Json);
+    }
+}
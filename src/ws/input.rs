@@ -0,0 +1,306 @@
+use mongodb::bson::oid::ObjectId;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+use crate::error::ErrorCode;
+
+pub const MAX_KEY_LEN: usize = 32;
+pub const MAX_CLIPBOARD_BYTES: usize = 64 * 1024;
+pub const MAX_SCROLL_DELTA: f64 = 10_000.0;
+pub const MAX_COORDINATE: f64 = 100_000.0;
+
+pub enum InputError {
+    TooLarge,
+    Malformed,
+}
+
+impl InputError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            InputError::TooLarge => ErrorCode::PayloadTooLarge,
+            InputError::Malformed => ErrorCode::InvalidInput,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            InputError::TooLarge => "Input event exceeds the size limit.",
+            InputError::Malformed => "Input event is missing fields or has invalid values.",
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MouseButton {
+    #[default]
+    Left,
+    Right,
+    Middle,
+    Back,
+    Forward,
+}
+
+// `press` and `release` bracket a drag; `click` and `double-click` are
+// complete gestures.
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ButtonAction {
+    #[default]
+    Click,
+    DoubleClick,
+    Press,
+    Release,
+}
+
+// `press` is a full down/up stroke, kept as the default for older clients.
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyAction {
+    #[default]
+    Press,
+    Down,
+    Up,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default)]
+pub struct Modifiers {
+    #[serde(default)]
+    pub shift: bool,
+    #[serde(default)]
+    pub ctrl: bool,
+    #[serde(default)]
+    pub alt: bool,
+    #[serde(default)]
+    pub meta: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct MouseMove {
+    #[serde(skip_serializing)]
+    pub to: ObjectId,
+    pub x: f64,
+    pub y: f64,
+    #[serde(default)]
+    pub modifiers: Modifiers,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct MouseClick {
+    #[serde(skip_serializing)]
+    pub to: ObjectId,
+    #[serde(default)]
+    pub button: MouseButton,
+    #[serde(default)]
+    pub action: ButtonAction,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<f64>,
+    #[serde(default)]
+    pub modifiers: Modifiers,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct KeyPress {
+    #[serde(skip_serializing)]
+    pub to: ObjectId,
+    pub key: String,
+    #[serde(default)]
+    pub action: KeyAction,
+    #[serde(default)]
+    pub modifiers: Modifiers,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Scroll {
+    #[serde(skip_serializing)]
+    pub to: ObjectId,
+    #[serde(default)]
+    pub delta_x: f64,
+    #[serde(default)]
+    pub delta_y: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<f64>,
+    #[serde(default)]
+    pub modifiers: Modifiers,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct Clipboard {
+    #[serde(skip_serializing)]
+    pub to: ObjectId,
+    pub text: String,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum InputEvent {
+    MouseMove(MouseMove),
+    MouseClick(MouseClick),
+    KeyPress(KeyPress),
+    Scroll(Scroll),
+    Clipboard(Clipboard),
+}
+
+impl InputEvent {
+    pub fn to(&self) -> ObjectId {
+        match self {
+            InputEvent::MouseMove(event) => event.to,
+            InputEvent::MouseClick(event) => event.to,
+            InputEvent::KeyPress(event) => event.to,
+            InputEvent::Scroll(event) => event.to,
+            InputEvent::Clipboard(event) => event.to,
+        }
+    }
+
+    // Keyboard and clipboard events count as key events in the audit log.
+    pub fn is_key(&self) -> bool {
+        matches!(self, InputEvent::KeyPress(_) | InputEvent::Clipboard(_))
+    }
+}
+
+pub fn parse(message_type: &str, data: Value) -> Result<InputEvent, InputError> {
+    let event = match message_type {
+        "mouse-move" => InputEvent::MouseMove(from_value(data)?),
+        "mouse-click" => InputEvent::MouseClick(from_value(data)?),
+        "key-press" => InputEvent::KeyPress(from_value(data)?),
+        "scroll" => InputEvent::Scroll(from_value(data)?),
+        "clipboard" => InputEvent::Clipboard(from_value(data)?),
+        _ => return Err(InputError::Malformed),
+    };
+
+    validate(&event)?;
+    Ok(event)
+}
+
+fn from_value<T: DeserializeOwned>(data: Value) -> Result<T, InputError> {
+    serde_json::from_value(data).map_err(|_| InputError::Malformed)
+}
+
+fn validate(event: &InputEvent) -> Result<(), InputError> {
+    let valid = match event {
+        InputEvent::MouseMove(event) => is_coordinate(event.x) && is_coordinate(event.y),
+        InputEvent::MouseClick(event) => {
+            event.x.is_none_or(is_coordinate) && event.y.is_none_or(is_coordinate)
+        }
+        InputEvent::KeyPress(event) => {
+            !event.key.is_empty()
+                && event.key.len() <= MAX_KEY_LEN
+                && !event.key.chars().any(char::is_control)
+        }
+        InputEvent::Scroll(event) => {
+            is_delta(event.delta_x)
+                && is_delta(event.delta_y)
+                && event.x.is_none_or(is_coordinate)
+                && event.y.is_none_or(is_coordinate)
+        }
+        InputEvent::Clipboard(event) => {
+            if event.text.len() > MAX_CLIPBOARD_BYTES {
+                return Err(InputError::TooLarge);
+            }
+            true
+        }
+    };
+
+    if valid {
+        Ok(())
+    } else {
+        Err(InputError::Malformed)
+    }
+}
+
+fn is_coordinate(value: f64) -> bool {
+    value.is_finite() && value.abs() <= MAX_COORDINATE
+}
+
+fn is_delta(value: f64) -> bool {
+    value.is_finite() && value.abs() <= MAX_SCROLL_DELTA
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const TARGET: &str = "65a1b2c3d4e5f60718293a4b";
+
+    #[test]
+    fn parses_each_event_type() {
+        let cases = [
+            ("mouse-move", json!({"to": TARGET, "x": 10.0, "y": 20.5})),
+            (
+                "mouse-click",
+                json!({"to": TARGET, "button": "right", "action": "double-click"}),
+            ),
+            (
+                "key-press",
+                json!({"to": TARGET, "key": "Enter", "action": "down"}),
+            ),
+            ("scroll", json!({"to": TARGET, "delta_y": -120.0})),
+            ("clipboard", json!({"to": TARGET, "text": "hello"})),
+        ];
+
+        for (message_type, data) in cases {
+            let event = parse(message_type, data)
+                .unwrap_or_else(|err| panic!("{message_type}: {}", err.message()));
+            assert_eq!(event.to().to_hex(), TARGET);
+        }
+    }
+
+    #[test]
+    fn target_is_not_relayed() {
+        let event = parse("key-press", json!({"to": TARGET, "key": "a"}))
+            .ok()
+            .unwrap();
+        let value = serde_json::to_value(&event).unwrap();
+
+        assert!(value.get("to").is_none());
+        assert_eq!(value["action"], "press");
+        assert!(event.is_key());
+    }
+
+    #[test]
+    fn rejects_malformed_payloads() {
+        let cases = [
+            ("mouse-wheel", json!({"to": TARGET})),
+            ("mouse-move", json!({"to": TARGET, "x": 10.0})),
+            ("mouse-move", json!({"to": "not-an-id", "x": 1.0, "y": 1.0})),
+            ("mouse-move", json!({"to": TARGET, "x": 1e9, "y": 0.0})),
+            ("mouse-click", json!({"to": TARGET, "button": "thumb"})),
+            ("key-press", json!({"to": TARGET, "key": ""})),
+            (
+                "key-press",
+                json!({"to": TARGET, "key": "a".repeat(MAX_KEY_LEN + 1)}),
+            ),
+            ("key-press", json!({"to": TARGET, "key": "\u{7}"})),
+            (
+                "scroll",
+                json!({"to": TARGET, "delta_x": MAX_SCROLL_DELTA * 2.0}),
+            ),
+        ];
+
+        for (message_type, data) in cases {
+            assert!(
+                matches!(
+                    parse(message_type, data.clone()),
+                    Err(InputError::Malformed)
+                ),
+                "{message_type} accepted {data}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_clipboard() {
+        let text = "x".repeat(MAX_CLIPBOARD_BYTES + 1);
+
+        assert!(matches!(
+            parse("clipboard", json!({"to": TARGET, "text": text})),
+            Err(InputError::TooLarge)
+        ));
+    }
+}
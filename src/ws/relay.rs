@@ -0,0 +1,174 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use axum::extract::ws::Message;
+use futures_util::SinkExt;
+use mongodb::bson::oid::ObjectId;
+use tokio::sync::Notify;
+use tracing::debug;
+
+use super::{SocketSender, codec::Encoding};
+
+// Bounds what a stalled target can hold; coalesced moves never count twice.
+const MAX_QUEUED_INPUT: usize = 512;
+
+struct QueuedInput {
+    sender: ObjectId,
+    coalesce: bool,
+    message: Message,
+}
+
+// Outbound input events for one target socket, written by a dedicated task so
+// senders never wait on a slow target. Consecutive coalescable events from the
+// same sender collapse into the latest one; everything else stays in order.
+pub struct InputQueue {
+    pub encoding: Encoding,
+    events: Mutex<VecDeque<QueuedInput>>,
+    notify: Notify,
+    closed: AtomicBool,
+}
+
+impl InputQueue {
+    pub fn spawn(sink: SocketSender, encoding: Encoding) -> Arc<Self> {
+        let queue = Arc::new(InputQueue {
+            encoding,
+            events: Mutex::new(VecDeque::new()),
+            notify: Notify::new(),
+            closed: AtomicBool::new(false),
+        });
+        tokio::spawn(drain(queue.clone(), sink));
+        queue
+    }
+
+    // Returns false if the target has fallen too far behind.
+    pub fn push(&self, sender: ObjectId, coalesce: bool, message: Message) -> bool {
+        {
+            let mut events = self.events.lock().unwrap();
+            let len = events.len();
+            match events.back_mut() {
+                Some(last) if coalesce && last.coalesce && last.sender == sender => {
+                    last.message = message;
+                }
+                _ if len >= MAX_QUEUED_INPUT => return false,
+                _ => events.push_back(QueuedInput {
+                    sender,
+                    coalesce,
+                    message,
+                }),
+            }
+        }
+
+        self.notify.notify_one();
+        true
+    }
+
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.notify.notify_one();
+    }
+}
+
+async fn drain(queue: Arc<InputQueue>, sink: SocketSender) {
+    loop {
+        let next = queue.events.lock().unwrap().pop_front();
+        match next {
+            Some(input) => {
+                let mut sink = sink.lock().await;
+                if let Err(err) = sink.send(input.message).await {
+                    debug!(error = %err, "Failed to relay input event");
+                    break;
+                }
+            }
+            None if queue.closed.load(Ordering::SeqCst) => break,
+            None => queue.notify.notified().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Built without a drain task so the queued events can be inspected.
+    fn queue() -> InputQueue {
+        InputQueue {
+            encoding: Encoding::Json,
+            events: Mutex::new(VecDeque::new()),
+            notify: Notify::new(),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    fn text(body: &str) -> Message {
+        Message::Text(body.into())
+    }
+
+    fn queued(queue: &InputQueue) -> Vec<String> {
+        queue
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|input| input.message.to_text().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn consecutive_moves_collapse_into_the_latest() {
+        let queue = queue();
+        let sender = ObjectId::new();
+
+        assert!(queue.push(sender, true, text("move-1")));
+        assert!(queue.push(sender, true, text("move-2")));
+        assert!(queue.push(sender, true, text("move-3")));
+
+        assert_eq!(queued(&queue), ["move-3"]);
+    }
+
+    #[test]
+    fn other_events_keep_their_order() {
+        let queue = queue();
+        let sender = ObjectId::new();
+
+        queue.push(sender, true, text("move-1"));
+        queue.push(sender, true, text("move-2"));
+        queue.push(sender, false, text("click"));
+        queue.push(sender, true, text("move-3"));
+        queue.push(sender, false, text("key"));
+        queue.push(sender, false, text("key"));
+
+        assert_eq!(queued(&queue), ["move-2", "click", "move-3", "key", "key"]);
+    }
+
+    #[test]
+    fn moves_from_different_senders_are_kept() {
+        let queue = queue();
+
+        queue.push(ObjectId::new(), true, text("move-a"));
+        queue.push(ObjectId::new(), true, text("move-b"));
+
+        assert_eq!(queued(&queue), ["move-a", "move-b"]);
+    }
+
+    #[test]
+    fn full_queue_rejects_new_events_but_still_coalesces() {
+        let queue = queue();
+        let sender = ObjectId::new();
+
+        for _ in 0..MAX_QUEUED_INPUT - 1 {
+            assert!(queue.push(sender, false, text("key")));
+        }
+        assert!(queue.push(sender, true, text("move-1")));
+        assert!(!queue.push(sender, false, text("key")));
+        assert!(queue.push(sender, true, text("move-2")));
+
+        let events = queued(&queue);
+        assert_eq!(events.len(), MAX_QUEUED_INPUT);
+        assert_eq!(events.last().unwrap(), "move-2");
+    }
+}
@@ -0,0 +1,102 @@
+use serde_json::{Value, json};
+
+use crate::error::ErrorCode;
+
+pub const MAX_SDP_BYTES: usize = 64 * 1024;
+pub const MAX_CANDIDATE_BYTES: usize = 2 * 1024;
+
+pub enum SignalError {
+    TooLarge,
+    Malformed,
+}
+
+impl SignalError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            SignalError::TooLarge => ErrorCode::PayloadTooLarge,
+            SignalError::Malformed => ErrorCode::InvalidSignal,
+        }
+    }
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            SignalError::TooLarge => "Signaling payload exceeds the size limit.",
+            SignalError::Malformed => "Signaling payload is not a valid SDP or ICE candidate.",
+        }
+    }
+}
+
+// Structural checks only: enough to reject garbage before it reaches another
+// peer, without trying to parse SDP fully. Returns the payload to forward.
+pub fn validate(message_type: &str, item: Value) -> Result<Value, SignalError> {
+    match message_type {
+        "offer" | "answer" => validate_description(message_type, &item),
+        "ice-candidate" => validate_candidate(&item).map(|()| item),
+        _ => Err(SignalError::Malformed),
+    }
+}
+
+// Only `type` and `sdp` are forwarded, so nothing else in the payload
+// escapes the size limit.
+fn validate_description(message_type: &str, item: &Value) -> Result<Value, SignalError> {
+    let sdp = item["sdp"].as_str().ok_or(SignalError::Malformed)?;
+    if sdp.len() > MAX_SDP_BYTES {
+        return Err(SignalError::TooLarge);
+    }
+
+    if item["type"].as_str() != Some(message_type) {
+        return Err(SignalError::Malformed);
+    }
+
+    let mut lines = sdp.lines().map(|line| line.trim_end_matches('\r'));
+    if lines.next() != Some("v=0") {
+        return Err(SignalError::Malformed);
+    }
+
+    let mut has_origin = false;
+    let mut has_media = false;
+    for line in lines.filter(|line| !line.is_empty()) {
+        let bytes = line.as_bytes();
+        if bytes.len() < 2 || !bytes[0].is_ascii_lowercase() || bytes[1] != b'=' {
+            return Err(SignalError::Malformed);
+        }
+        has_origin |= bytes[0] == b'o';
+        has_media |= bytes[0] == b'm';
+    }
+
+    if !has_origin || !has_media {
+        return Err(SignalError::Malformed);
+    }
+
+    Ok(json!({ "type": message_type, "sdp": sdp }))
+}
+
+fn validate_candidate(item: &Value) -> Result<(), SignalError> {
+    if item.to_string().len() > MAX_CANDIDATE_BYTES {
+        return Err(SignalError::TooLarge);
+    }
+
+    let candidate = item["candidate"].as_str().ok_or(SignalError::Malformed)?;
+
+    // An empty candidate marks the end of gathering.
+    if !candidate.is_empty() {
+        let candidate = candidate.strip_prefix("a=").unwrap_or(candidate);
+        let fields: Vec<&str> = candidate.split_whitespace().collect();
+        if fields.len() < 8
+            || !fields[0].starts_with("candidate:")
+            || fields[3].parse::<u32>().is_err()
+            || fields[5].parse::<u16>().is_err()
+            || fields[6] != "typ"
+        {
+            return Err(SignalError::Malformed);
+        }
+    }
+
+    let mid_ok = item["sdpMid"].is_null() || item["sdpMid"].is_string();
+    let index_ok = item["sdpMLineIndex"].is_null() || item["sdpMLineIndex"].is_u64();
+    if !mid_ok || !index_ok {
+        return Err(SignalError::Malformed);
+    }
+
+    Ok(())
+}